@@ -5,3 +5,24 @@
 pub fn example_function() -> u64 {
     42 // Example function for benchmarking
 }
+
+/// Deterministic stream of `(is_bid, price_ticks, size)` book updates drifting around `mid`.
+/// Roughly one in four updates removes a level (size 0).
+pub fn quote_stream(count: usize, mid: i64) -> Vec<(bool, i64, u64)> {
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut mid = mid;
+    let mut out = Vec::with_capacity(count);
+    for _ in 0..count {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        mid += (state % 3) as i64 - 1;
+        let is_bid = state & (1 << 40) != 0;
+        let distance = ((state >> 8) % 50) as i64 + 1;
+        let price = if is_bid { mid - distance } else { mid + distance };
+        let size = (state >> 20) % 4 * 100;
+        out.push((is_bid, price, size));
+    }
+    out
+}
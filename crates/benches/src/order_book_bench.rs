@@ -0,0 +1,43 @@
+//! order_book_bench.rs
+//! Compares the tree (`TreeLevels`) and flat ladder (`PriceLadder`) order book backends.
+
+use benches::quote_stream;
+use common::price::Price;
+use core_pipeline::order_book::{BookMode, OrderBook, Side};
+use core_pipeline::price_ladder::PriceLadder;
+use core_pipeline::price_levels::{PriceLevels, TreeLevels};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const UPDATES: usize = 10_000;
+
+fn replay<L: PriceLevels>(book: &mut OrderBook<L>, stream: &[(bool, i64, u64)]) {
+    for &(is_bid, ticks, size) in stream {
+        let side = if is_bid { Side::Bid } else { Side::Ask };
+        book.apply_quote("ES", side, Price::from_ticks(ticks), size)
+            .unwrap();
+        black_box(book.best_bid_ask("ES"));
+    }
+}
+
+/// Applies a stream of quotes and reads top of book after each one.
+pub fn benchmark_apply_quote(c: &mut Criterion) {
+    let stream = quote_stream(UPDATES, 400_000);
+    let mut group = c.benchmark_group("order_book_apply_quote");
+
+    group.bench_function("tree", |b| {
+        b.iter(|| {
+            let mut book = OrderBook::<TreeLevels>::with_mode(BookMode::MarketByPrice);
+            replay(&mut book, &stream);
+        });
+    });
+    group.bench_function("ladder", |b| {
+        b.iter(|| {
+            let mut book = OrderBook::<PriceLadder>::with_mode(BookMode::MarketByPrice);
+            replay(&mut book, &stream);
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_apply_quote);
+criterion_main!(benches);
@@ -0,0 +1,50 @@
+//! risk_check_bench.rs
+//! Latency of the pre-trade risk checks against a live book, which sit on the order path.
+
+use common::price::Price;
+use core_pipeline::book_builder::BookState;
+use core_pipeline::order_book::{OrderBook, Side};
+use core_pipeline::order_types::Order;
+use core_pipeline::risk_checks::{RiskChecker, RiskLimits};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+/// Runs every limit and book check on an order that passes them all.
+pub fn benchmark_check_with_book(c: &mut Criterion) {
+    let limits = RiskLimits {
+        max_order_quantity: Some(1_000),
+        max_notional: Some(10_000_000),
+        price_collar_ticks: Some(50),
+        max_open_orders: Some(100),
+        max_position: Some(10_000),
+        max_daily_loss: Some(1_000_000),
+        max_ticks_through_touch: Some(5),
+        max_bps_through_touch: Some(50),
+        trade_band_ticks: Some(20),
+        max_book_age_ns: Some(1_000_000),
+        reject_crossed_book: true,
+    };
+    let mut risk = RiskChecker::new();
+    risk.set_instrument_limits("ES", limits);
+    risk.set_account_limits(1, limits);
+    risk.set_reference_price("ES", Price::from_ticks(4_000));
+    risk.record_trade("ES", Price::from_ticks(4_001));
+    risk.record_book_update("ES", 0);
+    risk.positions_mut().set_tick_value("ES", 12.5);
+
+    let mut book = OrderBook::new();
+    for level in 0..10 {
+        book.apply_quote("ES", Side::Bid, Price::from_ticks(3_999 - level), 10)
+            .unwrap();
+        book.apply_quote("ES", Side::Ask, Price::from_ticks(4_001 + level), 10)
+            .unwrap();
+    }
+    let order = Order::limit(1, "ES", Side::Bid, Price::from_ticks(4_002), 5).with_account(1);
+
+    let check = || risk.check_with_book(black_box(&order), &book, BookState::Live, 100);
+    assert_eq!(check(), Ok(()));
+
+    c.bench_function("risk_check_with_book", |b| b.iter(|| black_box(check())));
+}
+
+criterion_group!(benches, benchmark_check_with_book);
+criterion_main!(benches);
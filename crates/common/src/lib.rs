@@ -3,3 +3,5 @@
 
 pub mod cpu_features;
 pub mod byte_utils;
+pub mod price;
+pub mod side;
@@ -0,0 +1,295 @@
+//! price.rs
+//! Fixed-point price representation based on an instrument's tick size.
+//! Prices are stored as a signed integer number of ticks so they can be compared, hashed
+//! and used as map keys exactly, unlike `f64`.
+//!
+//! # Key Points
+//! - `Price` is just a tick count; it only becomes a decimal value together with a `TickSize`.
+//! - `TickSize` is kept as an exact decimal (`units * 10^-decimals`), so string conversions never round.
+//! - Conversions from `f64` round to the nearest tick and reject non-finite or out-of-range values.
+//!
+//! # Example
+//! ```
+//! use common::price::{Price, TickSize};
+//!
+//! let tick = TickSize::parse("0.25").unwrap();
+//! let price = tick.parse_price("101.75").unwrap();
+//! assert_eq!(price, Price::from_ticks(407));
+//! assert_eq!(tick.format_price(price), "101.75");
+//! assert_eq!(tick.price_to_f64(price), 101.75);
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+/// Largest number of decimals supported by `TickSize` (10^18 still fits in an `i64`).
+const MAX_DECIMALS: u32 = 18;
+
+/// A price expressed as an integer number of ticks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Price(i64);
+
+impl Price {
+    pub const ZERO: Price = Price(0);
+    pub const MIN: Price = Price(i64::MIN);
+    pub const MAX: Price = Price(i64::MAX);
+
+    /// Creates a price from a raw tick count.
+    pub const fn from_ticks(ticks: i64) -> Self {
+        Price(ticks)
+    }
+
+    /// Returns the raw tick count.
+    pub const fn ticks(self) -> i64 {
+        self.0
+    }
+
+    /// Returns the price moved by `ticks` (negative moves down), saturating at the bounds.
+    pub const fn offset(self, ticks: i64) -> Self {
+        Price(self.0.saturating_add(ticks))
+    }
+
+    /// Number of ticks between `self` and `other` (`self - other`), saturating at the bounds
+    /// (e.g. between the `MIN` and `MAX` sentinels).
+    pub const fn ticks_from(self, other: Price) -> i64 {
+        self.0.saturating_sub(other.0)
+    }
+}
+
+/// Errors returned when parsing tick sizes or prices from decimal strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceError {
+    /// The input string was empty or only a sign.
+    Empty,
+    /// The input contained a character that is not a digit, sign or single decimal point.
+    InvalidDigit,
+    /// The input had more significant decimals than supported.
+    TooManyDecimals,
+    /// The value is not a whole multiple of the tick size.
+    NotOnTick,
+    /// The value does not fit in the fixed-point representation.
+    Overflow,
+    /// A tick size must be strictly positive.
+    ZeroTick,
+    /// A floating point input was NaN or infinite.
+    NotFinite,
+}
+
+impl fmt::Display for PriceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            PriceError::Empty => "empty price string",
+            PriceError::InvalidDigit => "invalid character in price string",
+            PriceError::TooManyDecimals => "too many decimal places",
+            PriceError::NotOnTick => "price is not a multiple of the tick size",
+            PriceError::Overflow => "price out of range",
+            PriceError::ZeroTick => "tick size must be positive",
+            PriceError::NotFinite => "price is not a finite number",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl Error for PriceError {}
+
+/// Tick size of an instrument, stored exactly as `units * 10^-decimals`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TickSize {
+    units: u64,
+    decimals: u32,
+}
+
+impl TickSize {
+    /// Creates a tick size of `units * 10^-decimals`, e.g. `TickSize::new(25, 2)` is 0.25.
+    /// Panics if `units` is 0 or `decimals` exceeds 18.
+    pub fn new(units: u64, decimals: u32) -> Self {
+        assert!(units > 0, "tick size must be positive");
+        assert!(decimals <= MAX_DECIMALS, "too many decimal places");
+        TickSize { units, decimals }
+    }
+
+    /// Parses a tick size from a decimal string such as `"0.01"`.
+    pub fn parse(s: &str) -> Result<Self, PriceError> {
+        let (negative, scaled, decimals) = parse_decimal(s)?;
+        if negative || scaled == 0 {
+            return Err(PriceError::ZeroTick);
+        }
+        // Strip trailing zeros so "0.50" and "0.5" compare equal.
+        let (mut units, mut decimals) = (scaled as u64, decimals);
+        while decimals > 0 && units % 10 == 0 {
+            units /= 10;
+            decimals -= 1;
+        }
+        Ok(TickSize { units, decimals })
+    }
+
+    /// Returns the tick size as an `f64`.
+    pub fn to_f64(self) -> f64 {
+        self.units as f64 / pow10(self.decimals) as f64
+    }
+
+    /// Converts a floating point value to the nearest tick. NaN, infinities and values whose
+    /// tick count does not fit in an `i64` are rejected rather than saturated.
+    pub fn price_from_f64(self, value: f64) -> Result<Price, PriceError> {
+        if !value.is_finite() {
+            return Err(PriceError::NotFinite);
+        }
+        let ticks = (value * pow10(self.decimals) as f64 / self.units as f64).round();
+        // i64::MAX is not representable as f64; 2^63 is the first value out of range.
+        if !ticks.is_finite() || ticks < i64::MIN as f64 || ticks >= i64::MAX as f64 {
+            return Err(PriceError::Overflow);
+        }
+        Ok(Price(ticks as i64))
+    }
+
+    /// Converts a price back to an `f64`.
+    pub fn price_to_f64(self, price: Price) -> f64 {
+        (price.0 as f64 * self.units as f64) / pow10(self.decimals) as f64
+    }
+
+    /// Parses a decimal string into a price. The value must lie exactly on the tick grid.
+    pub fn parse_price(self, s: &str) -> Result<Price, PriceError> {
+        let (negative, scaled, decimals) = parse_decimal(s)?;
+        // Rescale the parsed value to this tick size's number of decimals.
+        let scaled = if decimals > self.decimals {
+            let div = pow10(decimals - self.decimals);
+            if scaled % div != 0 {
+                return Err(PriceError::NotOnTick);
+            }
+            scaled / div
+        } else {
+            scaled
+                .checked_mul(pow10(self.decimals - decimals))
+                .ok_or(PriceError::Overflow)?
+        };
+        let units = self.units as i64;
+        if scaled % units != 0 {
+            return Err(PriceError::NotOnTick);
+        }
+        let ticks = scaled / units;
+        Ok(Price(if negative { -ticks } else { ticks }))
+    }
+
+    /// Formats a price as a decimal string with exactly this tick size's number of decimals.
+    pub fn format_price(self, price: Price) -> String {
+        let scaled = price.0 as i128 * self.units as i128;
+        let sign = if scaled < 0 { "-" } else { "" };
+        let abs = scaled.unsigned_abs();
+        if self.decimals == 0 {
+            return format!("{}{}", sign, abs);
+        }
+        let div = pow10(self.decimals) as u128;
+        format!(
+            "{}{}.{:0width$}",
+            sign,
+            abs / div,
+            abs % div,
+            width = self.decimals as usize
+        )
+    }
+}
+
+#[inline]
+fn pow10(exp: u32) -> i64 {
+    10i64.pow(exp)
+}
+
+/// Parses `[-]digits[.digits]` into `(negative, scaled_magnitude, decimals)`.
+fn parse_decimal(s: &str) -> Result<(bool, i64, u32), PriceError> {
+    let s = s.trim();
+    let (negative, body) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    if body.is_empty() || body == "." {
+        return Err(PriceError::Empty);
+    }
+
+    let mut scaled: i64 = 0;
+    let mut decimals: u32 = 0;
+    let mut seen_point = false;
+    for b in body.bytes() {
+        match b {
+            b'.' if !seen_point => seen_point = true,
+            b'0'..=b'9' => {
+                if seen_point {
+                    decimals += 1;
+                    if decimals > MAX_DECIMALS {
+                        return Err(PriceError::TooManyDecimals);
+                    }
+                }
+                scaled = scaled
+                    .checked_mul(10)
+                    .and_then(|v| v.checked_add((b - b'0') as i64))
+                    .ok_or(PriceError::Overflow)?;
+            }
+            _ => return Err(PriceError::InvalidDigit),
+        }
+    }
+    Ok((negative, scaled, decimals))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tick_size() {
+        assert_eq!(TickSize::parse("0.25"), Ok(TickSize::new(25, 2)));
+        assert_eq!(TickSize::parse("0.50"), Ok(TickSize::new(5, 1)));
+        assert_eq!(TickSize::parse("5"), Ok(TickSize::new(5, 0)));
+        assert_eq!(TickSize::parse("0"), Err(PriceError::ZeroTick));
+        assert_eq!(TickSize::parse("-0.1"), Err(PriceError::ZeroTick));
+    }
+
+    #[test]
+    fn test_parse_and_format_price() {
+        let tick = TickSize::new(1, 2);
+        assert_eq!(tick.parse_price("101.25"), Ok(Price::from_ticks(10125)));
+        assert_eq!(tick.parse_price("101.2"), Ok(Price::from_ticks(10120)));
+        assert_eq!(tick.parse_price("101.250"), Ok(Price::from_ticks(10125)));
+        assert_eq!(tick.parse_price("-0.05"), Ok(Price::from_ticks(-5)));
+        assert_eq!(tick.format_price(Price::from_ticks(10125)), "101.25");
+        assert_eq!(tick.format_price(Price::from_ticks(-5)), "-0.05");
+        assert_eq!(TickSize::new(5, 0).format_price(Price::from_ticks(3)), "15");
+    }
+
+    #[test]
+    fn test_parse_price_rejects_off_tick_and_garbage() {
+        let tick = TickSize::new(25, 2);
+        assert_eq!(tick.parse_price("101.10"), Err(PriceError::NotOnTick));
+        assert_eq!(tick.parse_price("101.251"), Err(PriceError::NotOnTick));
+        assert_eq!(tick.parse_price("10a.25"), Err(PriceError::InvalidDigit));
+        assert_eq!(tick.parse_price("1.2.3"), Err(PriceError::InvalidDigit));
+        assert_eq!(tick.parse_price(""), Err(PriceError::Empty));
+    }
+
+    #[test]
+    fn test_f64_round_trip() {
+        let tick = TickSize::new(1, 2);
+        // 0.1 + 0.2 is not exactly 0.3 in binary, but lands on the same tick.
+        assert_eq!(tick.price_from_f64(0.1 + 0.2), tick.price_from_f64(0.3));
+        assert_eq!(tick.price_from_f64(101.25), Ok(Price::from_ticks(10125)));
+        assert_eq!(tick.price_from_f64(f64::NAN), Err(PriceError::NotFinite));
+        assert_eq!(
+            tick.price_from_f64(f64::INFINITY),
+            Err(PriceError::NotFinite)
+        );
+        assert_eq!(tick.price_from_f64(1e18), Err(PriceError::Overflow));
+        assert_eq!(tick.price_from_f64(-1e18), Err(PriceError::Overflow));
+        assert_eq!(tick.price_to_f64(Price::from_ticks(10125)), 101.25);
+        assert_eq!(TickSize::new(25, 2).to_f64(), 0.25);
+    }
+
+    #[test]
+    fn test_price_ordering_and_offsets() {
+        let p = Price::from_ticks(100);
+        assert!(p.offset(1) > p);
+        assert_eq!(p.offset(-3).ticks(), 97);
+        assert_eq!(p.offset(5).ticks_from(p), 5);
+        assert_eq!(Price::MAX.offset(1), Price::MAX);
+        assert_eq!(Price::MAX.ticks_from(Price::MIN), i64::MAX);
+        assert_eq!(Price::MIN.ticks_from(Price::MAX), i64::MIN);
+    }
+}
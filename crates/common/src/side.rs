@@ -0,0 +1,19 @@
+//! side.rs
+//! Book side shared by the reception layer (normalized messages) and the core pipeline (order book).
+
+/// Which side of the book a quote or order belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+impl Side {
+    /// Returns the opposite side.
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        }
+    }
+}
@@ -4,11 +4,27 @@
 //! # Example
 //! - Update aggregator with each new trade price
 //! - Recalculate EMA in O(1)
-//! - Optionally compute linear regression over last N data points.
+//! - Rolling sum, mean, variance, min and max over the last `window_size` prices, kept in a
+//!   `RollingStats` ring buffer so each update is O(1) (amortised for min and max).
+//! - Rolling OLS slope, intercept and R² of the last N prices against their position.
+//! - Realised volatility over the window: close-to-close (square root of the summed squared
+//!   log returns) and Parkinson (from the window's high and low).
+//! - Z-score of the latest price against the window mean and standard deviation.
+//! - Time-based statistics for irregular tick arrival (`TimeWindowStats`, `TimeDecayEma`) are
+//!   kept next to the aggregators of an instrument by its `AggregatorSet`.
+//! - As a `BookListener`, update from the mid price (in ticks) whenever the top of book changes.
+//! - Microstructure features (VWAP, signed volume, order-flow imbalance, microprice) are not
+//!   part of the aggregator; they are kept per instrument in a `MarketFeatures`.
+
+use super::book_events::{BookEvent, BookListener};
+use super::rolling_window::{Regression, RollingStats};
 
 pub struct Aggregator {
     window_size: usize,
-    prices: Vec<f64>,
+    stats: RollingStats,
+    /// Log returns between consecutive prices in the window.
+    returns: RollingStats,
+    last_price: Option<f64>,
     current_ema: f64,
 }
 
@@ -16,21 +32,26 @@ impl Aggregator {
     pub fn new(window_size: usize) -> Self {
         Aggregator {
             window_size,
-            prices: Vec::with_capacity(window_size),
+            stats: RollingStats::new(window_size),
+            returns: RollingStats::new(window_size.saturating_sub(1)),
+            last_price: None,
             current_ema: 0.0,
         }
     }
 
     pub fn update_price(&mut self, price: f64) {
-        // Add the new price, remove old if at capacity
-        if self.prices.len() == self.window_size {
-            self.prices.remove(0);
+        // Add the new price, overwriting the oldest if at capacity
+        self.stats.push(price);
+        if let Some(last) = self.last_price {
+            if last > 0.0 && price > 0.0 {
+                self.returns.push((price / last).ln());
+            }
         }
-        self.prices.push(price);
+        self.last_price = Some(price);
 
         // Compute EMA incrementally
         let alpha = 2.0 / (self.window_size as f64 + 1.0);
-        self.current_ema = if self.prices.len() == 1 {
+        self.current_ema = if self.stats.len() == 1 {
             // Initialize EMA with the first price
             price
         } else {
@@ -43,5 +64,95 @@ impl Aggregator {
         self.current_ema
     }
 
-    // Future: implement regression or other statistical measures here.
+    /// Rolling statistics over the last `window_size` prices.
+    pub fn stats(&self) -> &RollingStats {
+        &self.stats
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.stats.mean()
+    }
+
+    pub fn variance(&self) -> f64 {
+        self.stats.variance()
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        self.stats.min()
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.stats.max()
+    }
+
+    /// Least-squares trend of the window; slope is per update. `None` before two prices.
+    pub fn regression(&self) -> Option<Regression> {
+        self.stats.regression()
+    }
+
+    /// Close-to-close realised volatility over the window: `sqrt(sum(r^2))` of the log
+    /// returns between consecutive prices. `None` before the first return.
+    pub fn realised_volatility(&self) -> Option<f64> {
+        if self.returns.is_empty() {
+            return None;
+        }
+        let n = self.returns.len() as f64;
+        let mean = self.returns.mean();
+        let sum_squares = self.returns.variance() * (n - 1.0) + n * mean * mean;
+        Some(sum_squares.max(0.0).sqrt())
+    }
+
+    /// Parkinson volatility over the window, treating it as one bar:
+    /// `ln(high / low) / sqrt(4 ln 2)`. `None` if empty or not all prices are positive.
+    pub fn parkinson_volatility(&self) -> Option<f64> {
+        let (low, high) = (self.stats.min()?, self.stats.max()?);
+        if low <= 0.0 {
+            return None;
+        }
+        Some((high / low).ln() / (4.0 * std::f64::consts::LN_2).sqrt())
+    }
+
+    /// How many standard deviations the latest price is from the window mean. `None` before
+    /// two prices or when the window has no spread.
+    pub fn z_score(&self) -> Option<f64> {
+        let latest = self.last_price?;
+        let std_dev = self.stats.std_dev();
+        (std_dev > 0.0).then(|| (latest - self.stats.mean()) / std_dev)
+    }
+}
+
+impl BookListener for Aggregator {
+    /// Feeds the mid price, in ticks, on every two-sided top-of-book change.
+    fn on_book_event(&mut self, _symbol: &str, event: &BookEvent) {
+        if let BookEvent::TopOfBook {
+            bid: Some((bid, _)),
+            ask: Some((ask, _)),
+        } = *event
+        {
+            self.update_price((bid.ticks() + ask.ticks()) as f64 / 2.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volatility_and_z_score() {
+        let mut aggregator = Aggregator::new(3);
+        assert_eq!(aggregator.realised_volatility(), None);
+        for price in [1000.0, 100.0, 110.0, 121.0] {
+            aggregator.update_price(price);
+        }
+        // Window [100, 110, 121]: two returns of ln(1.1).
+        let expected = (2.0 * 1.1f64.ln().powi(2)).sqrt();
+        assert!((aggregator.realised_volatility().unwrap() - expected).abs() < 1e-12);
+        let expected = 1.21f64.ln() / (4.0 * std::f64::consts::LN_2).sqrt();
+        assert!((aggregator.parkinson_volatility().unwrap() - expected).abs() < 1e-12);
+
+        let (mean, std_dev) = (331.0 / 3.0, aggregator.stats().std_dev());
+        assert!((aggregator.z_score().unwrap() - (121.0 - mean) / std_dev).abs() < 1e-12);
+        assert!(aggregator.regression().unwrap().slope > 10.0);
+    }
 }
@@ -0,0 +1,390 @@
+//! aggregator_registry.rs
+//! Owns the aggregators of every instrument, keyed by interned instrument ids.
+//!
+//! # Key Concepts
+//! - `SymbolTable` interns symbols into dense `InstrumentId`s. Only the first sighting of a
+//!   symbol allocates; later lookups borrow the `&str` and the aggregators are found by
+//!   indexing a `Vec` with the id.
+//! - Each instrument has an `AggregatorSet`: one `Aggregator` per configured tick-count
+//!   window, one `TimeWindowStats` and `TimeDecayEma` per configured time window (e.g. the
+//!   last 500 ms and the last 5 s), and one `MarketFeatures` for the instrument. Sets are
+//!   created on the instrument's first message from its `AggregatorConfig`, or the default
+//!   config; instruments with neither are ignored.
+//! - Trades feed each aggregator's price series (with their timestamps, for the time-based
+//!   statistics) and the set's market features; quotes feed the market features (see
+//!   `MarketFeatures::on_market_message`).
+//! - `on_messages` processes a decoded batch, reusing the previous message's id while the
+//!   symbol does not change.
+//! - Without a default config, symbols that have no config of their own are dropped before
+//!   interning, so a feed's unknown instruments do not grow the `SymbolTable`.
+
+use std::collections::HashMap;
+
+use reception_layer::message_types::MarketMessage;
+
+use super::aggregator::Aggregator;
+use super::market_features::MarketFeatures;
+use super::time_window::{TimeDecayEma, TimeWindowStats};
+
+/// Dense id of an interned symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InstrumentId(u32);
+
+impl InstrumentId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    ids: HashMap<String, InstrumentId>,
+    symbols: Vec<String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    /// Id of `symbol`, assigning the next id on first use.
+    pub fn intern(&mut self, symbol: &str) -> InstrumentId {
+        if let Some(&id) = self.ids.get(symbol) {
+            return id;
+        }
+        let id = InstrumentId(self.symbols.len() as u32);
+        self.symbols.push(symbol.to_string());
+        self.ids.insert(symbol.to_string(), id);
+        id
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<InstrumentId> {
+        self.ids.get(symbol).copied()
+    }
+
+    pub fn symbol(&self, id: InstrumentId) -> Option<&str> {
+        self.symbols.get(id.index()).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+/// Statistics over the last `window_ns` of event time, with an EMA decaying with time
+/// constant `tau_ns`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindowConfig {
+    pub window_ns: u64,
+    pub tau_ns: u64,
+}
+
+/// Aggregators to create for an instrument: one per tick-count window and one per time
+/// window.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AggregatorConfig {
+    pub windows: Vec<usize>,
+    pub time_windows: Vec<TimeWindowConfig>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegistryConfig {
+    /// Used for instruments without their own entry; `None` ignores them.
+    pub default: Option<AggregatorConfig>,
+    pub instruments: HashMap<String, AggregatorConfig>,
+}
+
+/// The aggregators of one instrument, in the order of its configured windows, and its
+/// market features.
+pub struct AggregatorSet {
+    aggregators: Vec<Aggregator>,
+    /// In the order of the configured time windows.
+    timed: Vec<(TimeWindowStats, TimeDecayEma)>,
+    features: MarketFeatures,
+}
+
+impl AggregatorSet {
+    fn new(config: &AggregatorConfig) -> Self {
+        let aggregators = config
+            .windows
+            .iter()
+            .map(|&window| Aggregator::new(window))
+            .collect();
+        let timed = config
+            .time_windows
+            .iter()
+            .map(|w| {
+                (
+                    TimeWindowStats::new(w.window_ns),
+                    TimeDecayEma::new(w.tau_ns),
+                )
+            })
+            .collect();
+        // Rolling VWAP and order-flow imbalance cover the longest window.
+        let window = config.windows.iter().copied().max().unwrap_or(1);
+        AggregatorSet {
+            aggregators,
+            timed,
+            features: MarketFeatures::new(window),
+        }
+    }
+
+    /// Statistics over the `index`th configured time window.
+    pub fn time_stats(&self, index: usize) -> Option<&TimeWindowStats> {
+        self.timed.get(index).map(|(stats, _)| stats)
+    }
+
+    /// The time-decayed EMA of the `index`th configured time window; `None` before the
+    /// first trade.
+    pub fn time_decayed_ema(&self, index: usize) -> Option<f64> {
+        self.timed.get(index).and_then(|(_, ema)| ema.value())
+    }
+
+    /// Number of configured time windows.
+    pub fn time_window_count(&self) -> usize {
+        self.timed.len()
+    }
+
+    /// VWAP, signed volume, order-flow imbalance, microprice and trade intensity of the
+    /// instrument, over its longest window.
+    pub fn features(&self) -> &MarketFeatures {
+        &self.features
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Aggregator> {
+        self.aggregators.get(index)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Aggregator> {
+        self.aggregators.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.aggregators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aggregators.is_empty()
+    }
+
+    fn on_message(&mut self, message: &MarketMessage) {
+        if let MarketMessage::Trade {
+            price, timestamp, ..
+        } = *message
+        {
+            let price = price.ticks() as f64;
+            for aggregator in &mut self.aggregators {
+                aggregator.update_price(price);
+            }
+            for (stats, ema) in &mut self.timed {
+                stats.push(price, timestamp);
+                ema.update(price, timestamp);
+            }
+        }
+        self.features.on_market_message(message);
+    }
+}
+
+/// Whether an instrument's set exists yet.
+enum Slot {
+    Pending,
+    /// No config applies to the instrument.
+    Ignored,
+    Ready(Box<AggregatorSet>),
+}
+
+pub struct AggregatorRegistry {
+    config: RegistryConfig,
+    symbols: SymbolTable,
+    /// Indexed by `InstrumentId`.
+    slots: Vec<Slot>,
+}
+
+impl AggregatorRegistry {
+    pub fn new(config: RegistryConfig) -> Self {
+        AggregatorRegistry {
+            config,
+            symbols: SymbolTable::new(),
+            slots: Vec::new(),
+        }
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Interns `symbol` ahead of its first message, e.g. at start-up.
+    pub fn intern(&mut self, symbol: &str) -> InstrumentId {
+        let id = self.symbols.intern(symbol);
+        if self.slots.len() <= id.index() {
+            self.slots.resize_with(id.index() + 1, || Slot::Pending);
+        }
+        id
+    }
+
+    /// Aggregators of `id`, if it has received a message and has a config.
+    pub fn get(&self, id: InstrumentId) -> Option<&AggregatorSet> {
+        match self.slots.get(id.index())? {
+            Slot::Ready(set) => Some(set),
+            Slot::Pending | Slot::Ignored => None,
+        }
+    }
+
+    /// Aggregators of `symbol`; a convenience for lookups off the hot path.
+    pub fn get_by_symbol(&self, symbol: &str) -> Option<&AggregatorSet> {
+        self.get(self.symbols.get(symbol)?)
+    }
+
+    pub fn on_message(&mut self, message: &MarketMessage) {
+        if let Some(id) = self.lookup(message.symbol()) {
+            self.dispatch(id, message);
+        }
+    }
+
+    /// Processes a batch of decoded messages in order.
+    pub fn on_messages(&mut self, messages: &[MarketMessage]) {
+        let mut current: Option<(&str, Option<InstrumentId>)> = None;
+        for message in messages {
+            let symbol = message.symbol();
+            let id = match current {
+                Some((last, id)) if last == symbol => id,
+                _ => self.lookup(symbol),
+            };
+            current = Some((symbol, id));
+            if let Some(id) = id {
+                self.dispatch(id, message);
+            }
+        }
+    }
+
+    /// Id of a message's symbol, interning it only if a config could apply, so that symbols
+    /// we do not aggregate never grow the `SymbolTable`.
+    fn lookup(&mut self, symbol: &str) -> Option<InstrumentId> {
+        if let Some(id) = self.symbols.get(symbol) {
+            return Some(id);
+        }
+        if self.config.default.is_none() && !self.config.instruments.contains_key(symbol) {
+            return None;
+        }
+        Some(self.intern(symbol))
+    }
+
+    fn dispatch(&mut self, id: InstrumentId, message: &MarketMessage) {
+        let slot = &mut self.slots[id.index()];
+        if let Slot::Pending = slot {
+            let config = self
+                .config
+                .instruments
+                .get(message.symbol())
+                .or(self.config.default.as_ref());
+            *slot = match config {
+                Some(config) => Slot::Ready(Box::new(AggregatorSet::new(config))),
+                None => Slot::Ignored,
+            };
+        }
+        if let Slot::Ready(set) = slot {
+            set.on_message(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::price::Price;
+
+    const MS: u64 = 1_000_000;
+
+    fn trade(symbol: &str, ticks: i64) -> MarketMessage {
+        trade_at(symbol, ticks, 0)
+    }
+
+    fn trade_at(symbol: &str, ticks: i64, timestamp: u64) -> MarketMessage {
+        MarketMessage::Trade {
+            symbol: symbol.to_string(),
+            price: Price::from_ticks(ticks),
+            size: 1,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn test_lazy_sets_from_config() {
+        let mut config = RegistryConfig {
+            default: Some(AggregatorConfig {
+                windows: vec![2],
+                time_windows: Vec::new(),
+            }),
+            ..RegistryConfig::default()
+        };
+        config.instruments.insert(
+            "ES".to_string(),
+            AggregatorConfig {
+                windows: vec![2, 4],
+                time_windows: vec![
+                    TimeWindowConfig {
+                        window_ns: 500 * MS,
+                        tau_ns: 100 * MS,
+                    },
+                    TimeWindowConfig {
+                        window_ns: 5_000 * MS,
+                        tau_ns: 1_000 * MS,
+                    },
+                ],
+            },
+        );
+        let mut registry = AggregatorRegistry::new(config);
+        let es = registry.intern("ES");
+        assert!(registry.get(es).is_none());
+
+        registry.on_messages(&[
+            trade_at("ES", 100, 0),
+            trade_at("ES", 102, 1_000 * MS),
+            trade("NQ", 50),
+            trade_at("ES", 104, 5_500 * MS),
+        ]);
+        let set = registry.get(es).unwrap();
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.get(0).unwrap().mean(), 103.0);
+        assert_eq!(set.get(1).unwrap().mean(), 102.0);
+        // Time windows are independent of the count windows: the last 500 ms and 5 s.
+        assert_eq!(set.time_window_count(), 2);
+        assert_eq!(set.time_stats(0).unwrap().len(), 1);
+        let last_5s = set.time_stats(1).unwrap();
+        assert_eq!((last_5s.len(), last_5s.mean()), (2, 103.0));
+        // 100 held for 1 s, then 102 held for 4.5 s.
+        let expected = 102.0 - 2.0 * (-4.5f64).exp();
+        assert!((set.time_decayed_ema(1).unwrap() - expected).abs() < 1e-9);
+        // One set of features per instrument, over the longest window.
+        assert_eq!(set.features().session_vwap(), Some(102.0));
+        assert_eq!(set.features().rolling_vwap(), Some(102.0));
+        assert_eq!(registry.get_by_symbol("NQ").unwrap().len(), 1);
+        assert_eq!(registry.symbols().get("NQ"), Some(InstrumentId(1)));
+        assert_eq!(registry.symbols().symbol(es), Some("ES"));
+    }
+
+    #[test]
+    fn test_unconfigured_instruments_are_ignored() {
+        let mut registry = AggregatorRegistry::new(RegistryConfig::default());
+        registry.on_message(&trade("ES", 100));
+        assert!(registry.get_by_symbol("ES").is_none());
+        assert_eq!(registry.symbols().len(), 0);
+
+        let mut config = RegistryConfig::default();
+        config.instruments.insert(
+            "ES".to_string(),
+            AggregatorConfig {
+                windows: vec![2],
+                time_windows: Vec::new(),
+            },
+        );
+        let mut registry = AggregatorRegistry::new(config);
+        registry.on_messages(&[trade("NQ", 50), trade("ES", 100), trade("NQ", 51)]);
+        assert_eq!(registry.symbols().len(), 1);
+        assert_eq!(registry.get_by_symbol("ES").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,180 @@
+//! auction.rs
+//! Equilibrium price calculation for call auctions (opening and closing crosses).
+//!
+//! # Key Concepts
+//! - While an auction collects orders nothing trades and the book may be crossed.
+//! - The equilibrium price is the candidate (any resting limit price) that maximises the
+//!   executable volume, i.e. `min(buy volume at or above p, sell volume at or below p)`.
+//! - Market orders (market-on-open/close) take any price: they count towards the volume at
+//!   every candidate and execute ahead of limit orders. If only market orders are present the
+//!   reference price is the sole candidate.
+//! - Ties are broken in the usual order:
+//!   1. smallest absolute imbalance (surplus) at the price;
+//!   2. market pressure: if every tied price has a buy surplus take the highest, if every one
+//!      has a sell surplus take the lowest;
+//!   3. the price closest to the reference price (e.g. the previous close), then the lower
+//!      price.
+//!
+//! # Performance
+//! - Sorts the levels once, then a linear scan. Auctions run rarely, so this allocates.
+
+use common::price::Price;
+
+use super::order_book::Level;
+
+/// The outcome of an uncross at the equilibrium price.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Equilibrium {
+    pub price: Price,
+    /// Quantity that executes at `price`.
+    pub volume: u64,
+    /// Buy volume minus sell volume at `price`; positive means unmatched buyers.
+    pub imbalance: i64,
+}
+
+/// Computes the equilibrium price for the given bid and ask levels (any order, duplicates
+/// allowed). Returns `None` when the book does not cross.
+pub fn equilibrium(
+    bids: &[Level],
+    asks: &[Level],
+    reference: Option<Price>,
+) -> Option<Equilibrium> {
+    equilibrium_with_market(bids, asks, (0, 0), reference)
+}
+
+/// Like `equilibrium`, with the `(buy, sell)` quantities of market orders collected in the
+/// auction.
+pub fn equilibrium_with_market(
+    bids: &[Level],
+    asks: &[Level],
+    market: (u64, u64),
+    reference: Option<Price>,
+) -> Option<Equilibrium> {
+    let mut bids = bids.to_vec();
+    let mut asks = asks.to_vec();
+    bids.sort_unstable_by_key(|l| l.0);
+    asks.sort_unstable_by_key(|l| l.0);
+    let mut prices: Vec<Price> = bids.iter().chain(&asks).map(|&(p, _)| p).collect();
+    prices.sort_unstable();
+    prices.dedup();
+    if prices.is_empty() {
+        prices.extend(reference);
+    }
+
+    // Candidates ascending: sell volume grows with the price, buy volume shrinks.
+    let total_buy: u64 = market.0 + bids.iter().map(|l| l.1).sum::<u64>();
+    let (mut next_bid, mut next_ask, mut buy_below, mut sell) = (0, 0, 0, market.1);
+    let mut candidates: Vec<Equilibrium> = Vec::with_capacity(prices.len());
+    for &price in &prices {
+        while next_bid < bids.len() && bids[next_bid].0 < price {
+            buy_below += bids[next_bid].1;
+            next_bid += 1;
+        }
+        while next_ask < asks.len() && asks[next_ask].0 <= price {
+            sell += asks[next_ask].1;
+            next_ask += 1;
+        }
+        let buy = total_buy - buy_below;
+        candidates.push(Equilibrium {
+            price,
+            volume: buy.min(sell),
+            imbalance: buy as i64 - sell as i64,
+        });
+    }
+
+    let max_volume = candidates.iter().map(|c| c.volume).max()?;
+    if max_volume == 0 {
+        return None;
+    }
+    candidates.retain(|c| c.volume == max_volume);
+    let min_imbalance = candidates
+        .iter()
+        .map(|c| c.imbalance.unsigned_abs())
+        .min()?;
+    candidates.retain(|c| c.imbalance.unsigned_abs() == min_imbalance);
+
+    if candidates.iter().all(|c| c.imbalance > 0) {
+        return candidates.last().copied();
+    }
+    if candidates.iter().all(|c| c.imbalance < 0) {
+        return candidates.first().copied();
+    }
+    match reference {
+        Some(reference) => candidates
+            .iter()
+            .min_by_key(|c| c.price.ticks().abs_diff(reference.ticks()))
+            .copied(),
+        None => candidates.first().copied(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(ticks: i64) -> Price {
+        Price::from_ticks(ticks)
+    }
+
+    #[test]
+    fn test_maximises_volume() {
+        let bids = [(px(102), 5), (px(101), 5), (px(100), 10)];
+        let asks = [(px(99), 4), (px(100), 4), (px(101), 4), (px(103), 10)];
+        let eq = equilibrium(&bids, &asks, None).unwrap();
+        assert_eq!(
+            eq,
+            Equilibrium {
+                price: px(101),
+                volume: 10,
+                imbalance: -2
+            }
+        );
+        assert_eq!(equilibrium(&[(px(99), 1)], &[(px(100), 1)], None), None);
+    }
+
+    #[test]
+    fn test_tie_breaks() {
+        // Volume 5 at 100 and 101, both with a buy surplus: market pressure picks the higher.
+        let bids = [(px(101), 10)];
+        let asks = [(px(100), 5)];
+        assert_eq!(equilibrium(&bids, &asks, None).unwrap().price, px(101));
+
+        // Balanced at every candidate: the reference price decides.
+        let bids = [(px(103), 5)];
+        let asks = [(px(100), 5)];
+        assert_eq!(
+            equilibrium(&bids, &asks, Some(px(102))).unwrap().price,
+            px(103)
+        );
+        assert_eq!(
+            equilibrium(&bids, &asks, Some(px(90))).unwrap().price,
+            px(100)
+        );
+    }
+
+    #[test]
+    fn test_market_orders_count_at_every_price() {
+        // The 4 market lots buy at any price, so all 9 asks clear at 102.
+        let bids = [(px(102), 5)];
+        let asks = [(px(100), 2), (px(101), 2), (px(102), 5)];
+        let eq = equilibrium_with_market(&bids, &asks, (4, 0), None).unwrap();
+        assert_eq!(
+            eq,
+            Equilibrium {
+                price: px(102),
+                volume: 9,
+                imbalance: 0
+            }
+        );
+
+        assert_eq!(equilibrium_with_market(&[], &[], (3, 2), None), None);
+        assert_eq!(
+            equilibrium_with_market(&[], &[], (3, 2), Some(px(100))),
+            Some(Equilibrium {
+                price: px(100),
+                volume: 2,
+                imbalance: 1
+            })
+        );
+    }
+}
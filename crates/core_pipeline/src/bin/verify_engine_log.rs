@@ -0,0 +1,54 @@
+//! verify_engine_log.rs
+//! Checks that the matching engine is deterministic using logs written by `EngineLog::encode`.
+//!
+//! # Usage
+//! - `verify_engine_log <recorded.log>` replays the recorded commands into a fresh engine and
+//!   compares the outputs with the recording.
+//! - `verify_engine_log <a.log> <b.log>` diffs two recorded runs.
+//!
+//! Exits with status 1 and prints the first differing record when the logs diverge.
+
+use std::process::ExitCode;
+
+use core_pipeline::engine_log::{diff, replay, EngineLog};
+
+fn load(path: &str) -> Result<EngineLog, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    EngineLog::decode(&bytes).map_err(|e| format!("{}: invalid engine log ({:?})", path, e))
+}
+
+fn run(args: &[String]) -> Result<bool, String> {
+    let (expected, actual) = match args {
+        [recorded] => {
+            let log = load(recorded)?;
+            let replayed = replay(&log);
+            (log, replayed)
+        }
+        [a, b] => (load(a)?, load(b)?),
+        _ => return Err("usage: verify_engine_log <recorded.log> [<other.log>]".to_string()),
+    };
+    match diff(&expected, &actual) {
+        None => {
+            println!("identical: {} records", expected.records().len());
+            Ok(true)
+        }
+        Some(divergence) => {
+            println!("logs diverge at record {}", divergence.index);
+            println!("  expected: {:?}", divergence.expected);
+            println!("  actual:   {:?}", divergence.actual);
+            Ok(false)
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::from(2)
+        }
+    }
+}
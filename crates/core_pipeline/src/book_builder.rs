@@ -0,0 +1,657 @@
+//! book_builder.rs
+//! Feeds decoded `reception_layer` messages into an `OrderBook`, tracking exchange sequence
+//! numbers per channel and recovering from gaps with snapshots.
+//!
+//! # Recovery
+//! - Each channel expects `seq = last + 1`. Older sequence numbers are dropped as duplicates.
+//! - On a gap, every symbol seen on the channel is marked `Stale` and its incremental updates
+//!   are buffered instead of applied.
+//! - A `Snapshot` whose `last_seq` covers the gap rebuilds the symbol's book; buffered updates
+//!   newer than `last_seq` are replayed and the book goes `Live` again.
+//! - If the snapshot is ahead of the incremental stream the book is `Recovering` until the
+//!   stream catches up; updates already reflected in the snapshot are skipped.
+//! - An L3 update that does not match the book (e.g. unknown order id) also marks it `Stale`.
+//!   If that happens while replaying, the next snapshot must cover every replayed update.
+//! - The replay buffer of a channel holds at most `max_buffered` updates. On overflow it is
+//!   dropped and `BufferOverflow` tells the caller to resubscribe: the stale symbols then need
+//!   a snapshot at least as new as the overflowing update.
+//!
+//! Only `Live` books should be traded on.
+
+use std::collections::HashMap;
+
+use reception_layer::message_types::{MarketMessage, OrderAction, SequencedMessage};
+
+use super::book_snapshot::{BookSnapshot, SnapshotError};
+use super::order_book::{BookMode, OrderBook, RestingOrder};
+use super::price_levels::{PriceLevels, TreeLevels};
+
+/// Health of a symbol's book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookState {
+    /// In sync with the exchange.
+    Live,
+    /// Updates were missed; waiting for a snapshot.
+    Stale,
+    /// Rebuilt from a snapshot that is ahead of the incremental stream; waiting to catch up.
+    Recovering,
+}
+
+/// What `BookBuilder::on_message` did with a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildOutcome {
+    /// The update was applied to the book.
+    Applied,
+    /// The message was already seen (sequence number below the expected one).
+    Duplicate,
+    /// The book is not live; the update was buffered for replay.
+    Buffered,
+    /// The update is already reflected in the snapshot the book was rebuilt from.
+    Skipped,
+    /// A sequence gap was detected; affected books are now stale and the update was buffered.
+    Gap {
+        channel: u32,
+        expected: u64,
+        received: u64,
+    },
+    /// An L3 update did not match the book, which is now stale.
+    Inconsistent,
+    /// A snapshot rebuilt the book and `replayed` buffered updates were applied.
+    SnapshotApplied { replayed: usize },
+    /// The snapshot was not needed (book live) or too old to cover the gap.
+    SnapshotIgnored,
+    /// The snapshot's orders were inconsistent; the book was left as it was, still not live,
+    /// and its buffered updates are kept for the next snapshot.
+    SnapshotRejected { error: SnapshotError },
+    /// The channel's replay buffer was full and has been dropped; its stale symbols must be
+    /// resubscribed to get a fresh snapshot.
+    BufferOverflow { channel: u32 },
+}
+
+/// Default bound on the updates buffered per channel while books are stale.
+pub const DEFAULT_MAX_BUFFERED: usize = 65_536;
+
+#[derive(Debug, Default)]
+struct ChannelState {
+    /// Next expected sequence number, once the first message has been seen.
+    next_seq: Option<u64>,
+    /// Incremental updates held while any symbol on the channel is not live.
+    buffer: Vec<SequencedMessage>,
+    /// Number of symbols on the channel that are not live.
+    not_live: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SymbolState {
+    state: BookState,
+    channel: u32,
+    /// A snapshot must include at least this sequence number to repair the book.
+    required_seq: u64,
+    /// Updates up to this sequence number are already in the book (from a snapshot).
+    applied_seq: u64,
+}
+
+/// Sequenced book building on top of an `OrderBook`.
+#[derive(Debug)]
+pub struct BookBuilder<L = TreeLevels> {
+    book: OrderBook<L>,
+    channels: HashMap<u32, ChannelState>,
+    symbols: HashMap<String, SymbolState>,
+    max_buffered: usize,
+}
+
+impl<L: PriceLevels> BookBuilder<L> {
+    pub fn new(book: OrderBook<L>) -> Self {
+        BookBuilder {
+            book,
+            channels: HashMap::new(),
+            symbols: HashMap::new(),
+            max_buffered: DEFAULT_MAX_BUFFERED,
+        }
+    }
+
+    /// Bounds the updates buffered per channel while books are stale.
+    pub fn with_max_buffered(mut self, max_buffered: usize) -> Self {
+        self.max_buffered = max_buffered;
+        self
+    }
+
+    /// The book being built.
+    pub fn book(&self) -> &OrderBook<L> {
+        &self.book
+    }
+
+    /// State of `symbol`'s book. Symbols never seen are reported as `Stale`.
+    pub fn state(&self, symbol: &str) -> BookState {
+        self.symbols
+            .get(symbol)
+            .map_or(BookState::Stale, |s| s.state)
+    }
+
+    /// Symbols currently waiting for a snapshot.
+    pub fn stale_symbols(&self) -> impl Iterator<Item = &str> {
+        self.symbols
+            .iter()
+            .filter(|(_, s)| s.state == BookState::Stale)
+            .map(|(symbol, _)| symbol.as_str())
+    }
+
+    /// Processes one message from the feed.
+    pub fn on_message(&mut self, msg: &SequencedMessage) -> BuildOutcome {
+        if let MarketMessage::Snapshot { .. } = msg.message {
+            return self.on_snapshot(msg);
+        }
+
+        let channel = self.channels.entry(msg.channel).or_default();
+        let mut gap = None;
+        if let Some(expected) = channel.next_seq {
+            if msg.seq < expected {
+                return BuildOutcome::Duplicate;
+            }
+            if msg.seq > expected {
+                gap = Some(expected);
+            }
+        }
+        channel.next_seq = Some(msg.seq + 1);
+
+        if let Some(expected) = gap {
+            self.mark_channel_stale(msg.channel, msg.seq - 1);
+            self.track_symbol(msg);
+            // An overflow here raises `required_seq` too; the gap is the more useful outcome.
+            self.buffer(msg);
+            return BuildOutcome::Gap {
+                channel: msg.channel,
+                expected,
+                received: msg.seq,
+            };
+        }
+
+        let state = self.track_symbol(msg);
+        match state.state {
+            BookState::Stale => {
+                if self.buffer(msg) {
+                    BuildOutcome::Buffered
+                } else {
+                    BuildOutcome::BufferOverflow {
+                        channel: msg.channel,
+                    }
+                }
+            }
+            BookState::Recovering if msg.seq <= state.applied_seq => BuildOutcome::Skipped,
+            BookState::Live | BookState::Recovering => {
+                if state.state == BookState::Recovering {
+                    self.set_state(msg.message.symbol(), BookState::Live);
+                }
+                if self.apply(&msg.message) {
+                    BuildOutcome::Applied
+                } else {
+                    self.set_state(msg.message.symbol(), BookState::Stale);
+                    if let Some(s) = self.symbols.get_mut(msg.message.symbol()) {
+                        s.required_seq = msg.seq;
+                    }
+                    BuildOutcome::Inconsistent
+                }
+            }
+        }
+    }
+
+    /// Registers the symbol on first sight and returns a copy of its state.
+    fn track_symbol(&mut self, msg: &SequencedMessage) -> SymbolState {
+        let symbol = msg.message.symbol();
+        if !self.symbols.contains_key(symbol) {
+            let channel = self.channels.entry(msg.channel).or_default();
+            // While the channel is recovering, a symbol first seen now may have missed updates too.
+            let state = if channel.not_live > 0 {
+                channel.not_live += 1;
+                BookState::Stale
+            } else {
+                BookState::Live
+            };
+            self.symbols.insert(
+                symbol.to_string(),
+                SymbolState {
+                    state,
+                    channel: msg.channel,
+                    required_seq: msg.seq.saturating_sub(1),
+                    applied_seq: 0,
+                },
+            );
+        }
+        self.symbols[symbol]
+    }
+
+    /// Buffers an update for replay. Returns `false` if the buffer was full: it is dropped,
+    /// and the stale symbols on the channel then need a snapshot that includes `msg`.
+    fn buffer(&mut self, msg: &SequencedMessage) -> bool {
+        let Some(channel) = self.channels.get_mut(&msg.channel) else {
+            return true;
+        };
+        if channel.buffer.len() < self.max_buffered {
+            channel.buffer.push(msg.clone());
+            return true;
+        }
+        channel.buffer.clear();
+        for s in self
+            .symbols
+            .values_mut()
+            .filter(|s| s.channel == msg.channel && s.state == BookState::Stale)
+        {
+            s.required_seq = s.required_seq.max(msg.seq);
+        }
+        false
+    }
+
+    /// Marks every symbol on `channel` stale; a snapshot must cover `required_seq`.
+    fn mark_channel_stale(&mut self, channel: u32, required_seq: u64) {
+        let mut newly_stale = 0;
+        for s in self.symbols.values_mut().filter(|s| s.channel == channel) {
+            if s.state == BookState::Live {
+                newly_stale += 1;
+            }
+            s.state = BookState::Stale;
+            s.required_seq = s.required_seq.max(required_seq);
+        }
+        if let Some(ch) = self.channels.get_mut(&channel) {
+            ch.not_live += newly_stale;
+        }
+    }
+
+    /// Moves a symbol to `state`, keeping the channel's count of non-live symbols in step.
+    fn set_state(&mut self, symbol: &str, state: BookState) {
+        let Some(s) = self.symbols.get_mut(symbol) else {
+            return;
+        };
+        let was_live = s.state == BookState::Live;
+        s.state = state;
+        let is_live = state == BookState::Live;
+        if let Some(ch) = self.channels.get_mut(&s.channel) {
+            if was_live && !is_live {
+                ch.not_live += 1;
+            } else if !was_live && is_live {
+                ch.not_live -= 1;
+                if ch.not_live == 0 {
+                    ch.buffer.clear();
+                }
+            }
+        }
+    }
+
+    fn on_snapshot(&mut self, msg: &SequencedMessage) -> BuildOutcome {
+        let MarketMessage::Snapshot {
+            symbol,
+            last_seq,
+            bids,
+            asks,
+            orders,
+            ..
+        } = &msg.message
+        else {
+            return BuildOutcome::SnapshotIgnored;
+        };
+        let last_seq = *last_seq;
+
+        let (channel, required_seq) = match self.symbols.get(symbol.as_str()) {
+            Some(s) if s.state == BookState::Live => return BuildOutcome::SnapshotIgnored,
+            Some(s) => (s.channel, s.required_seq),
+            None => {
+                // First sight of the symbol is a snapshot: start from it.
+                self.track_symbol(msg);
+                self.set_state(symbol, BookState::Stale);
+                (msg.channel, 0)
+            }
+        };
+        if last_seq < required_seq {
+            return BuildOutcome::SnapshotIgnored;
+        }
+
+        // The feed snapshot carries orders for L3 books and levels for L2 books, so it is
+        // restored in the book's own mode.
+        let restored = self.book.restore(&BookSnapshot {
+            symbol: symbol.clone(),
+            mode: self.book.mode(),
+            bids: bids.clone(),
+            asks: asks.clone(),
+            orders: orders
+                .iter()
+                .map(|o| RestingOrder {
+                    order_id: o.order_id,
+                    side: o.side,
+                    price: o.price,
+                    quantity: o.size,
+                })
+                .collect(),
+        });
+        if let Err(error) = restored {
+            return BuildOutcome::SnapshotRejected { error };
+        }
+
+        // Replay buffered updates for this symbol that the snapshot does not include.
+        let buffered = match self.channels.get_mut(&channel) {
+            Some(ch) => {
+                let (mine, others) = std::mem::take(&mut ch.buffer)
+                    .into_iter()
+                    .partition(|m| m.message.symbol() == symbol);
+                ch.buffer = others;
+                mine
+            }
+            None => Vec::new(),
+        };
+        let mut replayed = 0;
+        let mut consistent = true;
+        for m in buffered.iter().filter(|m| m.seq > last_seq) {
+            consistent = self.apply(&m.message);
+            replayed += 1;
+            if !consistent {
+                break;
+            }
+        }
+        // The buffered updates are gone either way, so a later snapshot must include them.
+        let consumed_seq = buffered.iter().map(|m| m.seq).max().unwrap_or(0);
+
+        let next_seq = self.channels.get(&channel).and_then(|ch| ch.next_seq);
+        let state = if !consistent {
+            BookState::Stale
+        } else if next_seq.is_none_or(|next| last_seq >= next) {
+            BookState::Recovering
+        } else {
+            BookState::Live
+        };
+        if let Some(s) = self.symbols.get_mut(symbol.as_str()) {
+            s.applied_seq = last_seq;
+            if !consistent {
+                s.required_seq = s.required_seq.max(consumed_seq);
+            }
+        }
+        self.set_state(symbol, state);
+        BuildOutcome::SnapshotApplied { replayed }
+    }
+
+    /// Applies an incremental update; returns `false` if it does not match the book.
+    fn apply(&mut self, message: &MarketMessage) -> bool {
+        match message {
+            MarketMessage::Quote {
+                symbol,
+                side,
+                price,
+                size,
+                ..
+            } => {
+                // An L3 book ignores quotes: its levels follow its orders.
+                let _ = self.book.apply_quote(symbol, *side, *price, *size);
+                true
+            }
+            MarketMessage::OrderUpdate {
+                symbol,
+                order_id,
+                action,
+                side,
+                price,
+                size,
+                ..
+            } => {
+                if self.book.mode() != BookMode::MarketByOrder {
+                    return true;
+                }
+                let result = match action {
+                    OrderAction::Add => {
+                        self.book.add_order(symbol, *order_id, *side, *price, *size)
+                    }
+                    OrderAction::Modify => self.book.modify_order(symbol, *order_id, *price, *size),
+                    OrderAction::Delete => self.book.delete_order(symbol, *order_id).map(|_| ()),
+                    OrderAction::Execute => self
+                        .book
+                        .execute_order(symbol, *order_id, *size)
+                        .map(|_| ()),
+                };
+                result.is_ok()
+            }
+            MarketMessage::Trade { .. } | MarketMessage::Snapshot { .. } => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::price::Price;
+    use common::side::Side;
+    use reception_layer::message_types::SnapshotOrder;
+
+    fn quote(seq: u64, symbol: &str, side: Side, ticks: i64, size: u64) -> SequencedMessage {
+        SequencedMessage {
+            channel: 1,
+            seq,
+            message: MarketMessage::Quote {
+                symbol: symbol.to_string(),
+                side,
+                price: Price::from_ticks(ticks),
+                size,
+                timestamp: 0,
+            },
+        }
+    }
+
+    fn snapshot(last_seq: u64, symbol: &str, bids: &[(i64, u64)]) -> SequencedMessage {
+        SequencedMessage {
+            channel: 1,
+            seq: 0,
+            message: MarketMessage::Snapshot {
+                symbol: symbol.to_string(),
+                last_seq,
+                bids: bids
+                    .iter()
+                    .map(|&(t, s)| (Price::from_ticks(t), s))
+                    .collect(),
+                asks: Vec::new(),
+                orders: Vec::new(),
+                timestamp: 0,
+            },
+        }
+    }
+
+    fn best_bid(builder: &BookBuilder, symbol: &str) -> Option<(i64, u64)> {
+        builder
+            .book()
+            .best_bid_ask(symbol)
+            .0
+            .map(|(p, s)| (p.ticks(), s))
+    }
+
+    fn order(seq: u64, action: OrderAction, order_id: u64, size: u64) -> SequencedMessage {
+        SequencedMessage {
+            channel: 1,
+            seq,
+            message: MarketMessage::OrderUpdate {
+                symbol: "ES".to_string(),
+                order_id,
+                action,
+                side: Side::Bid,
+                price: Price::from_ticks(100),
+                size,
+                timestamp: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_in_sequence_updates_are_applied() {
+        let mut builder = BookBuilder::new(OrderBook::new());
+        assert_eq!(
+            builder.on_message(&quote(1, "ES", Side::Bid, 100, 5)),
+            BuildOutcome::Applied
+        );
+        assert_eq!(
+            builder.on_message(&quote(2, "ES", Side::Bid, 101, 5)),
+            BuildOutcome::Applied
+        );
+        assert_eq!(
+            builder.on_message(&quote(2, "ES", Side::Bid, 101, 9)),
+            BuildOutcome::Duplicate
+        );
+        assert_eq!(builder.state("ES"), BookState::Live);
+        assert_eq!(best_bid(&builder, "ES"), Some((101, 5)));
+    }
+
+    #[test]
+    fn test_gap_buffers_until_snapshot_then_replays() {
+        let mut builder = BookBuilder::new(OrderBook::new());
+        builder.on_message(&quote(1, "ES", Side::Bid, 100, 5));
+        builder.on_message(&quote(2, "NQ", Side::Bid, 500, 1));
+
+        // Seq 3 and 4 are lost.
+        assert_eq!(
+            builder.on_message(&quote(5, "ES", Side::Bid, 102, 7)),
+            BuildOutcome::Gap {
+                channel: 1,
+                expected: 3,
+                received: 5
+            }
+        );
+        assert_eq!(builder.state("ES"), BookState::Stale);
+        assert_eq!(builder.state("NQ"), BookState::Stale);
+        assert_eq!(
+            builder.on_message(&quote(6, "ES", Side::Bid, 103, 1)),
+            BuildOutcome::Buffered
+        );
+        assert_eq!(best_bid(&builder, "ES"), Some((100, 5)));
+
+        // A snapshot older than the gap cannot repair the book.
+        assert_eq!(
+            builder.on_message(&snapshot(3, "ES", &[(101, 2)])),
+            BuildOutcome::SnapshotIgnored
+        );
+
+        // Snapshot as of seq 5: replay only seq 6.
+        assert_eq!(
+            builder.on_message(&snapshot(5, "ES", &[(102, 7), (101, 2)])),
+            BuildOutcome::SnapshotApplied { replayed: 1 }
+        );
+        assert_eq!(builder.state("ES"), BookState::Live);
+        assert_eq!(best_bid(&builder, "ES"), Some((103, 1)));
+        assert_eq!(
+            builder
+                .book()
+                .level_size("ES", Side::Bid, Price::from_ticks(101)),
+            Some(2)
+        );
+        assert_eq!(builder.state("NQ"), BookState::Stale);
+        assert_eq!(builder.stale_symbols().collect::<Vec<_>>(), vec!["NQ"]);
+
+        assert_eq!(
+            builder.on_message(&quote(7, "ES", Side::Bid, 103, 0)),
+            BuildOutcome::Applied
+        );
+    }
+
+    #[test]
+    fn test_snapshot_ahead_of_stream_is_recovering() {
+        let mut builder = BookBuilder::new(OrderBook::new());
+        builder.on_message(&quote(1, "ES", Side::Bid, 100, 5));
+        builder.on_message(&quote(3, "ES", Side::Bid, 100, 6));
+
+        // The snapshot already includes seq 4.
+        builder.on_message(&snapshot(4, "ES", &[(100, 8)]));
+        assert_eq!(builder.state("ES"), BookState::Recovering);
+        assert_eq!(
+            builder.on_message(&quote(4, "ES", Side::Bid, 100, 8)),
+            BuildOutcome::Skipped
+        );
+        assert_eq!(
+            builder.on_message(&quote(5, "ES", Side::Bid, 100, 9)),
+            BuildOutcome::Applied
+        );
+        assert_eq!(builder.state("ES"), BookState::Live);
+        assert_eq!(best_bid(&builder, "ES"), Some((100, 9)));
+    }
+
+    #[test]
+    fn test_inconsistent_replay_requires_newer_snapshot() {
+        let mut builder = BookBuilder::new(OrderBook::market_by_order());
+        builder.on_message(&order(1, OrderAction::Add, 1, 5));
+        builder.on_message(&order(3, OrderAction::Add, 2, 5));
+        builder.on_message(&order(4, OrderAction::Delete, 9, 0));
+        builder.on_message(&order(5, OrderAction::Add, 3, 5));
+
+        // Order 9 is not in the snapshot, so replaying seq 4 fails and seq 5 is dropped.
+        assert_eq!(
+            builder.on_message(&snapshot(2, "ES", &[])),
+            BuildOutcome::SnapshotApplied { replayed: 2 }
+        );
+        assert_eq!(builder.state("ES"), BookState::Stale);
+        assert_eq!(builder.book().order("ES", 3), None);
+
+        assert_eq!(
+            builder.on_message(&snapshot(4, "ES", &[])),
+            BuildOutcome::SnapshotIgnored
+        );
+        assert_eq!(
+            builder.on_message(&snapshot(5, "ES", &[])),
+            BuildOutcome::SnapshotApplied { replayed: 0 }
+        );
+        assert_eq!(builder.state("ES"), BookState::Live);
+    }
+
+    #[test]
+    fn test_buffer_overflow_asks_for_resubscribe() {
+        let mut builder = BookBuilder::new(OrderBook::new()).with_max_buffered(2);
+        builder.on_message(&quote(1, "ES", Side::Bid, 100, 5));
+        builder.on_message(&quote(3, "ES", Side::Bid, 101, 5));
+        assert_eq!(
+            builder.on_message(&quote(4, "ES", Side::Bid, 102, 5)),
+            BuildOutcome::Buffered
+        );
+        assert_eq!(
+            builder.on_message(&quote(5, "ES", Side::Bid, 103, 5)),
+            BuildOutcome::BufferOverflow { channel: 1 }
+        );
+
+        // The dropped updates can only be recovered from a snapshot that includes them.
+        assert_eq!(
+            builder.on_message(&snapshot(4, "ES", &[(102, 5)])),
+            BuildOutcome::SnapshotIgnored
+        );
+        assert_eq!(
+            builder.on_message(&snapshot(5, "ES", &[(103, 5)])),
+            BuildOutcome::SnapshotApplied { replayed: 0 }
+        );
+        assert_eq!(best_bid(&builder, "ES"), Some((103, 5)));
+    }
+
+    #[test]
+    fn test_inconsistent_snapshot_is_rejected() {
+        let mut builder = BookBuilder::new(OrderBook::market_by_order());
+        builder.on_message(&order(1, OrderAction::Add, 1, 5));
+        builder.on_message(&order(3, OrderAction::Add, 2, 5));
+        assert_eq!(builder.state("ES"), BookState::Stale);
+
+        let resting = |order_id| SnapshotOrder {
+            order_id,
+            side: Side::Bid,
+            price: Price::from_ticks(100),
+            size: 5,
+        };
+        let mut bad = snapshot(2, "ES", &[]);
+        if let MarketMessage::Snapshot { orders, .. } = &mut bad.message {
+            *orders = vec![resting(1), resting(1)];
+        }
+        assert_eq!(
+            builder.on_message(&bad),
+            BuildOutcome::SnapshotRejected {
+                error: SnapshotError::DuplicateOrder { order_id: 1 }
+            }
+        );
+        assert_eq!(builder.state("ES"), BookState::Stale);
+        assert_eq!(builder.book().order_count("ES"), 1);
+
+        // The buffered update is still there for the next good snapshot.
+        let mut good = snapshot(2, "ES", &[]);
+        if let MarketMessage::Snapshot { orders, .. } = &mut good.message {
+            *orders = vec![resting(1), resting(4)];
+        }
+        assert_eq!(
+            builder.on_message(&good),
+            BuildOutcome::SnapshotApplied { replayed: 1 }
+        );
+        assert_eq!(builder.book().order_count("ES"), 3);
+    }
+}
@@ -0,0 +1,185 @@
+//! book_events.rs
+//! Compact change notifications emitted by the `OrderBook` on every mutation.
+//!
+//! # Usage
+//! - Enable recording with `OrderBook::record_events(true)`.
+//! - After applying a message, call `OrderBook::publish_events(symbol, ..)` to hand the
+//!   events to subscribers (`BookListener`), or `drain_events` to consume them directly.
+//! - Subscribers such as the `Aggregator` react only to the changes they care about
+//!   (typically `TopOfBook`) instead of polling `best_bid_ask`.
+//! - `encode` gives a fixed 34-byte record for the journal; `decode` reads it back.
+
+use common::price::Price;
+
+use super::order_book::{Level, Side};
+
+/// A single change to a symbol's book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookEvent {
+    /// A new price level appeared.
+    LevelAdded { side: Side, price: Price, size: u64 },
+    /// An existing level's size changed.
+    LevelChanged { side: Side, price: Price, size: u64 },
+    /// A level disappeared.
+    LevelRemoved { side: Side, price: Price },
+    /// The best bid and/or ask changed (price or size); carries the new top of book.
+    TopOfBook {
+        bid: Option<Level>,
+        ask: Option<Level>,
+    },
+    /// The whole book was cleared or replaced (e.g. restored from a snapshot).
+    Reset,
+}
+
+/// Receives book events as they are published.
+pub trait BookListener {
+    fn on_book_event(&mut self, symbol: &str, event: &BookEvent);
+}
+
+/// Collects published events, e.g. for tests or batching.
+impl BookListener for Vec<BookEvent> {
+    fn on_book_event(&mut self, _symbol: &str, event: &BookEvent) {
+        self.push(*event);
+    }
+}
+
+const TAG_ADDED: u8 = 1;
+const TAG_CHANGED: u8 = 2;
+const TAG_REMOVED: u8 = 3;
+const TAG_TOP: u8 = 4;
+const TAG_RESET: u8 = 5;
+
+/// Size in bytes of an encoded event.
+pub const ENCODED_EVENT_BYTES: usize = 34;
+
+/// Errors returned when decoding an event record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventDecodeError {
+    /// The buffer is shorter than `ENCODED_EVENT_BYTES`.
+    Truncated,
+    /// The tag or side byte held an unknown value.
+    InvalidField,
+}
+
+impl BookEvent {
+    /// Appends a fixed-size little-endian record for journaling:
+    /// `u8 tag`, `u8 side`, then two `(i64 price_ticks, u64 size)` pairs (unused fields are 0).
+    /// For `TopOfBook` the pairs are the bid and the ask; a missing side is encoded as size 0.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let (tag, side, first, second) = match *self {
+            BookEvent::LevelAdded { side, price, size } => {
+                (TAG_ADDED, side, (price, size), (Price::ZERO, 0))
+            }
+            BookEvent::LevelChanged { side, price, size } => {
+                (TAG_CHANGED, side, (price, size), (Price::ZERO, 0))
+            }
+            BookEvent::LevelRemoved { side, price } => {
+                (TAG_REMOVED, side, (price, 0), (Price::ZERO, 0))
+            }
+            BookEvent::TopOfBook { bid, ask } => (
+                TAG_TOP,
+                Side::Bid,
+                bid.unwrap_or((Price::ZERO, 0)),
+                ask.unwrap_or((Price::ZERO, 0)),
+            ),
+            BookEvent::Reset => (TAG_RESET, Side::Bid, (Price::ZERO, 0), (Price::ZERO, 0)),
+        };
+        out.push(tag);
+        out.push(match side {
+            Side::Bid => 0,
+            Side::Ask => 1,
+        });
+        for (price, size) in [first, second] {
+            out.extend_from_slice(&price.ticks().to_le_bytes());
+            out.extend_from_slice(&size.to_le_bytes());
+        }
+    }
+
+    /// Reads a record written by `encode` from the start of `data`.
+    pub fn decode(data: &[u8]) -> Result<Self, EventDecodeError> {
+        let record = data
+            .get(..ENCODED_EVENT_BYTES)
+            .ok_or(EventDecodeError::Truncated)?;
+        let side = match record[1] {
+            0 => Side::Bid,
+            1 => Side::Ask,
+            _ => return Err(EventDecodeError::InvalidField),
+        };
+        let pair = |at: usize| -> Level {
+            let word = |at: usize| record[at..at + 8].try_into().expect("8-byte field");
+            (
+                Price::from_ticks(i64::from_le_bytes(word(at))),
+                u64::from_le_bytes(word(at + 8)),
+            )
+        };
+        let ((price, size), second) = (pair(2), pair(18));
+        let present = |level: Level| (level.1 > 0).then_some(level);
+        Ok(match record[0] {
+            TAG_ADDED => BookEvent::LevelAdded { side, price, size },
+            TAG_CHANGED => BookEvent::LevelChanged { side, price, size },
+            TAG_REMOVED => BookEvent::LevelRemoved { side, price },
+            TAG_TOP => BookEvent::TopOfBook {
+                bid: present((price, size)),
+                ask: present(second),
+            },
+            TAG_RESET => BookEvent::Reset,
+            _ => return Err(EventDecodeError::InvalidField),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(ticks: i64) -> Price {
+        Price::from_ticks(ticks)
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let events = [
+            BookEvent::LevelAdded {
+                side: Side::Bid,
+                price: px(-5),
+                size: 7,
+            },
+            BookEvent::LevelChanged {
+                side: Side::Ask,
+                price: px(10100),
+                size: u64::MAX,
+            },
+            BookEvent::LevelRemoved {
+                side: Side::Ask,
+                price: px(10125),
+            },
+            BookEvent::TopOfBook {
+                bid: Some((px(10000), 3)),
+                ask: None,
+            },
+            BookEvent::TopOfBook {
+                bid: None,
+                ask: Some((px(10025), 1)),
+            },
+            BookEvent::Reset,
+        ];
+        let mut bytes = Vec::new();
+        for event in &events {
+            event.encode(&mut bytes);
+        }
+        assert_eq!(bytes.len(), events.len() * ENCODED_EVENT_BYTES);
+        let decoded: Vec<_> = bytes
+            .chunks(ENCODED_EVENT_BYTES)
+            .map(|record| BookEvent::decode(record).unwrap())
+            .collect();
+        assert_eq!(decoded, events);
+
+        assert_eq!(
+            BookEvent::decode(&bytes[..ENCODED_EVENT_BYTES - 1]),
+            Err(EventDecodeError::Truncated)
+        );
+        let mut bad = bytes[..ENCODED_EVENT_BYTES].to_vec();
+        bad[0] = 0;
+        assert_eq!(BookEvent::decode(&bad), Err(EventDecodeError::InvalidField));
+    }
+}
@@ -0,0 +1,185 @@
+//! book_snapshot.rs
+//! Point-in-time copy of one symbol's order book, with a compact binary encoding so it can be
+//! written to the journal or sent to another process and restored later.
+//!
+//! # Format
+//! All integers are little-endian:
+//! - `u32` magic `OBS1`, `u8` mode (0 = L2, 1 = L3)
+//! - `u32` symbol length followed by the UTF-8 symbol
+//! - `u32` bid count, `u32` ask count, `u32` order count
+//! - bids then asks as `(i64 price_ticks, u64 size)`, best first
+//! - orders as `(u64 order_id, u8 side, i64 price_ticks, u64 quantity)` in queue order
+
+use common::byte_utils::{le_to_u32, le_to_u64};
+use common::price::Price;
+
+use super::order_book::{BookMode, Level, RestingOrder, Side};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"OBS1");
+const LEVEL_BYTES: usize = 16;
+const ORDER_BYTES: usize = 25;
+
+/// Full copy of one symbol's book.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookSnapshot {
+    pub symbol: String,
+    pub mode: BookMode,
+    /// Bid levels, best (highest) first.
+    pub bids: Vec<Level>,
+    /// Ask levels, best (lowest) first.
+    pub asks: Vec<Level>,
+    /// Resting orders (L3 only), grouped by level and in FIFO order within each level.
+    pub orders: Vec<RestingOrder>,
+}
+
+/// Errors returned when decoding or restoring a snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The buffer does not start with the snapshot magic number.
+    BadMagic,
+    /// The buffer ended before the snapshot was complete.
+    Truncated,
+    /// An enum field held an unknown value.
+    InvalidField,
+    /// The symbol is not valid UTF-8.
+    InvalidSymbol,
+    /// A `MarketByPrice` snapshot has no orders to rebuild a `MarketByOrder` book from.
+    ModeMismatch,
+    /// The same order id appears twice.
+    DuplicateOrder { order_id: u64 },
+    /// An order has zero quantity.
+    InvalidOrder { order_id: u64 },
+    /// The orders at this price do not add up to the snapshot's level there.
+    LevelMismatch { side: Side, price: Price },
+}
+
+impl BookSnapshot {
+    /// Appends the binary encoding of the snapshot to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.reserve(
+            21 + self.symbol.len()
+                + (self.bids.len() + self.asks.len()) * LEVEL_BYTES
+                + self.orders.len() * ORDER_BYTES,
+        );
+        out.extend_from_slice(&MAGIC.to_le_bytes());
+        out.push(match self.mode {
+            BookMode::MarketByPrice => 0,
+            BookMode::MarketByOrder => 1,
+        });
+        out.extend_from_slice(&(self.symbol.len() as u32).to_le_bytes());
+        out.extend_from_slice(self.symbol.as_bytes());
+        out.extend_from_slice(&(self.bids.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(self.asks.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(self.orders.len() as u32).to_le_bytes());
+        for (price, size) in self.bids.iter().chain(self.asks.iter()) {
+            out.extend_from_slice(&price.ticks().to_le_bytes());
+            out.extend_from_slice(&size.to_le_bytes());
+        }
+        for order in &self.orders {
+            out.extend_from_slice(&order.order_id.to_le_bytes());
+            out.push(side_to_u8(order.side));
+            out.extend_from_slice(&order.price.ticks().to_le_bytes());
+            out.extend_from_slice(&order.quantity.to_le_bytes());
+        }
+    }
+
+    /// Decodes a snapshot produced by `encode`.
+    pub fn decode(data: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = Reader { data, pos: 0 };
+        if reader.u32()? != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let mode = match reader.u8()? {
+            0 => BookMode::MarketByPrice,
+            1 => BookMode::MarketByOrder,
+            _ => return Err(SnapshotError::InvalidField),
+        };
+        let symbol_len = reader.u32()? as usize;
+        let symbol = std::str::from_utf8(reader.take(symbol_len)?)
+            .map_err(|_| SnapshotError::InvalidSymbol)?
+            .to_string();
+        let bid_count = reader.u32()? as usize;
+        let ask_count = reader.u32()? as usize;
+        let order_count = reader.u32()? as usize;
+
+        // Validate the remaining length up front so corrupt counts cannot trigger huge allocations.
+        let needed = (bid_count + ask_count) * LEVEL_BYTES + order_count * ORDER_BYTES;
+        if data.len() - reader.pos < needed {
+            return Err(SnapshotError::Truncated);
+        }
+
+        let mut read_levels = |count: usize| -> Result<Vec<Level>, SnapshotError> {
+            let mut levels = Vec::with_capacity(count);
+            for _ in 0..count {
+                let price = Price::from_ticks(reader.u64()? as i64);
+                levels.push((price, reader.u64()?));
+            }
+            Ok(levels)
+        };
+        let bids = read_levels(bid_count)?;
+        let asks = read_levels(ask_count)?;
+
+        let mut orders = Vec::with_capacity(order_count);
+        for _ in 0..order_count {
+            let order_id = reader.u64()?;
+            let side = match reader.u8()? {
+                0 => Side::Bid,
+                1 => Side::Ask,
+                _ => return Err(SnapshotError::InvalidField),
+            };
+            let price = Price::from_ticks(reader.u64()? as i64);
+            let quantity = reader.u64()?;
+            orders.push(RestingOrder {
+                order_id,
+                side,
+                price,
+                quantity,
+            });
+        }
+
+        Ok(BookSnapshot {
+            symbol,
+            mode,
+            bids,
+            asks,
+            orders,
+        })
+    }
+}
+
+fn side_to_u8(side: Side) -> u8 {
+    match side {
+        Side::Bid => 0,
+        Side::Ask => 1,
+    }
+}
+
+/// Bounds-checked cursor over the encoded bytes.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        let end = self.pos.checked_add(len).ok_or(SnapshotError::Truncated)?;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or(SnapshotError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(le_to_u32(self.take(4)?))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(le_to_u64(self.take(8)?))
+    }
+}
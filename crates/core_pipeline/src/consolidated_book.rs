@@ -0,0 +1,260 @@
+//! consolidated_book.rs
+//! Merges per-venue order books for the same instruments into a consolidated view.
+//!
+//! # Key Concepts
+//! - Each venue (exchange endpoint) keeps its own `OrderBook`; venues are identified by their
+//!   index, e.g. the position of the endpoint in `Config::exchange_endpoints`.
+//! - `best_bid_ask` reports the single best level across venues with the venue attached.
+//! - `nbbo` reports the national-best-bid-and-offer style view: best price per side, the size
+//!   summed over every venue quoting that price, and which venues are at the touch.
+//! - `market_condition` flags locked (bid == ask) and crossed (bid > ask) markets.
+//!
+//! # Performance
+//! - Queries are O(venues) best-level lookups and never allocate.
+
+use common::price::Price;
+
+use super::order_book::{OrderBook, OrderError, Side};
+use super::price_levels::{PriceLevels, TreeLevels};
+
+/// Index of a venue in the consolidated book.
+pub type VenueId = u16;
+
+/// Maximum number of venues, bounded by the width of `NbboSide::venues`.
+pub const MAX_VENUES: usize = 64;
+
+/// A top-of-book level attributed to the venue quoting it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VenueQuote {
+    pub venue: VenueId,
+    pub price: Price,
+    pub size: u64,
+}
+
+/// One side of the NBBO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NbboSide {
+    pub price: Price,
+    /// Size at `price` summed across venues.
+    pub size: u64,
+    /// Bit `v` is set when venue `v` is quoting `price`.
+    pub venues: u64,
+}
+
+impl NbboSide {
+    /// Iterates over the venues quoting at the NBBO price.
+    pub fn venue_ids(&self) -> impl Iterator<Item = VenueId> {
+        let bits = self.venues;
+        (0..MAX_VENUES as VenueId).filter(move |v| bits & (1u64 << v) != 0)
+    }
+}
+
+/// Consolidated best bid and offer across venues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nbbo {
+    pub bid: Option<NbboSide>,
+    pub ask: Option<NbboSide>,
+}
+
+/// Relationship between the best bid and best ask across venues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarketCondition {
+    /// Best bid below best ask, or one side empty.
+    Normal,
+    /// Best bid equals best ask.
+    Locked { bid: VenueQuote, ask: VenueQuote },
+    /// Best bid above best ask.
+    Crossed { bid: VenueQuote, ask: VenueQuote },
+}
+
+/// Per-venue books for the same instruments.
+#[derive(Debug)]
+pub struct ConsolidatedBook<L = TreeLevels> {
+    venues: Vec<OrderBook<L>>,
+}
+
+impl ConsolidatedBook {
+    /// Creates `venue_count` empty price-level books with the tree backend.
+    pub fn new(venue_count: usize) -> Self {
+        ConsolidatedBook::with_books((0..venue_count).map(|_| OrderBook::new()).collect())
+    }
+}
+
+impl<L: PriceLevels> ConsolidatedBook<L> {
+    /// Wraps existing books; venue `i` is `books[i]`. Panics above `MAX_VENUES` venues.
+    pub fn with_books(books: Vec<OrderBook<L>>) -> Self {
+        assert!(books.len() <= MAX_VENUES, "too many venues");
+        ConsolidatedBook { venues: books }
+    }
+
+    pub fn venue_count(&self) -> usize {
+        self.venues.len()
+    }
+
+    pub fn venue(&self, venue: VenueId) -> &OrderBook<L> {
+        &self.venues[venue as usize]
+    }
+
+    pub fn venue_mut(&mut self, venue: VenueId) -> &mut OrderBook<L> {
+        &mut self.venues[venue as usize]
+    }
+
+    /// Applies a quote to one venue's book; fails like `OrderBook::apply_quote`.
+    pub fn apply_quote(
+        &mut self,
+        venue: VenueId,
+        symbol: &str,
+        side: Side,
+        price: Price,
+        size: u64,
+    ) -> Result<(), OrderError> {
+        self.venues[venue as usize].apply_quote(symbol, side, price, size)
+    }
+
+    /// Best level on `side` across venues. Ties on price go to the venue with the most size,
+    /// then the lowest venue id.
+    pub fn best(&self, symbol: &str, side: Side) -> Option<VenueQuote> {
+        let mut best: Option<VenueQuote> = None;
+        for (venue, book) in self.venues.iter().enumerate() {
+            let (bid, ask) = book.best_bid_ask(symbol);
+            let Some((price, size)) = (match side {
+                Side::Bid => bid,
+                Side::Ask => ask,
+            }) else {
+                continue;
+            };
+            let better = match best {
+                None => true,
+                Some(b) => {
+                    let price_better = match side {
+                        Side::Bid => price > b.price,
+                        Side::Ask => price < b.price,
+                    };
+                    price_better || (price == b.price && size > b.size)
+                }
+            };
+            if better {
+                best = Some(VenueQuote {
+                    venue: venue as VenueId,
+                    price,
+                    size,
+                });
+            }
+        }
+        best
+    }
+
+    /// Best bid and ask across venues, each attributed to its venue.
+    pub fn best_bid_ask(&self, symbol: &str) -> (Option<VenueQuote>, Option<VenueQuote>) {
+        (self.best(symbol, Side::Bid), self.best(symbol, Side::Ask))
+    }
+
+    /// NBBO-style view: best price per side with size aggregated over venues at that price.
+    pub fn nbbo(&self, symbol: &str) -> Nbbo {
+        Nbbo {
+            bid: self.nbbo_side(symbol, Side::Bid),
+            ask: self.nbbo_side(symbol, Side::Ask),
+        }
+    }
+
+    fn nbbo_side(&self, symbol: &str, side: Side) -> Option<NbboSide> {
+        let best = self.best(symbol, side)?;
+        let mut nbbo = NbboSide {
+            price: best.price,
+            size: 0,
+            venues: 0,
+        };
+        for (venue, book) in self.venues.iter().enumerate() {
+            if let Some(size) = book.level_size(symbol, side, best.price) {
+                nbbo.size += size;
+                nbbo.venues |= 1u64 << venue;
+            }
+        }
+        Some(nbbo)
+    }
+
+    /// Detects locked and crossed markets across venues.
+    pub fn market_condition(&self, symbol: &str) -> MarketCondition {
+        match self.best_bid_ask(symbol) {
+            (Some(bid), Some(ask)) if bid.price == ask.price => {
+                MarketCondition::Locked { bid, ask }
+            }
+            (Some(bid), Some(ask)) if bid.price > ask.price => {
+                MarketCondition::Crossed { bid, ask }
+            }
+            _ => MarketCondition::Normal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(ticks: i64) -> Price {
+        Price::from_ticks(ticks)
+    }
+
+    #[test]
+    fn test_best_across_venues_with_attribution() {
+        let mut book = ConsolidatedBook::new(3);
+        book.apply_quote(0, "ES", Side::Bid, px(100), 5).unwrap();
+        book.apply_quote(1, "ES", Side::Bid, px(101), 2).unwrap();
+        book.apply_quote(2, "ES", Side::Bid, px(101), 4).unwrap();
+        book.apply_quote(0, "ES", Side::Ask, px(103), 1).unwrap();
+        book.apply_quote(2, "ES", Side::Ask, px(102), 3).unwrap();
+
+        let (bid, ask) = book.best_bid_ask("ES");
+        assert_eq!(
+            bid,
+            Some(VenueQuote {
+                venue: 2,
+                price: px(101),
+                size: 4
+            })
+        );
+        assert_eq!(
+            ask,
+            Some(VenueQuote {
+                venue: 2,
+                price: px(102),
+                size: 3
+            })
+        );
+
+        let nbbo = book.nbbo("ES");
+        let nbbo_bid = nbbo.bid.unwrap();
+        assert_eq!((nbbo_bid.price, nbbo_bid.size), (px(101), 6));
+        assert_eq!(nbbo_bid.venue_ids().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(nbbo.ask.unwrap().size, 3);
+        assert_eq!(book.market_condition("ES"), MarketCondition::Normal);
+    }
+
+    #[test]
+    fn test_locked_and_crossed() {
+        let mut book = ConsolidatedBook::new(2);
+        book.apply_quote(0, "ES", Side::Bid, px(101), 1).unwrap();
+        book.apply_quote(1, "ES", Side::Ask, px(101), 1).unwrap();
+        assert!(matches!(
+            book.market_condition("ES"),
+            MarketCondition::Locked { .. }
+        ));
+
+        book.apply_quote(1, "ES", Side::Ask, px(101), 0).unwrap();
+        book.apply_quote(1, "ES", Side::Ask, px(100), 2).unwrap();
+        match book.market_condition("ES") {
+            MarketCondition::Crossed { bid, ask } => {
+                assert_eq!((bid.venue, ask.venue), (0, 1));
+                assert_eq!((bid.price, ask.price), (px(101), px(100)));
+            }
+            other => panic!("expected crossed market, got {:?}", other),
+        }
+        assert_eq!(
+            book.nbbo("NQ"),
+            Nbbo {
+                bid: None,
+                ask: None
+            }
+        );
+    }
+}
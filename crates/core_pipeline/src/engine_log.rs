@@ -0,0 +1,1060 @@
+//! engine_log.rs
+//! Sequenced record of every command given to a `MatchEngine` and every event it produced,
+//! used to prove the engine is deterministic.
+//!
+//! # Usage
+//! - Enable recording with `MatchEngine::record_log(true)` and collect it with `take_log`.
+//!   Each command is followed by the events it produced, all numbered in one sequence. The
+//!   log opens with a `LoadBook` per symbol already in the books, and quotes or orders added
+//!   through the engine without matching are logged as commands, so a replay starts from and
+//!   sees the same books.
+//! - `replay` feeds the recorded commands into a fresh engine and returns the new log;
+//!   `diff` compares two logs record by record on their encoded bytes. A deterministic engine
+//!   reproduces its own log exactly.
+//! - The `verify_engine_log` binary runs both steps on logs written with `encode`.
+//!
+//! # Format
+//! All integers are little-endian:
+//! - `u32` magic `MEL1`, `u8` book mode (0 = L2, 1 = L3), `u64` record count
+//! - each record: `u64 seq`, `u8 kind` (0 = command, 1 = event), `u8 tag`, then the fields in
+//!   declaration order. Strings are `u32 len` + UTF-8, options a `u8` flag then the value,
+//!   prices `i64` ticks. A book snapshot is a `u32 len` followed by its `BookSnapshot`
+//!   encoding.
+
+use common::byte_utils::{le_to_u32, le_to_u64};
+use common::price::Price;
+
+use super::auction::Equilibrium;
+use super::book_snapshot::{BookSnapshot, SnapshotError};
+use super::match_engine::{
+    EngineEvent, Fill, MatchEngine, RejectReason, SelfTradePrevention, TradingPhase,
+};
+use super::order_book::{BookMode, OrderBook, Side};
+use super::order_types::{Order, OrderFlags, TimeInForce};
+use super::price_levels::{PriceLevels, TreeLevels};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"MEL1");
+
+/// An input to the engine, mirroring its public methods.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineCommand {
+    Submit(Order),
+    Cancel {
+        symbol: String,
+        order_id: u64,
+    },
+    Amend {
+        symbol: String,
+        order_id: u64,
+        quantity: u64,
+    },
+    Replace {
+        symbol: String,
+        order_id: u64,
+        price: Price,
+        quantity: u64,
+    },
+    MatchOrder {
+        symbol: String,
+        side: Side,
+        quantity: u64,
+        limit: Option<Price>,
+    },
+    /// A trade from the market data feed (`on_trade`).
+    Trade {
+        symbol: String,
+        price: Price,
+    },
+    Uncross {
+        symbol: String,
+    },
+    EndOfDay,
+    SetPhase(TradingPhase),
+    SetPosition {
+        symbol: String,
+        position: i64,
+    },
+    SetReferencePrice {
+        symbol: String,
+        price: Price,
+    },
+    SetSelfTradePrevention(Option<SelfTradePrevention>),
+    /// Replaces one symbol's visible or hidden book (`load_book`).
+    LoadBook {
+        hidden: bool,
+        snapshot: BookSnapshot,
+    },
+    /// A market data quote applied to an L2 book (`apply_quote`).
+    Quote {
+        symbol: String,
+        side: Side,
+        price: Price,
+        size: u64,
+    },
+    /// An order added to an L3 book without matching (`add_order`).
+    AddOrder {
+        symbol: String,
+        order_id: u64,
+        side: Side,
+        price: Price,
+        quantity: u64,
+    },
+}
+
+impl EngineCommand {
+    /// Applies the command to `engine` and returns the events it produced.
+    pub fn apply<'e, L: PriceLevels>(
+        &self,
+        engine: &'e mut MatchEngine<'_, L>,
+    ) -> &'e [EngineEvent] {
+        match self {
+            EngineCommand::Submit(order) => engine.submit(order),
+            EngineCommand::Cancel { symbol, order_id } => engine.cancel(symbol, *order_id),
+            EngineCommand::Amend {
+                symbol,
+                order_id,
+                quantity,
+            } => engine.amend(symbol, *order_id, *quantity),
+            EngineCommand::Replace {
+                symbol,
+                order_id,
+                price,
+                quantity,
+            } => engine.replace(symbol, *order_id, *price, *quantity),
+            EngineCommand::MatchOrder {
+                symbol,
+                side,
+                quantity,
+                limit,
+            } => {
+                engine.match_order(symbol, *side, *quantity, *limit);
+                engine.events()
+            }
+            EngineCommand::Trade { symbol, price } => engine.on_trade(symbol, *price),
+            EngineCommand::Uncross { symbol } => engine.uncross(symbol),
+            EngineCommand::EndOfDay => engine.end_of_day(),
+            EngineCommand::SetPhase(phase) => {
+                engine.set_phase(*phase);
+                engine.events()
+            }
+            EngineCommand::SetPosition { symbol, position } => {
+                engine.set_position(symbol, *position);
+                engine.events()
+            }
+            EngineCommand::SetReferencePrice { symbol, price } => {
+                engine.set_reference_price(symbol, *price);
+                engine.events()
+            }
+            EngineCommand::SetSelfTradePrevention(mode) => {
+                engine.set_self_trade_prevention(*mode);
+                engine.events()
+            }
+            // A load or add that failed when recorded fails the same way here, leaving the
+            // book unchanged both times.
+            EngineCommand::LoadBook { hidden, snapshot } => {
+                let _ = engine.load_book(snapshot, *hidden);
+                engine.events()
+            }
+            EngineCommand::Quote {
+                symbol,
+                side,
+                price,
+                size,
+            } => {
+                let _ = engine.apply_quote(symbol, *side, *price, *size);
+                engine.events()
+            }
+            EngineCommand::AddOrder {
+                symbol,
+                order_id,
+                side,
+                price,
+                quantity,
+            } => {
+                let _ = engine.add_order(symbol, *order_id, *side, *price, *quantity);
+                engine.events()
+            }
+        }
+    }
+}
+
+/// One entry in the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogEntry {
+    Command(EngineCommand),
+    Event(EngineEvent),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    pub seq: u64,
+    pub entry: LogEntry,
+}
+
+impl LogRecord {
+    /// Appends the binary encoding of the record to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let mut w = Writer(out);
+        w.u64(self.seq);
+        match &self.entry {
+            LogEntry::Command(command) => {
+                w.u8(0);
+                encode_command(&mut w, command);
+            }
+            LogEntry::Event(event) => {
+                w.u8(1);
+                encode_event(&mut w, event);
+            }
+        }
+    }
+}
+
+/// Errors returned when decoding a log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogError {
+    /// The buffer does not start with the log magic number.
+    BadMagic,
+    /// The buffer ended before the log was complete.
+    Truncated,
+    /// A tag or enum field held an unknown value.
+    InvalidField,
+    /// A symbol is not valid UTF-8.
+    InvalidSymbol,
+}
+
+/// Ordered command/event stream of one engine run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineLog {
+    mode: BookMode,
+    records: Vec<LogRecord>,
+}
+
+impl EngineLog {
+    /// An empty log for an engine matching against a book in `mode`.
+    pub fn new(mode: BookMode) -> Self {
+        EngineLog {
+            mode,
+            records: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> BookMode {
+        self.mode
+    }
+
+    pub fn records(&self) -> &[LogRecord] {
+        &self.records
+    }
+
+    pub fn push_command(&mut self, command: EngineCommand) {
+        self.push(LogEntry::Command(command));
+    }
+
+    pub fn push_event(&mut self, event: EngineEvent) {
+        self.push(LogEntry::Event(event));
+    }
+
+    fn push(&mut self, entry: LogEntry) {
+        let seq = self.records.len() as u64;
+        self.records.push(LogRecord { seq, entry });
+    }
+
+    /// The recorded inputs, in order.
+    pub fn commands(&self) -> impl Iterator<Item = &EngineCommand> {
+        self.records.iter().filter_map(|r| match &r.entry {
+            LogEntry::Command(command) => Some(command),
+            LogEntry::Event(_) => None,
+        })
+    }
+
+    /// Appends the binary encoding of the log to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&MAGIC.to_le_bytes());
+        out.push(mode_to_u8(self.mode));
+        out.extend_from_slice(&(self.records.len() as u64).to_le_bytes());
+        for record in &self.records {
+            record.encode(out);
+        }
+    }
+
+    /// Decodes a log produced by `encode`.
+    pub fn decode(data: &[u8]) -> Result<Self, LogError> {
+        let mut r = Reader { data, pos: 0 };
+        if r.u32()? != MAGIC {
+            return Err(LogError::BadMagic);
+        }
+        let mode = match r.u8()? {
+            0 => BookMode::MarketByPrice,
+            1 => BookMode::MarketByOrder,
+            _ => return Err(LogError::InvalidField),
+        };
+        let count = r.u64()?;
+        let mut records = Vec::new();
+        for _ in 0..count {
+            let seq = r.u64()?;
+            let entry = match r.u8()? {
+                0 => LogEntry::Command(decode_command(&mut r)?),
+                1 => LogEntry::Event(decode_event(&mut r)?),
+                _ => return Err(LogError::InvalidField),
+            };
+            records.push(LogRecord { seq, entry });
+        }
+        Ok(EngineLog { mode, records })
+    }
+}
+
+/// The first point where two logs differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Index of the first differing record.
+    pub index: usize,
+    /// Record from the first log, `None` if it ended first.
+    pub expected: Option<LogRecord>,
+    /// Record from the second log, `None` if it ended first.
+    pub actual: Option<LogRecord>,
+}
+
+/// Compares two logs record by record on their encoded bytes.
+pub fn diff(expected: &EngineLog, actual: &EngineLog) -> Option<Divergence> {
+    let (mut a, mut b) = (Vec::new(), Vec::new());
+    let len = expected.records.len().max(actual.records.len());
+    for index in 0..len {
+        let (x, y) = (expected.records.get(index), actual.records.get(index));
+        a.clear();
+        b.clear();
+        if let Some(x) = x {
+            x.encode(&mut a);
+        }
+        if let Some(y) = y {
+            y.encode(&mut b);
+        }
+        if x.is_none() || y.is_none() || a != b {
+            return Some(Divergence {
+                index,
+                expected: x.cloned(),
+                actual: y.cloned(),
+            });
+        }
+    }
+    None
+}
+
+/// Feeds the commands of `log` into a fresh engine over an empty book of the same mode and
+/// returns the log of that run. The books the recorded engine started from are rebuilt by
+/// the log's opening `LoadBook` commands.
+pub fn replay(log: &EngineLog) -> EngineLog {
+    let mut book = OrderBook::<TreeLevels>::with_mode(log.mode);
+    let mut engine = MatchEngine::new(&mut book);
+    engine.record_log(true);
+    for command in log.commands() {
+        command.apply(&mut engine);
+    }
+    engine
+        .take_log()
+        .unwrap_or_else(|| EngineLog::new(log.mode))
+}
+
+// Decode tables, indexed by the tags the `*_tag` functions below assign.
+const TIFS: [TimeInForce; 4] = [
+    TimeInForce::Day,
+    TimeInForce::Ioc,
+    TimeInForce::Fok,
+    TimeInForce::Gtc,
+];
+const PHASES: [TradingPhase; 2] = [TradingPhase::Continuous, TradingPhase::Auction];
+const STP_MODES: [SelfTradePrevention; 4] = [
+    SelfTradePrevention::CancelNewest,
+    SelfTradePrevention::CancelOldest,
+    SelfTradePrevention::CancelBoth,
+    SelfTradePrevention::DecrementAndCancel,
+];
+const REJECT_REASONS: [RejectReason; 9] = [
+    RejectReason::ZeroQuantity,
+    RejectReason::DuplicateOrderId,
+    RejectReason::InvalidOrder,
+    RejectReason::PostOnlyWouldCross,
+    RejectReason::FillOrKillUnfillable,
+    RejectReason::ReduceOnlyWouldIncrease,
+    RejectReason::NotAllowedInAuction,
+    RejectReason::UnknownOrder,
+    RejectReason::AmendNotReduction,
+];
+
+// Exhaustive so that a new variant fails to compile until it has a tag.
+fn tif_tag(tif: TimeInForce) -> u8 {
+    match tif {
+        TimeInForce::Day => 0,
+        TimeInForce::Ioc => 1,
+        TimeInForce::Fok => 2,
+        TimeInForce::Gtc => 3,
+    }
+}
+
+fn phase_tag(phase: TradingPhase) -> u8 {
+    match phase {
+        TradingPhase::Continuous => 0,
+        TradingPhase::Auction => 1,
+    }
+}
+
+fn stp_tag(mode: SelfTradePrevention) -> u8 {
+    match mode {
+        SelfTradePrevention::CancelNewest => 0,
+        SelfTradePrevention::CancelOldest => 1,
+        SelfTradePrevention::CancelBoth => 2,
+        SelfTradePrevention::DecrementAndCancel => 3,
+    }
+}
+
+fn reason_tag(reason: RejectReason) -> u8 {
+    match reason {
+        RejectReason::ZeroQuantity => 0,
+        RejectReason::DuplicateOrderId => 1,
+        RejectReason::InvalidOrder => 2,
+        RejectReason::PostOnlyWouldCross => 3,
+        RejectReason::FillOrKillUnfillable => 4,
+        RejectReason::ReduceOnlyWouldIncrease => 5,
+        RejectReason::NotAllowedInAuction => 6,
+        RejectReason::UnknownOrder => 7,
+        RejectReason::AmendNotReduction => 8,
+    }
+}
+
+fn from_tag<T: Copy>(table: &[T], tag: u8) -> Result<T, LogError> {
+    table
+        .get(tag as usize)
+        .copied()
+        .ok_or(LogError::InvalidField)
+}
+
+fn mode_to_u8(mode: BookMode) -> u8 {
+    match mode {
+        BookMode::MarketByPrice => 0,
+        BookMode::MarketByOrder => 1,
+    }
+}
+
+fn encode_command(w: &mut Writer, command: &EngineCommand) {
+    match command {
+        EngineCommand::Submit(order) => {
+            w.u8(0);
+            w.u64(order.order_id);
+            w.str(&order.symbol);
+            w.side(order.side);
+            w.u64(order.quantity);
+            w.opt_price(order.limit);
+            w.u8(tif_tag(order.tif));
+            w.u8(order.flags.bits());
+            match order.account {
+                Some(account) => {
+                    w.u8(1);
+                    w.u32(account);
+                }
+                None => w.u8(0),
+            }
+            w.opt_price(order.stop);
+        }
+        EngineCommand::Cancel { symbol, order_id } => {
+            w.u8(1);
+            w.str(symbol);
+            w.u64(*order_id);
+        }
+        EngineCommand::Amend {
+            symbol,
+            order_id,
+            quantity,
+        } => {
+            w.u8(2);
+            w.str(symbol);
+            w.u64(*order_id);
+            w.u64(*quantity);
+        }
+        EngineCommand::Replace {
+            symbol,
+            order_id,
+            price,
+            quantity,
+        } => {
+            w.u8(3);
+            w.str(symbol);
+            w.u64(*order_id);
+            w.price(*price);
+            w.u64(*quantity);
+        }
+        EngineCommand::MatchOrder {
+            symbol,
+            side,
+            quantity,
+            limit,
+        } => {
+            w.u8(4);
+            w.str(symbol);
+            w.side(*side);
+            w.u64(*quantity);
+            w.opt_price(*limit);
+        }
+        EngineCommand::Trade { symbol, price } => {
+            w.u8(5);
+            w.str(symbol);
+            w.price(*price);
+        }
+        EngineCommand::Uncross { symbol } => {
+            w.u8(6);
+            w.str(symbol);
+        }
+        EngineCommand::EndOfDay => w.u8(7),
+        EngineCommand::SetPhase(phase) => {
+            w.u8(8);
+            w.u8(phase_tag(*phase));
+        }
+        EngineCommand::SetPosition { symbol, position } => {
+            w.u8(9);
+            w.str(symbol);
+            w.u64(*position as u64);
+        }
+        EngineCommand::SetReferencePrice { symbol, price } => {
+            w.u8(10);
+            w.str(symbol);
+            w.price(*price);
+        }
+        EngineCommand::SetSelfTradePrevention(mode) => {
+            w.u8(11);
+            match mode {
+                Some(mode) => {
+                    w.u8(1);
+                    w.u8(stp_tag(*mode));
+                }
+                None => w.u8(0),
+            }
+        }
+        EngineCommand::LoadBook { hidden, snapshot } => {
+            w.u8(12);
+            w.u8(*hidden as u8);
+            let mut bytes = Vec::new();
+            snapshot.encode(&mut bytes);
+            w.u32(bytes.len() as u32);
+            w.0.extend_from_slice(&bytes);
+        }
+        EngineCommand::Quote {
+            symbol,
+            side,
+            price,
+            size,
+        } => {
+            w.u8(13);
+            w.str(symbol);
+            w.side(*side);
+            w.price(*price);
+            w.u64(*size);
+        }
+        EngineCommand::AddOrder {
+            symbol,
+            order_id,
+            side,
+            price,
+            quantity,
+        } => {
+            w.u8(14);
+            w.str(symbol);
+            w.u64(*order_id);
+            w.side(*side);
+            w.price(*price);
+            w.u64(*quantity);
+        }
+    }
+}
+
+fn decode_command(r: &mut Reader) -> Result<EngineCommand, LogError> {
+    Ok(match r.u8()? {
+        0 => {
+            let order_id = r.u64()?;
+            let symbol = r.string()?;
+            let side = r.side()?;
+            let quantity = r.u64()?;
+            let limit = r.opt_price()?;
+            let tif = from_tag(&TIFS, r.u8()?)?;
+            let flags = OrderFlags::from_bits(r.u8()?);
+            let account = match r.u8()? {
+                0 => None,
+                _ => Some(r.u32()?),
+            };
+            let stop = r.opt_price()?;
+            EngineCommand::Submit(Order {
+                order_id,
+                symbol,
+                side,
+                quantity,
+                limit,
+                tif,
+                flags,
+                account,
+                stop,
+            })
+        }
+        1 => EngineCommand::Cancel {
+            symbol: r.string()?,
+            order_id: r.u64()?,
+        },
+        2 => EngineCommand::Amend {
+            symbol: r.string()?,
+            order_id: r.u64()?,
+            quantity: r.u64()?,
+        },
+        3 => EngineCommand::Replace {
+            symbol: r.string()?,
+            order_id: r.u64()?,
+            price: r.price()?,
+            quantity: r.u64()?,
+        },
+        4 => EngineCommand::MatchOrder {
+            symbol: r.string()?,
+            side: r.side()?,
+            quantity: r.u64()?,
+            limit: r.opt_price()?,
+        },
+        5 => EngineCommand::Trade {
+            symbol: r.string()?,
+            price: r.price()?,
+        },
+        6 => EngineCommand::Uncross {
+            symbol: r.string()?,
+        },
+        7 => EngineCommand::EndOfDay,
+        8 => EngineCommand::SetPhase(from_tag(&PHASES, r.u8()?)?),
+        9 => EngineCommand::SetPosition {
+            symbol: r.string()?,
+            position: r.u64()? as i64,
+        },
+        10 => EngineCommand::SetReferencePrice {
+            symbol: r.string()?,
+            price: r.price()?,
+        },
+        11 => EngineCommand::SetSelfTradePrevention(match r.u8()? {
+            0 => None,
+            _ => Some(from_tag(&STP_MODES, r.u8()?)?),
+        }),
+        12 => {
+            let hidden = r.u8()? != 0;
+            let len = r.u32()? as usize;
+            let snapshot = BookSnapshot::decode(r.take(len)?).map_err(|e| match e {
+                SnapshotError::Truncated => LogError::Truncated,
+                SnapshotError::InvalidSymbol => LogError::InvalidSymbol,
+                _ => LogError::InvalidField,
+            })?;
+            EngineCommand::LoadBook { hidden, snapshot }
+        }
+        13 => EngineCommand::Quote {
+            symbol: r.string()?,
+            side: r.side()?,
+            price: r.price()?,
+            size: r.u64()?,
+        },
+        14 => EngineCommand::AddOrder {
+            symbol: r.string()?,
+            order_id: r.u64()?,
+            side: r.side()?,
+            price: r.price()?,
+            quantity: r.u64()?,
+        },
+        _ => return Err(LogError::InvalidField),
+    })
+}
+
+fn encode_event(w: &mut Writer, event: &EngineEvent) {
+    match *event {
+        EngineEvent::Accepted { order_id } => {
+            w.u8(0);
+            w.u64(order_id);
+        }
+        EngineEvent::Rejected { order_id, reason } => {
+            w.u8(1);
+            w.u64(order_id);
+            w.u8(reason_tag(reason));
+        }
+        EngineEvent::Fill(fill) => {
+            w.u8(2);
+            w.price(fill.price);
+            w.u64(fill.quantity);
+            w.opt_u64(fill.resting_order_id);
+            w.side(fill.aggressor);
+        }
+        EngineEvent::Rested {
+            order_id,
+            price,
+            quantity,
+        } => {
+            w.u8(3);
+            w.u64(order_id);
+            w.price(price);
+            w.u64(quantity);
+        }
+        EngineEvent::Cancelled { order_id, quantity } => {
+            w.u8(4);
+            w.u64(order_id);
+            w.u64(quantity);
+        }
+        EngineEvent::SelfTradePrevented {
+            order_id,
+            resting_order_id,
+            cancelled,
+            resting_cancelled,
+        } => {
+            w.u8(5);
+            w.u64(order_id);
+            w.u64(resting_order_id);
+            w.u64(cancelled);
+            w.u64(resting_cancelled);
+        }
+        EngineEvent::Indicative(eq) => {
+            w.u8(6);
+            w.price(eq.price);
+            w.u64(eq.volume);
+            w.u64(eq.imbalance as u64);
+        }
+        EngineEvent::AuctionTrade {
+            price,
+            quantity,
+            buy_order_id,
+            sell_order_id,
+        } => {
+            w.u8(7);
+            w.price(price);
+            w.u64(quantity);
+            w.opt_u64(buy_order_id);
+            w.opt_u64(sell_order_id);
+        }
+        EngineEvent::StopTriggered {
+            order_id,
+            last_trade,
+        } => {
+            w.u8(8);
+            w.u64(order_id);
+            w.price(last_trade);
+        }
+        EngineEvent::Amended { order_id, quantity } => {
+            w.u8(9);
+            w.u64(order_id);
+            w.u64(quantity);
+        }
+        EngineEvent::Replaced {
+            order_id,
+            price,
+            quantity,
+            priority_kept,
+        } => {
+            w.u8(10);
+            w.u64(order_id);
+            w.price(price);
+            w.u64(quantity);
+            w.u8(priority_kept as u8);
+        }
+    }
+}
+
+fn decode_event(r: &mut Reader) -> Result<EngineEvent, LogError> {
+    Ok(match r.u8()? {
+        0 => EngineEvent::Accepted { order_id: r.u64()? },
+        1 => EngineEvent::Rejected {
+            order_id: r.u64()?,
+            reason: from_tag(&REJECT_REASONS, r.u8()?)?,
+        },
+        2 => EngineEvent::Fill(Fill {
+            price: r.price()?,
+            quantity: r.u64()?,
+            resting_order_id: r.opt_u64()?,
+            aggressor: r.side()?,
+        }),
+        3 => EngineEvent::Rested {
+            order_id: r.u64()?,
+            price: r.price()?,
+            quantity: r.u64()?,
+        },
+        4 => EngineEvent::Cancelled {
+            order_id: r.u64()?,
+            quantity: r.u64()?,
+        },
+        5 => EngineEvent::SelfTradePrevented {
+            order_id: r.u64()?,
+            resting_order_id: r.u64()?,
+            cancelled: r.u64()?,
+            resting_cancelled: r.u64()?,
+        },
+        6 => EngineEvent::Indicative(Equilibrium {
+            price: r.price()?,
+            volume: r.u64()?,
+            imbalance: r.u64()? as i64,
+        }),
+        7 => EngineEvent::AuctionTrade {
+            price: r.price()?,
+            quantity: r.u64()?,
+            buy_order_id: r.opt_u64()?,
+            sell_order_id: r.opt_u64()?,
+        },
+        8 => EngineEvent::StopTriggered {
+            order_id: r.u64()?,
+            last_trade: r.price()?,
+        },
+        9 => EngineEvent::Amended {
+            order_id: r.u64()?,
+            quantity: r.u64()?,
+        },
+        10 => EngineEvent::Replaced {
+            order_id: r.u64()?,
+            price: r.price()?,
+            quantity: r.u64()?,
+            priority_kept: r.u8()? != 0,
+        },
+        _ => return Err(LogError::InvalidField),
+    })
+}
+
+struct Writer<'a>(&'a mut Vec<u8>);
+
+impl Writer<'_> {
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn price(&mut self, p: Price) {
+        self.0.extend_from_slice(&p.ticks().to_le_bytes());
+    }
+
+    fn opt_price(&mut self, p: Option<Price>) {
+        match p {
+            Some(p) => {
+                self.u8(1);
+                self.price(p);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn opt_u64(&mut self, v: Option<u64>) {
+        match v {
+            Some(v) => {
+                self.u8(1);
+                self.u64(v);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn side(&mut self, side: Side) {
+        self.u8(match side {
+            Side::Bid => 0,
+            Side::Ask => 1,
+        });
+    }
+
+    fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.0.extend_from_slice(s.as_bytes());
+    }
+}
+
+/// Bounds-checked cursor over the encoded bytes.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], LogError> {
+        let end = self.pos.checked_add(len).ok_or(LogError::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(LogError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, LogError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, LogError> {
+        Ok(le_to_u32(self.take(4)?))
+    }
+
+    fn u64(&mut self) -> Result<u64, LogError> {
+        Ok(le_to_u64(self.take(8)?))
+    }
+
+    fn price(&mut self) -> Result<Price, LogError> {
+        Ok(Price::from_ticks(self.u64()? as i64))
+    }
+
+    fn opt_price(&mut self) -> Result<Option<Price>, LogError> {
+        Ok(match self.u8()? {
+            0 => None,
+            _ => Some(self.price()?),
+        })
+    }
+
+    fn opt_u64(&mut self) -> Result<Option<u64>, LogError> {
+        Ok(match self.u8()? {
+            0 => None,
+            _ => Some(self.u64()?),
+        })
+    }
+
+    fn side(&mut self) -> Result<Side, LogError> {
+        match self.u8()? {
+            0 => Ok(Side::Bid),
+            1 => Ok(Side::Ask),
+            _ => Err(LogError::InvalidField),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, LogError> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?)
+            .map(str::to_string)
+            .map_err(|_| LogError::InvalidSymbol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(ticks: i64) -> Price {
+        Price::from_ticks(ticks)
+    }
+
+    fn record_session() -> EngineLog {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 30, Side::Bid, px(95), 2).unwrap();
+        let mut engine = MatchEngine::new(&mut book);
+        engine.record_log(true);
+        engine.set_self_trade_prevention(Some(SelfTradePrevention::CancelOldest));
+        engine.add_order("ES", 31, Side::Ask, px(103), 4).unwrap();
+        engine.submit(&Order::limit(1, "ES", Side::Ask, px(101), 5).with_account(1));
+        engine.submit(&Order::limit(2, "ES", Side::Ask, px(102), 5));
+        engine.submit(&Order::market(3, "ES", Side::Bid, 2).with_stop(px(102)));
+        engine.submit(&Order::limit(4, "ES", Side::Bid, px(102), 6).with_account(1));
+        engine.amend("ES", 2, 3);
+        engine.replace("ES", 4, px(100), 1);
+        engine.cancel("ES", 9);
+        engine.set_phase(TradingPhase::Auction);
+        engine.submit(&Order::limit(5, "ES", Side::Ask, px(99), 1));
+        engine.uncross("ES");
+        engine.take_log().unwrap()
+    }
+
+    #[test]
+    fn test_replay_reproduces_log() {
+        let log = record_session();
+        assert!(log.records().len() > 20);
+        assert_eq!(diff(&log, &replay(&log)), None);
+
+        let mut bytes = Vec::new();
+        log.encode(&mut bytes);
+        let decoded = EngineLog::decode(&bytes).unwrap();
+        assert_eq!(decoded, log);
+        assert_eq!(
+            EngineLog::decode(&bytes[..bytes.len() - 1]),
+            Err(LogError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_replay_starts_from_existing_book() {
+        let mut book = OrderBook::new();
+        book.apply_quote("ES", Side::Ask, px(101), 5).unwrap();
+        book.apply_quote("ES", Side::Bid, px(99), 5).unwrap();
+        let mut engine = MatchEngine::new(&mut book);
+        engine.set_position("ES", 3);
+        engine.record_log(true);
+        engine.apply_quote("ES", Side::Ask, px(100), 2).unwrap();
+        engine.submit(&Order::market(1, "ES", Side::Bid, 4));
+        engine.submit(
+            &Order::limit(2, "ES", Side::Ask, px(99), 3).with_flags(OrderFlags::REDUCE_ONLY),
+        );
+        let log = engine.take_log().unwrap();
+
+        let opening: Vec<_> = log.commands().take(2).collect();
+        assert!(matches!(
+            opening[0],
+            EngineCommand::LoadBook { hidden: false, snapshot } if snapshot.asks == vec![(px(101), 5)]
+        ));
+        assert_eq!(
+            opening[1],
+            &EngineCommand::SetPosition {
+                symbol: "ES".to_string(),
+                position: 3
+            }
+        );
+        assert_eq!(diff(&log, &replay(&log)), None);
+
+        let mut bytes = Vec::new();
+        log.encode(&mut bytes);
+        assert_eq!(EngineLog::decode(&bytes).unwrap(), log);
+    }
+
+    #[test]
+    fn test_replay_starts_from_last_trade() {
+        let mut book = OrderBook::new();
+        book.apply_quote("ES", Side::Ask, px(101), 5).unwrap();
+        let mut engine = MatchEngine::new(&mut book);
+        engine.on_trade("ES", px(102));
+        engine.record_log(true);
+        // Already triggered by the trade before recording started.
+        let events = engine
+            .submit(&Order::market(1, "ES", Side::Bid, 2).with_stop(px(101)))
+            .to_vec();
+        assert!(events.contains(&EngineEvent::StopTriggered {
+            order_id: 1,
+            last_trade: px(102)
+        }));
+        let log = engine.take_log().unwrap();
+
+        assert!(log.commands().any(|c| c
+            == &EngineCommand::Trade {
+                symbol: "ES".to_string(),
+                price: px(102)
+            }));
+        assert_eq!(diff(&log, &replay(&log)), None);
+    }
+
+    #[test]
+    fn test_tags_match_decode_tables() {
+        for (tag, &tif) in TIFS.iter().enumerate() {
+            assert_eq!(tif_tag(tif) as usize, tag);
+        }
+        for (tag, &phase) in PHASES.iter().enumerate() {
+            assert_eq!(phase_tag(phase) as usize, tag);
+        }
+        for (tag, &mode) in STP_MODES.iter().enumerate() {
+            assert_eq!(stp_tag(mode) as usize, tag);
+        }
+        for (tag, &reason) in REJECT_REASONS.iter().enumerate() {
+            assert_eq!(reason_tag(reason) as usize, tag);
+        }
+    }
+
+    #[test]
+    fn test_diff_reports_first_divergence() {
+        let log = record_session();
+        let mut altered = log.clone();
+        let index = altered
+            .records
+            .iter()
+            .position(|r| matches!(r.entry, LogEntry::Event(EngineEvent::Fill(_))))
+            .unwrap();
+        if let LogEntry::Event(EngineEvent::Fill(fill)) = &mut altered.records[index].entry {
+            fill.quantity += 1;
+        }
+        let divergence = diff(&log, &altered).unwrap();
+        assert_eq!(divergence.index, index);
+
+        altered.records.truncate(index);
+        assert_eq!(diff(&log, &altered).unwrap().actual, None);
+    }
+}
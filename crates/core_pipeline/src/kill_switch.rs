@@ -0,0 +1,549 @@
+//! kill_switch.rs
+//! Global and per-strategy kill switches that stop all new orders.
+//!
+//! # Key Concepts
+//! - There is one global switch and one switch per account (each strategy trades under its
+//!   own account). A tripped switch blocks every new order in its scope; `RiskChecker` checks
+//!   it before any other limit.
+//! - States: `Disarmed` (conditions ignored), `Armed` (conditions monitored) and `Tripped`.
+//!   Operators move between them with `KillCommand`: `Arm`, `Disarm`, `Trip` (manual) and
+//!   `Reset` (tripped back to armed).
+//! - Armed switches trip automatically on their `KillConditions`: a loss limit, a reject storm
+//!   (too many rejects within a time window) or stale market data.
+//! - Rejects are not seen by the switch itself: the order gateway reports every rejected
+//!   order, whether refused by `RiskChecker` or by the venue, with
+//!   `RiskChecker::record_reject` (or `on_reject` directly).
+//! - Tripping with `mass_cancel` set also emits a `MassCancel` event for the scope; the order
+//!   gateway cancels the resting orders.
+//! - Every state change is recorded as a `KillEvent` with `drain_events`; `encode` gives a
+//!   fixed 24-byte record for the journal. A `KillJournal` set with `set_journal` receives
+//!   each record inside the transition, so no state change can be lost before it is written.
+//! - Market data staleness is tracked only for instruments added with `subscribe`, so an
+//!   instrument we no longer trade (after `unsubscribe`) cannot trip the switch.
+//! - Time is passed in by the caller as nanoseconds.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::order_types::AccountId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KillScope {
+    Global,
+    Account(AccountId),
+}
+
+/// Why a switch tripped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TripReason {
+    Operator,
+    /// `pnl` is the PnL that breached the limit, rounded down to whole currency units.
+    LossLimit {
+        pnl: i64,
+    },
+    /// `rejects` rejects were seen within the configured window.
+    RejectStorm {
+        rejects: u32,
+    },
+    /// No market data for `age_ns` on at least one subscribed instrument.
+    StaleMarketData {
+        age_ns: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KillState {
+    #[default]
+    Disarmed,
+    Armed,
+    Tripped(TripReason),
+}
+
+/// At most `rejects` rejects within `window_ns`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RejectStorm {
+    pub rejects: u32,
+    pub window_ns: u64,
+}
+
+/// Automatic trip conditions of one switch. `None` disables a condition.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KillConditions {
+    /// Maximum loss (realised plus unrealised), in currency.
+    pub max_loss: Option<u64>,
+    pub reject_storm: Option<RejectStorm>,
+    /// Maximum time without market data on any subscribed instrument.
+    pub max_data_age_ns: Option<u64>,
+    /// Emit a `MassCancel` event when the switch trips.
+    pub mass_cancel: bool,
+}
+
+/// Operator commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillCommand {
+    Arm(KillScope),
+    Disarm(KillScope),
+    Trip(KillScope),
+    Reset(KillScope),
+}
+
+/// A command that is not valid in the switch's current state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidKillCommand {
+    pub command: KillCommand,
+    pub state: KillState,
+}
+
+/// Journaled kill switch activity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillEvent {
+    StateChanged {
+        scope: KillScope,
+        state: KillState,
+        at_ns: u64,
+    },
+    /// Cancel every resting order in `scope`.
+    MassCancel { scope: KillScope, at_ns: u64 },
+}
+
+/// Size in bytes of an encoded event.
+pub const ENCODED_KILL_EVENT_BYTES: usize = 24;
+
+impl KillEvent {
+    /// Appends a fixed-size little-endian record for journaling:
+    /// `u8 tag` (1 state change, 2 mass cancel), `u8 scope` (0 global, 1 account),
+    /// `u32 account`, `u8 state` (0 disarmed, 1 armed, 2 tripped), `u8 reason`
+    /// (0 none, 1 operator, 2 loss, 3 rejects, 4 stale data), `i64 reason value`, `u64 at_ns`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let (tag, scope, state, at_ns) = match *self {
+            KillEvent::StateChanged {
+                scope,
+                state,
+                at_ns,
+            } => (1u8, scope, state, at_ns),
+            KillEvent::MassCancel { scope, at_ns } => (2, scope, KillState::Disarmed, at_ns),
+        };
+        let (scope_tag, account) = match scope {
+            KillScope::Global => (0u8, 0),
+            KillScope::Account(account) => (1, account),
+        };
+        let (state_tag, reason_tag, value) = match state {
+            KillState::Disarmed => (0u8, 0u8, 0i64),
+            KillState::Armed => (1, 0, 0),
+            KillState::Tripped(TripReason::Operator) => (2, 1, 0),
+            KillState::Tripped(TripReason::LossLimit { pnl }) => (2, 2, pnl),
+            KillState::Tripped(TripReason::RejectStorm { rejects }) => (2, 3, rejects as i64),
+            KillState::Tripped(TripReason::StaleMarketData { age_ns }) => (2, 4, age_ns as i64),
+        };
+        out.push(tag);
+        out.push(scope_tag);
+        out.extend_from_slice(&account.to_le_bytes());
+        out.push(state_tag);
+        out.push(reason_tag);
+        out.extend_from_slice(&value.to_le_bytes());
+        out.extend_from_slice(&at_ns.to_le_bytes());
+    }
+}
+
+/// Receives each encoded `KillEvent` as the switch records it, e.g. to append it to the
+/// storage journal.
+pub trait KillJournal {
+    fn append(&mut self, record: &[u8]);
+}
+
+/// Collects the encoded records, e.g. for tests.
+impl KillJournal for Vec<Vec<u8>> {
+    fn append(&mut self, record: &[u8]) {
+        self.push(record.to_vec());
+    }
+}
+
+/// The journal sink with its encode buffer.
+struct JournalSink {
+    journal: Box<dyn KillJournal + Send>,
+    buf: Vec<u8>,
+}
+
+impl std::fmt::Debug for JournalSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JournalSink").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Switch {
+    conditions: KillConditions,
+    state: KillState,
+    /// Times of the rejects inside the reject storm window.
+    rejects: VecDeque<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct KillSwitch {
+    global: Switch,
+    accounts: HashMap<AccountId, Switch>,
+    /// Time of the last market data per subscribed instrument.
+    last_data: HashMap<String, u64>,
+    events: Vec<KillEvent>,
+    journal: Option<JournalSink>,
+}
+
+impl KillSwitch {
+    pub fn new() -> Self {
+        KillSwitch::default()
+    }
+
+    /// Writes every event from now on to `journal` as it is recorded.
+    pub fn set_journal(&mut self, journal: Box<dyn KillJournal + Send>) {
+        self.journal = Some(JournalSink {
+            journal,
+            buf: Vec::with_capacity(ENCODED_KILL_EVENT_BYTES),
+        });
+    }
+
+    pub fn set_conditions(&mut self, scope: KillScope, conditions: KillConditions) {
+        self.switch_mut(scope).conditions = conditions;
+    }
+
+    pub fn state(&self, scope: KillScope) -> KillState {
+        match scope {
+            KillScope::Global => self.global.state,
+            KillScope::Account(account) => self
+                .accounts
+                .get(&account)
+                .map_or(KillState::Disarmed, |switch| switch.state),
+        }
+    }
+
+    /// The tripped scope that blocks a new order from `account`, global first.
+    pub fn blocks(&self, account: Option<AccountId>) -> Option<KillScope> {
+        if matches!(self.global.state, KillState::Tripped(_)) {
+            return Some(KillScope::Global);
+        }
+        let account = account?;
+        let switch = self.accounts.get(&account)?;
+        matches!(switch.state, KillState::Tripped(_)).then_some(KillScope::Account(account))
+    }
+
+    /// Accounts with their own switch.
+    pub fn accounts(&self) -> impl Iterator<Item = AccountId> + '_ {
+        self.accounts.keys().copied()
+    }
+
+    /// Applies an operator command.
+    pub fn command(&mut self, command: KillCommand, now_ns: u64) -> Result<(), InvalidKillCommand> {
+        let scope = match command {
+            KillCommand::Arm(scope)
+            | KillCommand::Disarm(scope)
+            | KillCommand::Trip(scope)
+            | KillCommand::Reset(scope) => scope,
+        };
+        let state = self.state(scope);
+        match (command, state) {
+            (KillCommand::Arm(_), KillState::Disarmed) => {
+                self.transition(scope, KillState::Armed, now_ns)
+            }
+            (KillCommand::Disarm(_), KillState::Armed) => {
+                self.transition(scope, KillState::Disarmed, now_ns)
+            }
+            (KillCommand::Trip(_), KillState::Disarmed | KillState::Armed) => {
+                self.trip(scope, TripReason::Operator, now_ns)
+            }
+            (KillCommand::Reset(_), KillState::Tripped(_)) => {
+                self.switch_mut(scope).rejects.clear();
+                self.transition(scope, KillState::Armed, now_ns)
+            }
+            _ => return Err(InvalidKillCommand { command, state }),
+        }
+        Ok(())
+    }
+
+    /// Checks the loss limit of `scope` against its current PnL.
+    pub fn on_pnl(&mut self, scope: KillScope, pnl: f64, now_ns: u64) {
+        let switch = self.switch_mut(scope);
+        if switch.state != KillState::Armed {
+            return;
+        }
+        if let Some(limit) = switch.conditions.max_loss {
+            if pnl < -(limit as f64) {
+                let pnl = pnl.floor() as i64;
+                self.trip(scope, TripReason::LossLimit { pnl }, now_ns);
+            }
+        }
+    }
+
+    /// Counts an order reject against the global switch and the account's switch. Called by
+    /// the order gateway for every rejected order (see the module docs).
+    pub fn on_reject(&mut self, account: Option<AccountId>, now_ns: u64) {
+        let scopes = [Some(KillScope::Global), account.map(KillScope::Account)];
+        for scope in scopes.into_iter().flatten() {
+            let Some(switch) = self.existing_mut(scope) else {
+                continue;
+            };
+            let Some(storm) = switch.conditions.reject_storm else {
+                continue;
+            };
+            if switch.state != KillState::Armed {
+                continue;
+            }
+            switch.rejects.push_back(now_ns);
+            while let Some(&first) = switch.rejects.front() {
+                if now_ns.saturating_sub(first) < storm.window_ns {
+                    break;
+                }
+                switch.rejects.pop_front();
+            }
+            let rejects = switch.rejects.len() as u32;
+            if rejects > storm.rejects {
+                self.trip(scope, TripReason::RejectStorm { rejects }, now_ns);
+            }
+        }
+    }
+
+    /// Starts tracking market data staleness for `symbol`, counting its age from `now_ns`.
+    pub fn subscribe(&mut self, symbol: &str, now_ns: u64) {
+        self.last_data.insert(symbol.to_string(), now_ns);
+    }
+
+    /// Stops tracking `symbol`, e.g. once we no longer trade it.
+    pub fn unsubscribe(&mut self, symbol: &str) {
+        self.last_data.remove(symbol);
+    }
+
+    /// Records market data for `symbol`; ignored unless it is subscribed.
+    pub fn on_market_data(&mut self, symbol: &str, now_ns: u64) {
+        if let Some(last) = self.last_data.get_mut(symbol) {
+            *last = now_ns;
+        }
+    }
+
+    /// Trips armed switches whose market data age limit is exceeded at `now_ns` by any
+    /// subscribed instrument.
+    pub fn check_stale(&mut self, now_ns: u64) {
+        let Some(oldest) = self.last_data.values().min().copied() else {
+            return;
+        };
+        let age_ns = now_ns.saturating_sub(oldest);
+        let stale = |switch: &Switch| {
+            switch.state == KillState::Armed
+                && switch
+                    .conditions
+                    .max_data_age_ns
+                    .is_some_and(|max| age_ns > max)
+        };
+        let reason = TripReason::StaleMarketData { age_ns };
+        if stale(&self.global) {
+            self.trip(KillScope::Global, reason, now_ns);
+        }
+        // A tripped switch is no longer armed, so each account is found at most once.
+        while let Some(account) = self
+            .accounts
+            .iter()
+            .find(|(_, switch)| stale(switch))
+            .map(|(&account, _)| account)
+        {
+            self.trip(KillScope::Account(account), reason, now_ns);
+        }
+    }
+
+    /// Consumes the recorded events in order.
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, KillEvent> {
+        self.events.drain(..)
+    }
+
+    fn trip(&mut self, scope: KillScope, reason: TripReason, now_ns: u64) {
+        self.transition(scope, KillState::Tripped(reason), now_ns);
+        if self.switch_mut(scope).conditions.mass_cancel {
+            self.record(KillEvent::MassCancel {
+                scope,
+                at_ns: now_ns,
+            });
+        }
+    }
+
+    fn transition(&mut self, scope: KillScope, state: KillState, now_ns: u64) {
+        self.switch_mut(scope).state = state;
+        self.record(KillEvent::StateChanged {
+            scope,
+            state,
+            at_ns: now_ns,
+        });
+    }
+
+    fn record(&mut self, event: KillEvent) {
+        if let Some(sink) = self.journal.as_mut() {
+            sink.buf.clear();
+            event.encode(&mut sink.buf);
+            sink.journal.append(&sink.buf);
+        }
+        self.events.push(event);
+    }
+
+    fn existing_mut(&mut self, scope: KillScope) -> Option<&mut Switch> {
+        match scope {
+            KillScope::Global => Some(&mut self.global),
+            KillScope::Account(account) => self.accounts.get_mut(&account),
+        }
+    }
+
+    fn switch_mut(&mut self, scope: KillScope) -> &mut Switch {
+        match scope {
+            KillScope::Global => &mut self.global,
+            KillScope::Account(account) => self.accounts.entry(account).or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operator_commands_and_journal() {
+        let mut kill = KillSwitch::new();
+        let scope = KillScope::Account(7);
+        kill.set_conditions(
+            scope,
+            KillConditions {
+                mass_cancel: true,
+                ..KillConditions::default()
+            },
+        );
+        assert!(kill.command(KillCommand::Reset(scope), 1).is_err());
+        kill.command(KillCommand::Arm(scope), 1).unwrap();
+        kill.command(KillCommand::Trip(scope), 2).unwrap();
+        assert_eq!(kill.blocks(Some(7)), Some(scope));
+        assert_eq!(kill.blocks(Some(8)), None);
+        kill.command(KillCommand::Reset(scope), 3).unwrap();
+        assert_eq!(kill.blocks(Some(7)), None);
+
+        let events: Vec<KillEvent> = kill.drain_events().collect();
+        assert_eq!(
+            events,
+            vec![
+                KillEvent::StateChanged {
+                    scope,
+                    state: KillState::Armed,
+                    at_ns: 1
+                },
+                KillEvent::StateChanged {
+                    scope,
+                    state: KillState::Tripped(TripReason::Operator),
+                    at_ns: 2
+                },
+                KillEvent::MassCancel { scope, at_ns: 2 },
+                KillEvent::StateChanged {
+                    scope,
+                    state: KillState::Armed,
+                    at_ns: 3
+                },
+            ]
+        );
+        let mut out = Vec::new();
+        events[1].encode(&mut out);
+        assert_eq!(out.len(), ENCODED_KILL_EVENT_BYTES);
+        assert_eq!(&out[..8], &[1, 1, 7, 0, 0, 0, 2, 1]);
+    }
+
+    #[test]
+    fn test_automatic_trips() {
+        let mut kill = KillSwitch::new();
+        kill.set_conditions(
+            KillScope::Global,
+            KillConditions {
+                reject_storm: Some(RejectStorm {
+                    rejects: 2,
+                    window_ns: 100,
+                }),
+                max_data_age_ns: Some(1_000),
+                ..KillConditions::default()
+            },
+        );
+        // Conditions are ignored until armed.
+        kill.subscribe("ES", 0);
+        kill.check_stale(10_000);
+        kill.command(KillCommand::Arm(KillScope::Global), 0)
+            .unwrap();
+
+        kill.on_reject(None, 0);
+        kill.on_reject(None, 50);
+        kill.on_reject(None, 150);
+        kill.on_reject(None, 160);
+        assert_eq!(kill.state(KillScope::Global), KillState::Armed);
+        kill.on_reject(None, 170);
+        assert_eq!(
+            kill.state(KillScope::Global),
+            KillState::Tripped(TripReason::RejectStorm { rejects: 3 })
+        );
+
+        kill.command(KillCommand::Reset(KillScope::Global), 200)
+            .unwrap();
+        kill.on_market_data("ES", 500);
+        // A quiet instrument stops counting once unsubscribed.
+        kill.subscribe("NQ", 0);
+        kill.on_market_data("CL", 0);
+        kill.check_stale(1_100);
+        assert_eq!(
+            kill.state(KillScope::Global),
+            KillState::Tripped(TripReason::StaleMarketData { age_ns: 1_100 })
+        );
+        kill.command(KillCommand::Reset(KillScope::Global), 1_100)
+            .unwrap();
+        kill.unsubscribe("NQ");
+        kill.check_stale(1_200);
+        assert_eq!(kill.blocks(None), None);
+        kill.check_stale(1_600);
+        assert_eq!(
+            kill.state(KillScope::Global),
+            KillState::Tripped(TripReason::StaleMarketData { age_ns: 1_100 })
+        );
+        assert_eq!(kill.blocks(Some(3)), Some(KillScope::Global));
+    }
+
+    #[test]
+    fn test_journal_receives_each_transition() {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<Vec<u8>>>>);
+
+        impl KillJournal for Shared {
+            fn append(&mut self, record: &[u8]) {
+                self.0.lock().unwrap().push(record.to_vec());
+            }
+        }
+
+        let journal = Shared::default();
+        let mut kill = KillSwitch::new();
+        kill.set_journal(Box::new(journal.clone()));
+        kill.set_conditions(
+            KillScope::Global,
+            KillConditions {
+                max_loss: Some(100),
+                mass_cancel: true,
+                ..KillConditions::default()
+            },
+        );
+        kill.command(KillCommand::Arm(KillScope::Global), 1)
+            .unwrap();
+        kill.on_pnl(KillScope::Global, -150.5, 2);
+
+        let events: Vec<KillEvent> = kill.drain_events().collect();
+        let expected: Vec<Vec<u8>> = events
+            .iter()
+            .map(|event| {
+                let mut out = Vec::new();
+                event.encode(&mut out);
+                out
+            })
+            .collect();
+        let records = journal.0.lock().unwrap();
+        assert_eq!(*records, expected);
+        assert_eq!(records.len(), 3);
+        // Tripped on a loss of 151: state 2, reason 2, value -151.
+        assert_eq!(
+            &records[1][6..16],
+            &[2, 2, 0x69, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(records[2][0], 2);
+    }
+}
@@ -2,9 +2,26 @@
 //! Entry point for the `core_pipeline` crate. Re-exports modules for public use.
 
 pub mod order_book;
+pub mod price_levels;
+pub mod price_ladder;
+pub mod book_snapshot;
+pub mod book_events;
+pub mod book_builder;
+pub mod consolidated_book;
+pub mod order_types;
+pub mod auction;
+pub mod stop_book;
 pub mod match_engine;
+pub mod engine_log;
+pub mod kill_switch;
+pub mod positions;
 pub mod risk_checks;
+pub mod throttle;
+pub mod rolling_window;
+pub mod market_features;
+pub mod time_window;
 pub mod aggregator;
+pub mod aggregator_registry;
 pub mod signal_generator;
 pub mod allocators;
 pub mod lock_free_queues;
@@ -0,0 +1,286 @@
+//! market_features.rs
+//! Microstructure features computed from trades and best-level quotes.
+//!
+//! # Key Concepts
+//! - VWAP over the session (until `reset_session`) and over the last N trades.
+//! - Signed volume with tick-rule classification: a trade above the previous trade price is
+//!   buyer-initiated, below is seller-initiated, and at the same price keeps the previous
+//!   classification. Trades before the first price change are unclassified.
+//! - Order-flow imbalance (Cont, Kukanov and Stoikov): each best-level change contributes the
+//!   bid size added or removed at the touch minus the ask size added or removed. Summed over
+//!   the last N best-level changes.
+//! - Microprice: the mid weighted by the opposite sizes, `(bid * ask_size + ask * bid_size) /
+//!   (bid_size + ask_size)`.
+//! - Trade intensity: trades in the last second. Timestamps are in nanoseconds.
+//! - Prices are in ticks.
+//! - `on_market_message` consumes `Trade` and `Quote` messages; quotes are taken as best-level
+//!   updates, as sent by top-of-book feeds. With a full book, the features are fed the
+//!   `TopOfBook` events as a `BookListener` instead.
+
+use std::collections::VecDeque;
+
+use common::price::Price;
+use reception_layer::message_types::MarketMessage;
+
+use super::book_events::{BookEvent, BookListener};
+use super::order_book::{Level, Side};
+use super::rolling_window::{RingBuffer, RollingStats};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone)]
+pub struct MarketFeatures {
+    session_notional: f64,
+    session_volume: f64,
+    /// (price * size, size) of the last N trades.
+    trades: RingBuffer<(f64, f64)>,
+    rolling_notional: f64,
+    rolling_volume: f64,
+    last_trade: Option<Price>,
+    /// Tick-rule direction of the last trade: 1 buy, -1 sell, 0 unknown.
+    last_direction: i64,
+    signed_volume: i64,
+    bid: Option<Level>,
+    ask: Option<Level>,
+    imbalance: RollingStats,
+    /// Trade timestamps within the last second.
+    trade_times: VecDeque<u64>,
+}
+
+impl MarketFeatures {
+    /// Rolling VWAP and order-flow imbalance cover the last `window` trades and best-level
+    /// changes.
+    pub fn new(window: usize) -> Self {
+        MarketFeatures {
+            session_notional: 0.0,
+            session_volume: 0.0,
+            trades: RingBuffer::new(window),
+            rolling_notional: 0.0,
+            rolling_volume: 0.0,
+            last_trade: None,
+            last_direction: 0,
+            signed_volume: 0,
+            bid: None,
+            ask: None,
+            imbalance: RollingStats::new(window),
+            trade_times: VecDeque::new(),
+        }
+    }
+
+    pub fn on_trade(&mut self, price: Price, size: u64, timestamp: u64) {
+        let (ticks, quantity) = (price.ticks() as f64, size as f64);
+        self.session_notional += ticks * quantity;
+        self.session_volume += quantity;
+        self.rolling_notional += ticks * quantity;
+        self.rolling_volume += quantity;
+        if let Some((notional, volume)) = self.trades.push((ticks * quantity, quantity)) {
+            self.rolling_notional -= notional;
+            self.rolling_volume -= volume;
+        }
+
+        if let Some(last) = self.last_trade {
+            if price > last {
+                self.last_direction = 1;
+            } else if price < last {
+                self.last_direction = -1;
+            }
+        }
+        self.signed_volume += self.last_direction * size as i64;
+        self.last_trade = Some(price);
+
+        self.trade_times.push_back(timestamp);
+        self.evict_trade_times(timestamp);
+    }
+
+    /// Updates the features from a trade or best-level quote; other messages are ignored. A
+    /// quote of size 0 empties its side.
+    pub fn on_market_message(&mut self, message: &MarketMessage) {
+        match *message {
+            MarketMessage::Trade {
+                price,
+                size,
+                timestamp,
+                ..
+            } => self.on_trade(price, size, timestamp),
+            MarketMessage::Quote {
+                side, price, size, ..
+            } => self.on_best_level(side, (size > 0).then_some((price, size))),
+            MarketMessage::OrderUpdate { .. } | MarketMessage::Snapshot { .. } => {}
+        }
+    }
+
+    /// Applies a change of the best level on `side`; `None` when the side is empty.
+    pub fn on_best_level(&mut self, side: Side, level: Option<Level>) {
+        let (bid, ask) = match side {
+            Side::Bid => (level, self.ask),
+            Side::Ask => (self.bid, level),
+        };
+        self.on_top_of_book(bid, ask);
+    }
+
+    /// Applies a new top of book, adding its order-flow imbalance if either side changed.
+    pub fn on_top_of_book(&mut self, bid: Option<Level>, ask: Option<Level>) {
+        if (bid, ask) == (self.bid, self.ask) {
+            return;
+        }
+        let flow = touch_flow(self.bid, bid, Side::Bid) - touch_flow(self.ask, ask, Side::Ask);
+        self.imbalance.push(flow);
+        self.bid = bid;
+        self.ask = ask;
+    }
+
+    /// Starts a new session VWAP and signed volume.
+    pub fn reset_session(&mut self) {
+        self.session_notional = 0.0;
+        self.session_volume = 0.0;
+        self.signed_volume = 0;
+    }
+
+    pub fn session_vwap(&self) -> Option<f64> {
+        (self.session_volume > 0.0).then(|| self.session_notional / self.session_volume)
+    }
+
+    pub fn rolling_vwap(&self) -> Option<f64> {
+        (self.rolling_volume > 0.0).then(|| self.rolling_notional / self.rolling_volume)
+    }
+
+    /// Buy minus sell volume this session, by the tick rule.
+    pub fn signed_volume(&self) -> i64 {
+        self.signed_volume
+    }
+
+    /// Order-flow imbalance summed over the last N best-level changes.
+    pub fn order_flow_imbalance(&self) -> f64 {
+        self.imbalance.sum()
+    }
+
+    /// `None` unless both sides have a best level.
+    pub fn microprice(&self) -> Option<f64> {
+        let ((bid, bid_size), (ask, ask_size)) = (self.bid?, self.ask?);
+        let total = (bid_size + ask_size) as f64;
+        if total == 0.0 {
+            return None;
+        }
+        Some((bid.ticks() as f64 * ask_size as f64 + ask.ticks() as f64 * bid_size as f64) / total)
+    }
+
+    /// Trades in the second up to `now`.
+    pub fn trade_intensity(&self, now: u64) -> usize {
+        let expired = self
+            .trade_times
+            .partition_point(|&t| now.saturating_sub(t) >= NANOS_PER_SECOND);
+        self.trade_times.len() - expired
+    }
+
+    fn evict_trade_times(&mut self, now: u64) {
+        while self
+            .trade_times
+            .front()
+            .is_some_and(|&t| now.saturating_sub(t) >= NANOS_PER_SECOND)
+        {
+            self.trade_times.pop_front();
+        }
+    }
+}
+
+/// Size added (positive) or removed (negative) at the touch of `side` when its best level
+/// moves from `before` to `after`. An improving price counts the new size, a worsening price
+/// the size lost.
+fn touch_flow(before: Option<Level>, after: Option<Level>, side: Side) -> f64 {
+    match (before, after) {
+        (None, None) => 0.0,
+        (None, Some((_, size))) => size as f64,
+        (Some((_, size)), None) => -(size as f64),
+        (Some((old_price, old_size)), Some((price, size))) => {
+            let improved = match side {
+                Side::Bid => price > old_price,
+                Side::Ask => price < old_price,
+            };
+            if price == old_price {
+                size as f64 - old_size as f64
+            } else if improved {
+                size as f64
+            } else {
+                -(old_size as f64)
+            }
+        }
+    }
+}
+
+impl BookListener for MarketFeatures {
+    /// Applies every top-of-book change.
+    fn on_book_event(&mut self, _symbol: &str, event: &BookEvent) {
+        if let BookEvent::TopOfBook { bid, ask } = *event {
+            self.on_top_of_book(bid, ask);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(ticks: i64) -> Price {
+        Price::from_ticks(ticks)
+    }
+
+    #[test]
+    fn test_vwap_and_tick_rule() {
+        let mut features = MarketFeatures::new(2);
+        features.on_trade(px(100), 10, 0);
+        features.on_trade(px(101), 5, 100);
+        features.on_trade(px(101), 5, 200);
+        features.on_trade(px(99), 20, NANOS_PER_SECOND);
+
+        assert_eq!(features.session_vwap(), Some(3_990.0 / 40.0));
+        assert_eq!(features.rolling_vwap(), Some(2_485.0 / 25.0));
+        // Unclassified, buy, buy (same price), sell.
+        assert_eq!(features.signed_volume(), -10);
+        assert_eq!(features.trade_intensity(NANOS_PER_SECOND), 3);
+        assert_eq!(features.trade_intensity(NANOS_PER_SECOND + 150), 2);
+    }
+
+    #[test]
+    fn test_order_flow_imbalance_and_microprice() {
+        let mut features = MarketFeatures::new(10);
+        features.on_top_of_book(Some((px(99), 10)), Some((px(101), 10)));
+        assert_eq!(features.order_flow_imbalance(), 0.0);
+        assert_eq!(features.microprice(), Some(100.0));
+
+        // Bid size grows by 5, then the ask improves with 2 lots.
+        features.on_best_level(Side::Bid, Some((px(99), 15)));
+        features.on_best_level(Side::Ask, Some((px(100), 2)));
+        assert_eq!(features.order_flow_imbalance(), 5.0 - 2.0);
+        assert_eq!(
+            features.microprice(),
+            Some((99.0 * 2.0 + 100.0 * 15.0) / 17.0)
+        );
+    }
+
+    #[test]
+    fn test_market_messages() {
+        let mut features = MarketFeatures::new(4);
+        let quote = |side, ticks, size| MarketMessage::Quote {
+            symbol: "ES".to_string(),
+            side,
+            price: px(ticks),
+            size,
+            timestamp: 0,
+        };
+        features.on_market_message(&quote(Side::Bid, 99, 30));
+        features.on_market_message(&quote(Side::Ask, 101, 10));
+        assert_eq!(features.microprice(), Some(100.5));
+        features.on_market_message(&MarketMessage::Trade {
+            symbol: "ES".to_string(),
+            price: px(101),
+            size: 4,
+            timestamp: 7,
+        });
+        assert_eq!(features.session_vwap(), Some(101.0));
+        assert_eq!(features.trade_intensity(7), 1);
+
+        features.on_market_message(&quote(Side::Ask, 101, 0));
+        assert_eq!(features.microprice(), None);
+        assert_eq!(features.order_flow_imbalance(), 30.0);
+    }
+}
@@ -4,29 +4,2228 @@
 //! # Key Concepts
 //! - On a buy order: match against the ask side.
 //! - On a sell order: match against the bid side.
-//! - Generate fills and possibly partial fills.
+//! - Price-time priority: best price first, and within a price level the oldest order first.
+//! - An order can fill partially across several levels; every fill is reported.
+//! - On an L3 (`MarketByOrder`) book fills name the resting order. On an L2 book the engine
+//!   trades against aggregated level size and `resting_order_id` is `None`.
+//! - `submit` enforces the order's type (see `order_types`): limit remainders rest, IOC and
+//!   market remainders are cancelled, FOK orders fill completely or are rejected, and
+//!   post-only orders that would cross are rejected.
+//! - Resting orders can be cancelled, amended down (keeps queue priority) or replaced. A
+//!   replace that changes the price or increases the size loses priority; a replace priced
+//!   through the opposite side trades immediately and rests any remainder.
+//! - Every request is answered with an ack event or `Rejected` with a `RejectReason`.
+//! - Orders may carry an account id. With self-trade prevention enabled, an incoming order
+//!   never trades against a resting order of the same account; the configured
+//!   `SelfTradePrevention` mode decides which side is cancelled and an event reports it.
+//! - In the `Auction` phase orders are collected without matching and the indicative
+//!   equilibrium (see `auction`) is published after every change; `uncross` then executes
+//!   everything that crosses at the single equilibrium price. Market orders are collected
+//!   too (market-on-open/close) and execute ahead of limit orders; whatever is left of them
+//!   after the uncross is cancelled. Self-trade prevention applies at the uncross, with the
+//!   later arrival treated as the incoming order.
+//! - Stop and stop-limit orders are held off-book (see `stop_book`) until the last trade price,
+//!   from the engine's own executions or `on_trade`, reaches their stop price. They then
+//!   enter matching as market or limit orders; cascades are released one stop at a time in
+//!   trigger-price order.
+//! - Hidden orders rest in an engine-owned book that is not published. At the same price,
+//!   displayed size trades before hidden size.
+//!
+//! - With `record_log` every command and its events go to an `EngineLog` for deterministic
+//!   replay (see `engine_log`). Book changes from outside matching go through the engine
+//!   (`apply_quote`, `add_order`, `load_book`) so that they are logged too.
 //!
 //! # Performance
 //! - Operations should be constant or logarithmic time.
 //! - Avoid locks: updates happen in a single-threaded context if possible.
+//! - Fills and events are written to buffers owned by the engine, so matching does not
+//!   allocate once warm.
+
+use std::collections::{HashMap, VecDeque};
+
+use common::price::Price;
+
+use super::auction::{equilibrium_with_market, Equilibrium};
+use super::book_snapshot::{BookSnapshot, SnapshotError};
+use super::engine_log::{EngineCommand, EngineLog};
+use super::order_book::{BookMode, OrderBook, OrderError, QueuePosition, RestingOrder, Side};
+use super::order_types::{AccountId, Order, OrderFlags, TimeInForce};
+use super::price_levels::{PriceLevels, TreeLevels};
+use super::stop_book::StopBook;
+
+/// A single execution between an incoming order and resting liquidity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fill {
+    /// Execution price (the resting level's price).
+    pub price: Price,
+    pub quantity: u64,
+    /// The resting order that traded, when the book tracks individual orders.
+    pub resting_order_id: Option<u64>,
+    /// Side of the incoming (aggressive) order.
+    pub aggressor: Side,
+}
+
+/// Why the engine refused an order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    ZeroQuantity,
+    DuplicateOrderId,
+    /// The combination of price, time-in-force and flags is not meaningful
+    /// (e.g. a post-only market order).
+    InvalidOrder,
+    /// A post-only order would have taken liquidity.
+    PostOnlyWouldCross,
+    /// A fill-or-kill order could not be filled completely.
+    FillOrKillUnfillable,
+    /// A reduce-only order would open or increase a position.
+    ReduceOnlyWouldIncrease,
+    /// IOC and FOK limit orders and FOK market orders cannot be collected by a call auction.
+    NotAllowedInAuction,
+    /// Cancel, amend or replace of an order that is not resting (filled, cancelled or never
+    /// seen, or the book does not track individual orders).
+    UnknownOrder,
+    /// An amend that does not reduce the quantity; use `replace` to increase it.
+    AmendNotReduction,
+}
+
+/// Whether orders match on arrival or are collected for an uncross.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradingPhase {
+    Continuous,
+    /// Call auction: orders rest without matching until `uncross`.
+    Auction,
+}
 
-use super::order_book::OrderBook;
+/// What to do when an incoming order would trade with a resting order of the same account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTradePrevention {
+    /// Cancel the rest of the incoming order.
+    CancelNewest,
+    /// Cancel the resting order and keep matching.
+    CancelOldest,
+    /// Cancel both the resting order and the rest of the incoming order.
+    CancelBoth,
+    /// Reduce both by the smaller quantity; whichever reaches zero is cancelled.
+    DecrementAndCancel,
+}
+
+/// Outcome notifications produced by `submit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineEvent {
+    Accepted {
+        order_id: u64,
+    },
+    Rejected {
+        order_id: u64,
+        reason: RejectReason,
+    },
+    /// A fill of the submitted order.
+    Fill(Fill),
+    /// The unfilled remainder now rests in the book at `price`.
+    Rested {
+        order_id: u64,
+        price: Price,
+        quantity: u64,
+    },
+    /// The unfilled remainder (or an expired or cancelled resting order) was cancelled.
+    Cancelled {
+        order_id: u64,
+        quantity: u64,
+    },
+    /// A trade between two orders of the same account was prevented. `cancelled` is the
+    /// quantity removed from the incoming order, `resting_cancelled` from the resting one.
+    SelfTradePrevented {
+        order_id: u64,
+        resting_order_id: u64,
+        cancelled: u64,
+        resting_cancelled: u64,
+    },
+    /// Indicative uncross price, volume and imbalance, published after each change to the
+    /// book while an auction is collecting orders.
+    Indicative(Equilibrium),
+    /// An execution at the auction uncross. Order ids are `None` on an L2 book.
+    AuctionTrade {
+        price: Price,
+        quantity: u64,
+        buy_order_id: Option<u64>,
+        sell_order_id: Option<u64>,
+    },
+    /// A held stop order triggered on `last_trade` and entered matching. Its fills and
+    /// `Rested`/`Cancelled` (or `Rejected`) follow.
+    StopTriggered {
+        order_id: u64,
+        last_trade: Price,
+    },
+    /// Acknowledges a quantity-down amend; the order keeps its queue position.
+    Amended {
+        order_id: u64,
+        quantity: u64,
+    },
+    /// Acknowledges a cancel/replace. `priority_kept` is false when the order moved to the
+    /// back of the queue. A replace that crosses is followed by its fills and `Rested`.
+    Replaced {
+        order_id: u64,
+        price: Price,
+        quantity: u64,
+        priority_kept: bool,
+    },
+}
 
 /// The `MatchEngine` struct manages the matching of orders in the order book.
-pub struct MatchEngine<'a> {
-    _order_book: &'a mut OrderBook, // Field is prefixed with `_` to suppress unused warning
+pub struct MatchEngine<'a, L = TreeLevels> {
+    order_book: &'a mut OrderBook<L>,
+    /// Resting hidden orders; never published.
+    hidden: OrderBook,
+    /// Net position per symbol, as set by the caller; used for reduce-only checks.
+    positions: HashMap<String, i64>,
+    /// Resting day orders, expired by `end_of_day`.
+    day_orders: Vec<(String, u64)>,
+    /// Account, flags and time-in-force of each individually tracked resting order; the
+    /// account is used for self-trade prevention and all three carry over a `replace`.
+    resting: HashMap<u64, RestingMeta>,
+    /// Arrival sequence of resting and auction market orders.
+    arrivals: u64,
+    /// Market orders collected by an auction, per symbol.
+    auction_market: HashMap<String, AuctionMarketOrders>,
+    self_trade_prevention: Option<SelfTradePrevention>,
+    phase: TradingPhase,
+    /// Reference price per symbol for auction tie-breaks (e.g. the previous close).
+    reference_prices: HashMap<String, Price>,
+    /// Stop orders waiting for their trigger, per symbol.
+    stops: HashMap<String, StopBook>,
+    /// Arrival sequence of stop orders, for deterministic trigger order.
+    stop_seq: u64,
+    last_trades: HashMap<String, Price>,
+    /// Command/event log, when recording.
+    log: Option<EngineLog>,
+    fills: Vec<Fill>,
+    events: Vec<EngineEvent>,
 }
 
-impl<'a> MatchEngine<'a> {
+impl<'a, L: PriceLevels> MatchEngine<'a, L> {
     /// Creates a new instance of `MatchEngine`.
-    pub fn new(order_book: &'a mut OrderBook) -> Self {
-        MatchEngine { _order_book: order_book }
+    pub fn new(order_book: &'a mut OrderBook<L>) -> Self {
+        MatchEngine {
+            order_book,
+            hidden: OrderBook::market_by_order(),
+            positions: HashMap::new(),
+            day_orders: Vec::new(),
+            resting: HashMap::new(),
+            arrivals: 0,
+            auction_market: HashMap::new(),
+            self_trade_prevention: None,
+            phase: TradingPhase::Continuous,
+            reference_prices: HashMap::new(),
+            stops: HashMap::new(),
+            stop_seq: 0,
+            last_trades: HashMap::new(),
+            log: None,
+            fills: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// The book the engine matches against.
+    pub fn order_book(&self) -> &OrderBook<L> {
+        self.order_book
+    }
+
+    /// Resting hidden orders.
+    pub fn hidden_book(&self) -> &OrderBook {
+        &self.hidden
+    }
+
+    /// Starts or stops recording every command and event to an `EngineLog`. The log opens
+    /// with the state the engine already holds, as commands: a `LoadBook` per symbol of the
+    /// visible and hidden books, then any phase, self-trade prevention, positions, reference
+    /// prices and last trade prices that were set. Held stop orders and the account, flags and
+    /// time-in-force of orders already resting are not captured, so start recording before
+    /// orders are submitted.
+    pub fn record_log(&mut self, enabled: bool) {
+        self.events.clear();
+        self.log = enabled.then(|| self.initial_log());
+    }
+
+    fn initial_log(&self) -> EngineLog {
+        let mut log = EngineLog::new(self.order_book.mode());
+        for symbol in self.order_book.symbols() {
+            log.push_command(EngineCommand::LoadBook {
+                hidden: false,
+                snapshot: self.order_book.snapshot(symbol),
+            });
+        }
+        for symbol in self.hidden.symbols() {
+            log.push_command(EngineCommand::LoadBook {
+                hidden: true,
+                snapshot: self.hidden.snapshot(symbol),
+            });
+        }
+        if self.phase != TradingPhase::Continuous {
+            log.push_command(EngineCommand::SetPhase(self.phase));
+        }
+        if self.self_trade_prevention.is_some() {
+            log.push_command(EngineCommand::SetSelfTradePrevention(
+                self.self_trade_prevention,
+            ));
+        }
+        // Sorted so that the same state always opens the log the same way.
+        let mut positions: Vec<_> = self.positions.iter().collect();
+        positions.sort_unstable();
+        for (symbol, &position) in positions {
+            log.push_command(EngineCommand::SetPosition {
+                symbol: symbol.clone(),
+                position,
+            });
+        }
+        let mut references: Vec<_> = self.reference_prices.iter().collect();
+        references.sort_unstable();
+        for (symbol, &price) in references {
+            log.push_command(EngineCommand::SetReferencePrice {
+                symbol: symbol.clone(),
+                price,
+            });
+        }
+        // Stops trigger off the last trade, so replay must see the same one.
+        let mut trades: Vec<_> = self.last_trades.iter().collect();
+        trades.sort_unstable();
+        for (symbol, &price) in trades {
+            log.push_command(EngineCommand::Trade {
+                symbol: symbol.clone(),
+                price,
+            });
+        }
+        log
+    }
+
+    /// Returns the log recorded so far, leaving an empty one in its place if recording.
+    pub fn take_log(&mut self) -> Option<EngineLog> {
+        let mode = self.order_book.mode();
+        let log = self.log.as_mut()?;
+        for event in self.events.drain(..) {
+            log.push_event(event);
+        }
+        Some(std::mem::replace(log, EngineLog::new(mode)))
+    }
+
+    /// Events produced by the last call.
+    pub fn events(&self) -> &[EngineEvent] {
+        &self.events
+    }
+
+    /// Starts handling a command: logs the previous command's events and this command, then
+    /// clears the event buffer.
+    fn begin(&mut self, command: impl FnOnce() -> EngineCommand) {
+        if let Some(log) = self.log.as_mut() {
+            for event in self.events.drain(..) {
+                log.push_event(event);
+            }
+            log.push_command(command());
+        }
+        self.events.clear();
+    }
+
+    /// Sets the net position (positive long, negative short) used by reduce-only orders.
+    pub fn set_position(&mut self, symbol: &str, position: i64) {
+        self.begin(|| EngineCommand::SetPosition {
+            symbol: symbol.to_string(),
+            position,
+        });
+        self.positions.insert(symbol.to_string(), position);
+    }
+
+    /// Enables self-trade prevention with `mode`, or disables it with `None` (the default).
+    pub fn set_self_trade_prevention(&mut self, mode: Option<SelfTradePrevention>) {
+        self.begin(|| EngineCommand::SetSelfTradePrevention(mode));
+        self.self_trade_prevention = mode;
+    }
+
+    pub fn phase(&self) -> TradingPhase {
+        self.phase
+    }
+
+    /// Switches between continuous matching and auction collection. Switching back to
+    /// `Continuous` does not uncross; call `uncross` for each symbol first. Market orders
+    /// the auction collected are then cancelled, and stops triggered by the uncross are
+    /// released.
+    pub fn set_phase(&mut self, phase: TradingPhase) -> &[EngineEvent] {
+        self.begin(|| EngineCommand::SetPhase(phase));
+        self.phase = phase;
+        if phase == TradingPhase::Continuous {
+            // Sorted so that replaying a log produces the same events.
+            let mut symbols: Vec<String> = self
+                .auction_market
+                .keys()
+                .chain(self.stops.keys())
+                .cloned()
+                .collect();
+            symbols.sort_unstable();
+            symbols.dedup();
+            for symbol in &symbols {
+                self.cancel_auction_market(symbol);
+                self.release_stops(symbol);
+            }
+        }
+        &self.events
+    }
+
+    /// Replaces `snapshot.symbol`'s book, or its hidden book with `hidden`, with the
+    /// snapshot. Restored orders carry no account, flags or time-in-force.
+    pub fn load_book(
+        &mut self,
+        snapshot: &BookSnapshot,
+        hidden: bool,
+    ) -> Result<(), SnapshotError> {
+        self.begin(|| EngineCommand::LoadBook {
+            hidden,
+            snapshot: snapshot.clone(),
+        });
+        if hidden {
+            self.hidden.restore(snapshot)
+        } else {
+            self.order_book.restore(snapshot)
+        }
+    }
+
+    /// Applies a market data quote to an L2 book without matching (see
+    /// `OrderBook::apply_quote`; fails with `WrongMode` on an L3 book).
+    pub fn apply_quote(
+        &mut self,
+        symbol: &str,
+        side: Side,
+        price: Price,
+        size: u64,
+    ) -> Result<(), OrderError> {
+        self.begin(|| EngineCommand::Quote {
+            symbol: symbol.to_string(),
+            side,
+            price,
+            size,
+        });
+        self.order_book.apply_quote(symbol, side, price, size)
+    }
+
+    /// Adds an order to an L3 book without matching, e.g. to seed it from a feed. The order
+    /// carries no account, flags or time-in-force.
+    pub fn add_order(
+        &mut self,
+        symbol: &str,
+        order_id: u64,
+        side: Side,
+        price: Price,
+        quantity: u64,
+    ) -> Result<(), OrderError> {
+        self.begin(|| EngineCommand::AddOrder {
+            symbol: symbol.to_string(),
+            order_id,
+            side,
+            price,
+            quantity,
+        });
+        self.order_book
+            .add_order(symbol, order_id, side, price, quantity)
+    }
+
+    /// Sets the reference price used to break auction ties.
+    pub fn set_reference_price(&mut self, symbol: &str, price: Price) {
+        self.begin(|| EngineCommand::SetReferencePrice {
+            symbol: symbol.to_string(),
+            price,
+        });
+        self.reference_prices.insert(symbol.to_string(), price);
+    }
+
+    /// Equilibrium price, volume and imbalance if `symbol` were uncrossed now.
+    pub fn indicative(&self, symbol: &str) -> Option<Equilibrium> {
+        let mut bids = self.order_book.levels(symbol, Side::Bid);
+        bids.extend(self.hidden.levels(symbol, Side::Bid));
+        let mut asks = self.order_book.levels(symbol, Side::Ask);
+        asks.extend(self.hidden.levels(symbol, Side::Ask));
+        let market = self.auction_market.get(symbol).map_or((0, 0), |orders| {
+            (orders.quantity(Side::Bid), orders.quantity(Side::Ask))
+        });
+        equilibrium_with_market(
+            &bids,
+            &asks,
+            market,
+            self.reference_prices.get(symbol).copied(),
+        )
+    }
+
+    /// Executes every crossing order of `symbol` at the equilibrium price in one step:
+    /// market orders first, then limit orders in price-time priority on each side.
+    /// Unmatched limit orders stay in the book; unmatched market orders are cancelled.
+    pub fn uncross(&mut self, symbol: &str) -> &[EngineEvent] {
+        self.begin(|| EngineCommand::Uncross {
+            symbol: symbol.to_string(),
+        });
+        let (price, mut remaining) = match self.indicative(symbol) {
+            Some(eq) => (eq.price, eq.volume),
+            None => (Price::ZERO, 0),
+        };
+        while remaining > 0 {
+            let (Some(bid), Some(ask)) = (
+                self.auction_front(symbol, Side::Bid),
+                self.auction_front(symbol, Side::Ask),
+            ) else {
+                break;
+            };
+            // Prevented self-trades can leave orders that no longer reach the price.
+            if !crosses(Side::Bid, price, bid.limit) || !crosses(Side::Ask, price, ask.limit) {
+                break;
+            }
+            if let (Some(mode), Some(account)) = (self.self_trade_prevention, bid.account) {
+                if ask.account == Some(account) {
+                    self.prevent_uncross_self_trade(mode, symbol, bid, ask);
+                    continue;
+                }
+            }
+            let quantity = remaining.min(bid.quantity).min(ask.quantity);
+            self.take_auction_front(symbol, Side::Bid, bid, quantity);
+            self.take_auction_front(symbol, Side::Ask, ask, quantity);
+            remaining -= quantity;
+            self.set_last_trade(symbol, price);
+            self.events.push(EngineEvent::AuctionTrade {
+                price,
+                quantity,
+                buy_order_id: bid.order_id,
+                sell_order_id: ask.order_id,
+            });
+        }
+        self.cancel_auction_market(symbol);
+        self.release_stops(symbol);
+        &self.events
+    }
+
+    /// Matches `quantity` on `side` against the opposite side of `symbol`'s book in
+    /// price-time priority, removing the liquidity it takes. With a `limit`, only levels at
+    /// or better than the limit are taken (buy: `<= limit`, sell: `>= limit`); without one
+    /// the order sweeps until filled or the book is empty.
+    ///
+    /// Returns every fill, best price first. Any unfilled remainder is not rested.
+    pub fn match_order(
+        &mut self,
+        symbol: &str,
+        side: Side,
+        quantity: u64,
+        limit: Option<Price>,
+    ) -> &[Fill] {
+        self.begin(|| EngineCommand::MatchOrder {
+            symbol: symbol.to_string(),
+            side,
+            quantity,
+            limit,
+        });
+        self.sweep(symbol, side, quantity, limit, None);
+        &self.fills
+    }
+
+    /// Submits an order, enforcing its time-in-force and flags.
+    ///
+    /// Returns `Rejected`, or `Accepted` followed by any fills and prevented self-trades and
+    /// then `Rested` or `Cancelled` for the remainder.
+    pub fn submit(&mut self, order: &Order) -> &[EngineEvent] {
+        self.begin(|| EngineCommand::Submit(order.clone()));
+        match self.admit(order) {
+            Ok(_) if order.stop.is_some() => {
+                self.events.push(EngineEvent::Accepted {
+                    order_id: order.order_id,
+                });
+                self.stop_seq += 1;
+                self.stops
+                    .entry(order.symbol.clone())
+                    .or_default()
+                    .insert(self.stop_seq, order.clone());
+            }
+            Ok(quantity) => {
+                self.execute(order, quantity);
+                self.publish_indicative(&order.symbol);
+            }
+            Err(reason) => self.events.push(EngineEvent::Rejected {
+                order_id: order.order_id,
+                reason,
+            }),
+        }
+        self.release_stops(&order.symbol);
+        &self.events
+    }
+
+    /// Records a trade from the market data feed (e.g. `MarketMessage::Trade`) as the last
+    /// trade price of `symbol` and releases any stops it triggers.
+    pub fn on_trade(&mut self, symbol: &str, price: Price) -> &[EngineEvent] {
+        self.begin(|| EngineCommand::Trade {
+            symbol: symbol.to_string(),
+            price,
+        });
+        self.set_last_trade(symbol, price);
+        self.release_stops(symbol);
+        &self.events
+    }
+
+    /// Last trade price of `symbol`, from the engine's own executions or `on_trade`.
+    pub fn last_trade(&self, symbol: &str) -> Option<Price> {
+        self.last_trades.get(symbol).copied()
+    }
+
+    /// Number of stop orders waiting for their trigger.
+    pub fn pending_stops(&self, symbol: &str) -> usize {
+        self.stops.get(symbol).map_or(0, StopBook::len)
+    }
+
+    /// Cancels every resting day order and every held stop that is not good-till-cancelled
+    /// (a stop's IOC or FOK only applies once it triggers). Day orders rested on an L2 book
+    /// are part of the aggregated level size and are not tracked individually.
+    pub fn end_of_day(&mut self) -> &[EngineEvent] {
+        self.begin(|| EngineCommand::EndOfDay);
+        // Sorted so that replaying a log produces the same events.
+        let mut stop_books: Vec<_> = self.stops.iter_mut().collect();
+        stop_books.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        let mut expired = Vec::new();
+        for (_, stops) in stop_books {
+            expired.extend(stops.remove_where(|order| order.tif != TimeInForce::Gtc));
+        }
+        for order in expired {
+            self.events.push(EngineEvent::Cancelled {
+                order_id: order.order_id,
+                quantity: order.quantity,
+            });
+        }
+        for (symbol, order_id) in self.day_orders.drain(..) {
+            // The order may have traded away and its id been reused by a non-day order.
+            if self
+                .resting
+                .get(&order_id)
+                .is_none_or(|meta| meta.tif != TimeInForce::Day)
+            {
+                continue;
+            }
+            let deleted = match self.hidden.delete_order(&symbol, order_id) {
+                Err(OrderError::UnknownOrder) => self.order_book.delete_order(&symbol, order_id),
+                other => other,
+            };
+            if let Ok(order) = deleted {
+                self.resting.remove(&order_id);
+                self.events.push(EngineEvent::Cancelled {
+                    order_id,
+                    quantity: order.quantity,
+                });
+            }
+        }
+        &self.events
+    }
+
+    /// Cancels a resting order.
+    pub fn cancel(&mut self, symbol: &str, order_id: u64) -> &[EngineEvent] {
+        self.begin(|| EngineCommand::Cancel {
+            symbol: symbol.to_string(),
+            order_id,
+        });
+        if let Some(order) = self
+            .auction_market
+            .get_mut(symbol)
+            .and_then(|orders| orders.remove(order_id))
+        {
+            self.events.push(EngineEvent::Cancelled {
+                order_id,
+                quantity: order.quantity,
+            });
+            self.publish_indicative(symbol);
+            return &self.events;
+        }
+        if let Some(order) = self
+            .stops
+            .get_mut(symbol)
+            .and_then(|stops| stops.remove(order_id))
+        {
+            self.events.push(EngineEvent::Cancelled {
+                order_id,
+                quantity: order.quantity,
+            });
+            return &self.events;
+        }
+        let event = match self.locate(symbol, order_id) {
+            Some((_, hidden)) => {
+                let book_result = if hidden {
+                    self.hidden.delete_order(symbol, order_id)
+                } else {
+                    self.order_book.delete_order(symbol, order_id)
+                };
+                match book_result {
+                    Ok(order) => {
+                        self.resting.remove(&order_id);
+                        EngineEvent::Cancelled {
+                            order_id,
+                            quantity: order.quantity,
+                        }
+                    }
+                    Err(_) => unknown_order(order_id),
+                }
+            }
+            None => unknown_order(order_id),
+        };
+        self.events.push(event);
+        self.publish_indicative(symbol);
+        &self.events
+    }
+
+    /// Reduces a resting order to `quantity`, keeping its queue position.
+    pub fn amend(&mut self, symbol: &str, order_id: u64, quantity: u64) -> &[EngineEvent] {
+        self.begin(|| EngineCommand::Amend {
+            symbol: symbol.to_string(),
+            order_id,
+            quantity,
+        });
+        let event = match self.locate(symbol, order_id) {
+            None => unknown_order(order_id),
+            Some(_) if quantity == 0 => EngineEvent::Rejected {
+                order_id,
+                reason: RejectReason::ZeroQuantity,
+            },
+            Some((resting, _)) if quantity >= resting.quantity => EngineEvent::Rejected {
+                order_id,
+                reason: RejectReason::AmendNotReduction,
+            },
+            Some((resting, hidden)) => {
+                let by = resting.quantity - quantity;
+                let book_result = if hidden {
+                    self.hidden.reduce_order(symbol, order_id, by)
+                } else {
+                    self.order_book.reduce_order(symbol, order_id, by)
+                };
+                match book_result {
+                    Ok(_) => EngineEvent::Amended { order_id, quantity },
+                    Err(_) => unknown_order(order_id),
+                }
+            }
+        };
+        self.events.push(event);
+        self.publish_indicative(symbol);
+        &self.events
+    }
+
+    /// Replaces a resting order's price and quantity.
+    ///
+    /// A reduction at the same price keeps queue priority; any other change moves the order
+    /// to the back of the queue at the new price. If the new price crosses the opposite side
+    /// the order trades first and only its remainder rests.
+    ///
+    /// The order keeps its account, flags and time-in-force: a post-only order is rejected
+    /// rather than crossing, and a reduce-only order is capped at the position.
+    pub fn replace(
+        &mut self,
+        symbol: &str,
+        order_id: u64,
+        price: Price,
+        quantity: u64,
+    ) -> &[EngineEvent] {
+        self.begin(|| EngineCommand::Replace {
+            symbol: symbol.to_string(),
+            order_id,
+            price,
+            quantity,
+        });
+        let Some((resting, hidden)) = self.locate(symbol, order_id) else {
+            self.events.push(unknown_order(order_id));
+            return &self.events;
+        };
+        let side = resting.side;
+        let meta = self.resting.get(&order_id).copied();
+        let flags = meta.map_or(OrderFlags::NONE, |meta| meta.flags);
+        let marketable = self.phase == TradingPhase::Continuous
+            && self
+                .best_resting(symbol, side.opposite())
+                .is_some_and(|(best, _)| crosses(side, best, Some(price)));
+        let reject = if quantity == 0 {
+            Some(RejectReason::ZeroQuantity)
+        } else if marketable && flags.contains(OrderFlags::POST_ONLY) {
+            Some(RejectReason::PostOnlyWouldCross)
+        } else if flags.contains(OrderFlags::REDUCE_ONLY) && self.reducible(symbol, side) == 0 {
+            Some(RejectReason::ReduceOnlyWouldIncrease)
+        } else {
+            None
+        };
+        if let Some(reason) = reject {
+            self.events.push(EngineEvent::Rejected { order_id, reason });
+            return &self.events;
+        }
+        let quantity = if flags.contains(OrderFlags::REDUCE_ONLY) {
+            quantity.min(self.reducible(symbol, side))
+        } else {
+            quantity
+        };
+
+        let priority_kept = price == resting.price && quantity <= resting.quantity;
+        if !priority_kept {
+            self.arrivals += 1;
+            if let Some(meta) = self.resting.get_mut(&order_id) {
+                meta.arrival = self.arrivals;
+            }
+        }
+        self.events.push(EngineEvent::Replaced {
+            order_id,
+            price,
+            quantity,
+            priority_kept,
+        });
+        if !marketable {
+            let book_result = if hidden {
+                self.hidden.modify_order(symbol, order_id, price, quantity)
+            } else {
+                self.order_book
+                    .modify_order(symbol, order_id, price, quantity)
+            };
+            debug_assert!(book_result.is_ok());
+            self.publish_indicative(symbol);
+            return &self.events;
+        }
+
+        if hidden {
+            let _ = self.hidden.delete_order(symbol, order_id);
+        } else {
+            let _ = self.order_book.delete_order(symbol, order_id);
+        }
+        let aggressor = meta
+            .and_then(|meta| meta.account)
+            .map(|account| (order_id, account));
+        let remaining = self.sweep(symbol, side, quantity, Some(price), aggressor);
+        if remaining == 0 {
+            self.resting.remove(&order_id);
+        } else {
+            self.rest_order(symbol, order_id, side, price, remaining, hidden);
+            self.events.push(EngineEvent::Rested {
+                order_id,
+                price,
+                quantity: remaining,
+            });
+        }
+        self.release_stops(symbol);
+        &self.events
+    }
+
+    fn set_last_trade(&mut self, symbol: &str, price: Price) {
+        match self.last_trades.get_mut(symbol) {
+            Some(last) => *last = price,
+            None => {
+                self.last_trades.insert(symbol.to_string(), price);
+            }
+        }
+    }
+
+    /// Releases triggered stops of `symbol` one at a time until none is triggered by the
+    /// current last trade; fills of a released stop can trigger further stops. Stops stay
+    /// held during an auction.
+    fn release_stops(&mut self, symbol: &str) {
+        if self.phase != TradingPhase::Continuous {
+            return;
+        }
+        loop {
+            let Some(last_trade) = self.last_trade(symbol) else {
+                return;
+            };
+            let Some(mut order) = self
+                .stops
+                .get_mut(symbol)
+                .and_then(|stops| stops.pop_triggered(last_trade))
+            else {
+                return;
+            };
+            order.stop = None;
+            self.events.push(EngineEvent::StopTriggered {
+                order_id: order.order_id,
+                last_trade,
+            });
+            match self.admit(&order) {
+                Ok(quantity) => self.work(&order, quantity),
+                Err(reason) => self.events.push(EngineEvent::Rejected {
+                    order_id: order.order_id,
+                    reason,
+                }),
+            }
+        }
+    }
+
+    /// Publishes the indicative uncross while an auction is collecting.
+    fn publish_indicative(&mut self, symbol: &str) {
+        if self.phase != TradingPhase::Auction {
+            return;
+        }
+        if let Some(eq) = self.indicative(symbol) {
+            self.events.push(EngineEvent::Indicative(eq));
+        }
+    }
+
+    /// Finds a resting order and whether it is in the hidden book.
+    fn locate(&self, symbol: &str, order_id: u64) -> Option<(RestingOrder, bool)> {
+        if let Some(order) = self.order_book.order(symbol, order_id) {
+            return Some((order, false));
+        }
+        self.hidden.order(symbol, order_id).map(|o| (o, true))
+    }
+
+    /// Validates `order` and returns the quantity to work.
+    fn admit(&self, order: &Order) -> Result<u64, RejectReason> {
+        let symbol = order.symbol.as_str();
+        if order.quantity == 0 {
+            return Err(RejectReason::ZeroQuantity);
+        }
+        if order.is_market() && order.flags.contains(OrderFlags::POST_ONLY) {
+            return Err(RejectReason::InvalidOrder);
+        }
+        if self.order_book.order(symbol, order.order_id).is_some()
+            || self.hidden.order(symbol, order.order_id).is_some()
+            || self
+                .stops
+                .get(symbol)
+                .is_some_and(|stops| stops.contains(order.order_id))
+            || self
+                .auction_market
+                .get(symbol)
+                .is_some_and(|orders| orders.contains(order.order_id))
+        {
+            return Err(RejectReason::DuplicateOrderId);
+        }
+        if order.stop.is_some() {
+            // Everything else is checked when the stop triggers.
+            return Ok(order.quantity);
+        }
+
+        let auction = self.phase == TradingPhase::Auction;
+        // Market orders wait for the uncross; IOC and FOK limits cannot, and nothing can
+        // promise a FOK market order a full fill before it.
+        let collectable = if order.is_market() {
+            order.tif != TimeInForce::Fok
+        } else {
+            order.tif.rests()
+        };
+        if auction && !collectable {
+            return Err(RejectReason::NotAllowedInAuction);
+        }
+
+        let mut quantity = order.quantity;
+        if order.flags.contains(OrderFlags::REDUCE_ONLY) {
+            let reducible = self.reducible(symbol, order.side);
+            if reducible == 0 {
+                return Err(RejectReason::ReduceOnlyWouldIncrease);
+            }
+            quantity = quantity.min(reducible);
+        }
+
+        if !auction
+            && order.flags.contains(OrderFlags::POST_ONLY)
+            && self
+                .best_resting(symbol, order.side.opposite())
+                .is_some_and(|(price, _)| crosses(order.side, price, order.limit))
+        {
+            return Err(RejectReason::PostOnlyWouldCross);
+        }
+        if order.tif == TimeInForce::Fok
+            && self.available(symbol, order.side, order.limit, order.account) < quantity
+        {
+            return Err(RejectReason::FillOrKillUnfillable);
+        }
+        Ok(quantity)
+    }
+
+    /// Quantity an order on `side` can trade without opening or increasing the position.
+    fn reducible(&self, symbol: &str, side: Side) -> u64 {
+        let position = self.positions.get(symbol).copied().unwrap_or(0);
+        match side {
+            Side::Bid if position < 0 => position.unsigned_abs(),
+            Side::Ask if position > 0 => position.unsigned_abs(),
+            _ => 0,
+        }
+    }
+
+    fn execute(&mut self, order: &Order, quantity: u64) {
+        self.events.push(EngineEvent::Accepted {
+            order_id: order.order_id,
+        });
+        self.work(order, quantity);
     }
 
-    /// Matches an order and returns a trade result.
-    /// Currently a placeholder implementation.
-    pub fn match_order(&mut self, _symbol: &str, _size: u64, _is_buy: bool) -> Option<(f64, u64)> {
-        // TODO: Implement the actual order matching logic
+    /// Matches an admitted order and rests or cancels the remainder.
+    fn work(&mut self, order: &Order, quantity: u64) {
+        let symbol = order.symbol.as_str();
+        let aggressor = order.account.map(|account| (order.order_id, account));
+        let remaining = match self.phase {
+            TradingPhase::Continuous => {
+                self.sweep(symbol, order.side, quantity, order.limit, aggressor)
+            }
+            TradingPhase::Auction if order.limit.is_none() => {
+                self.arrivals += 1;
+                let queued = AuctionMarketOrder {
+                    order_id: order.order_id,
+                    quantity,
+                    account: order.account,
+                    arrival: self.arrivals,
+                };
+                self.auction_market
+                    .entry(order.symbol.clone())
+                    .or_default()
+                    .side_mut(order.side)
+                    .push_back(queued);
+                return;
+            }
+            TradingPhase::Auction => quantity,
+        };
+        if remaining == 0 {
+            return;
+        }
+        match order.limit {
+            Some(price) if order.tif.rests() => {
+                self.rest(order, price, remaining);
+                self.events.push(EngineEvent::Rested {
+                    order_id: order.order_id,
+                    price,
+                    quantity: remaining,
+                });
+            }
+            _ => self.events.push(EngineEvent::Cancelled {
+                order_id: order.order_id,
+                quantity: remaining,
+            }),
+        }
+    }
+
+    fn rest(&mut self, order: &Order, price: Price, quantity: u64) {
+        let tracked = self.rest_order(
+            &order.symbol,
+            order.order_id,
+            order.side,
+            price,
+            quantity,
+            order.flags.contains(OrderFlags::HIDDEN),
+        );
+        if !tracked {
+            return;
+        }
+        self.arrivals += 1;
+        self.resting.insert(
+            order.order_id,
+            RestingMeta {
+                account: order.account,
+                flags: order.flags,
+                tif: order.tif,
+                arrival: self.arrivals,
+            },
+        );
+        if order.tif == TimeInForce::Day {
+            self.day_orders.push((order.symbol.clone(), order.order_id));
+        }
+    }
+
+    /// Adds a resting order to the hidden or displayed book. Returns whether the order is
+    /// tracked individually (false on an L2 book, where it only adds to the level size).
+    fn rest_order(
+        &mut self,
+        symbol: &str,
+        order_id: u64,
+        side: Side,
+        price: Price,
+        quantity: u64,
+        hidden: bool,
+    ) -> bool {
+        if hidden {
+            self.hidden
+                .add_order(symbol, order_id, side, price, quantity)
+                .is_ok()
+        } else if self.order_book.mode() == BookMode::MarketByOrder {
+            self.order_book
+                .add_order(symbol, order_id, side, price, quantity)
+                .is_ok()
+        } else {
+            let size = self.order_book.level_size(symbol, side, price).unwrap_or(0);
+            self.order_book
+                .apply_quote(symbol, side, price, size + quantity)
+                .expect("an L2 book takes quotes");
+            false
+        }
+    }
+
+    /// Runs the matching loop, recording fills in `self.fills` and fill and self-trade events
+    /// in `self.events`. `aggressor` is the incoming order's id and account, used for
+    /// self-trade prevention. Returns the quantity left to rest or cancel.
+    fn sweep(
+        &mut self,
+        symbol: &str,
+        side: Side,
+        quantity: u64,
+        limit: Option<Price>,
+        aggressor: Option<(u64, AccountId)>,
+    ) -> u64 {
+        self.fills.clear();
+        let mut remaining = quantity;
+        while remaining > 0 {
+            let Some(consumed) = self.take_best(symbol, side, remaining, limit, aggressor) else {
+                break;
+            };
+            remaining -= consumed;
+        }
+        remaining
+    }
+
+    /// Best resting price on `side` across the displayed and hidden books, and whether it
+    /// comes from the hidden book. Displayed size wins ties.
+    fn best_resting(&self, symbol: &str, side: Side) -> Option<(Price, bool)> {
+        let (bid, ask) = self.order_book.best_bid_ask(symbol);
+        let visible = match side {
+            Side::Bid => bid,
+            Side::Ask => ask,
+        };
+        let hidden = self.hidden.best_order(symbol, side).map(|o| o.price);
+        match (visible, hidden) {
+            (Some((v, _)), Some(h)) => {
+                let hidden_better = match side {
+                    Side::Bid => h > v,
+                    Side::Ask => h < v,
+                };
+                Some(if hidden_better { (h, true) } else { (v, false) })
+            }
+            (Some((v, _)), None) => Some((v, false)),
+            (None, Some(h)) => Some((h, true)),
+            (None, None) => None,
+        }
+    }
+
+    /// Quantity available to an order of `account` on `side` at or better than `limit`.
+    ///
+    /// With self-trade prevention the account's own resting orders do not count:
+    /// `CancelOldest` cancels them and keeps matching, so only their quantity is left out;
+    /// the other modes take nothing past the first of them, so only the liquidity ahead of
+    /// it in priority order is available.
+    fn available(
+        &self,
+        symbol: &str,
+        side: Side,
+        limit: Option<Price>,
+        account: Option<AccountId>,
+    ) -> u64 {
+        let resting_side = side.opposite();
+        let depth = |limit: Price| {
+            self.order_book
+                .cumulative_depth(symbol, resting_side, limit)
+                + self.hidden.cumulative_depth(symbol, resting_side, limit)
+        };
+        let limit = limit.unwrap_or(match side {
+            Side::Bid => Price::MAX,
+            Side::Ask => Price::MIN,
+        });
+        let total = depth(limit);
+        let (Some(mode), Some(account)) = (self.self_trade_prevention, account) else {
+            return total;
+        };
+        let is_own = |order: &RestingOrder| {
+            self.resting
+                .get(&order.order_id)
+                .is_some_and(|meta| meta.account == Some(account))
+        };
+        if mode == SelfTradePrevention::CancelOldest {
+            let mut own = 0;
+            let mut add = |order: RestingOrder| {
+                if is_own(&order) {
+                    own += order.quantity;
+                }
+                true
+            };
+            self.order_book
+                .for_each_order_within(symbol, resting_side, limit, &mut add);
+            self.hidden
+                .for_each_order_within(symbol, resting_side, limit, &mut add);
+            return total - own;
+        }
+
+        // The first own order of each book, then the first of the two in priority order.
+        let first_own = |hidden: bool| {
+            let mut first = None;
+            let find = |order: RestingOrder| {
+                let own = is_own(&order);
+                if own {
+                    first = Some(order);
+                }
+                !own
+            };
+            if hidden {
+                self.hidden
+                    .for_each_order_within(symbol, resting_side, limit, find);
+            } else {
+                self.order_book
+                    .for_each_order_within(symbol, resting_side, limit, find);
+            }
+            first
+        };
+        let (visible, hidden) = (first_own(false), first_own(true));
+        let (first, hidden) = match (visible, hidden) {
+            (Some(v), Some(h))
+                if match resting_side {
+                    Side::Ask => v.price <= h.price,
+                    Side::Bid => v.price >= h.price,
+                } =>
+            {
+                (v, false)
+            }
+            (_, Some(h)) => (h, true),
+            (Some(v), None) => (v, false),
+            (None, None) => return total,
+        };
+
+        // Quantity ahead of an order at its price: displayed before hidden, then queue.
+        let ahead = |order: &RestingOrder, hidden: bool| -> u64 {
+            let queued = |position: Option<QueuePosition>| position.map_or(0, |p| p.quantity_ahead);
+            if hidden {
+                let displayed = self
+                    .order_book
+                    .level_size(symbol, resting_side, order.price)
+                    .unwrap_or(0);
+                displayed + queued(self.hidden.queue_position(symbol, order.order_id))
+            } else {
+                queued(self.order_book.queue_position(symbol, order.order_id))
+            }
+        };
+        let better = match resting_side {
+            Side::Ask => first.price.offset(-1),
+            Side::Bid => first.price.offset(1),
+        };
+        let better_depth = if first.price == better {
+            0
+        } else {
+            depth(better)
+        };
+        better_depth + ahead(&first, hidden)
+    }
+
+    /// Trades up to `quantity` against the front of the best opposite level if it is
+    /// marketable, or applies self-trade prevention if that order is ours. Returns how much
+    /// of the incoming quantity was consumed, or `None` when nothing is marketable.
+    fn take_best(
+        &mut self,
+        symbol: &str,
+        side: Side,
+        quantity: u64,
+        limit: Option<Price>,
+        aggressor: Option<(u64, AccountId)>,
+    ) -> Option<u64> {
+        let resting_side = side.opposite();
+        let front = self.front(symbol, resting_side)?;
+        if !crosses(side, front.price, limit) {
+            return None;
+        }
+
+        if let (Some(mode), Some((order_id, account)), Some(resting_order_id)) =
+            (self.self_trade_prevention, aggressor, front.order_id)
+        {
+            if self
+                .resting
+                .get(&resting_order_id)
+                .is_some_and(|meta| meta.account == Some(account))
+            {
+                return Some(self.prevent_self_trade(
+                    mode,
+                    symbol,
+                    order_id,
+                    resting_order_id,
+                    front,
+                    quantity,
+                ));
+            }
+        }
+        let taken = quantity.min(front.quantity);
+        self.take_front(symbol, resting_side, front, taken);
+        self.set_last_trade(symbol, front.price);
+        self.record_fill(Fill {
+            price: front.price,
+            quantity: taken,
+            resting_order_id: front.order_id,
+            aggressor: side,
+        });
+        Some(taken)
+    }
+
+    /// The liquidity that trades next on `side`: the front order of the best level, or the
+    /// whole level on an L2 book.
+    fn front(&self, symbol: &str, side: Side) -> Option<Front> {
+        let (price, hidden) = self.best_resting(symbol, side)?;
+        if !hidden && self.order_book.mode() == BookMode::MarketByPrice {
+            return Some(Front {
+                price,
+                hidden,
+                order_id: None,
+                quantity: self.order_book.level_size(symbol, side, price)?,
+            });
+        }
+        let order = if hidden {
+            self.hidden.best_order(symbol, side)?
+        } else {
+            self.order_book.best_order(symbol, side)?
+        };
+        Some(Front {
+            price,
+            hidden,
+            order_id: Some(order.order_id),
+            quantity: order.quantity,
+        })
+    }
+
+    /// Removes `quantity` of `front` from the book.
+    fn take_front(&mut self, symbol: &str, side: Side, front: Front, quantity: u64) {
+        match front.order_id {
+            Some(order_id) => self.reduce_resting(symbol, order_id, front.hidden, quantity),
+            None => self
+                .order_book
+                .apply_quote(symbol, side, front.price, front.quantity - quantity)
+                .expect("only L2 levels have no order id"),
+        }
+    }
+
+    /// The order that executes next on `side` at the uncross: collected market orders first,
+    /// then the book.
+    fn auction_front(&self, symbol: &str, side: Side) -> Option<AuctionFront> {
+        if let Some(order) = self
+            .auction_market
+            .get(symbol)
+            .and_then(|orders| orders.side(side).front())
+        {
+            return Some(AuctionFront {
+                order_id: Some(order.order_id),
+                limit: None,
+                quantity: order.quantity,
+                account: order.account,
+                arrival: order.arrival,
+                resting: None,
+            });
+        }
+        let front = self.front(symbol, side)?;
+        let meta = front.order_id.and_then(|id| self.resting.get(&id));
+        Some(AuctionFront {
+            order_id: front.order_id,
+            limit: Some(front.price),
+            quantity: front.quantity,
+            account: meta.and_then(|meta| meta.account),
+            arrival: meta.map_or(0, |meta| meta.arrival),
+            resting: Some(front),
+        })
+    }
+
+    /// Removes `quantity` of `front` from the book or the collected market orders.
+    fn take_auction_front(&mut self, symbol: &str, side: Side, front: AuctionFront, quantity: u64) {
+        if let Some(resting) = front.resting {
+            self.take_front(symbol, side, resting, quantity);
+            return;
+        }
+        if let Some(orders) = self.auction_market.get_mut(symbol) {
+            let queue = orders.side_mut(side);
+            if let Some(order) = queue.front_mut() {
+                order.quantity -= quantity;
+                if order.quantity == 0 {
+                    queue.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Applies `mode` to a bid and an ask of the same account meeting at the uncross. The
+    /// later arrival plays the incoming order.
+    fn prevent_uncross_self_trade(
+        &mut self,
+        mode: SelfTradePrevention,
+        symbol: &str,
+        bid: AuctionFront,
+        ask: AuctionFront,
+    ) {
+        let ((newer_side, newer), (older_side, older)) = if bid.arrival > ask.arrival {
+            ((Side::Bid, bid), (Side::Ask, ask))
+        } else {
+            ((Side::Ask, ask), (Side::Bid, bid))
+        };
+        let (cancelled, resting_cancelled) = match mode {
+            SelfTradePrevention::CancelNewest => (newer.quantity, 0),
+            SelfTradePrevention::CancelOldest => (0, older.quantity),
+            SelfTradePrevention::CancelBoth => (newer.quantity, older.quantity),
+            SelfTradePrevention::DecrementAndCancel => {
+                let overlap = newer.quantity.min(older.quantity);
+                (overlap, overlap)
+            }
+        };
+        if cancelled > 0 {
+            self.take_auction_front(symbol, newer_side, newer, cancelled);
+        }
+        if resting_cancelled > 0 {
+            self.take_auction_front(symbol, older_side, older, resting_cancelled);
+        }
+        self.events.push(EngineEvent::SelfTradePrevented {
+            order_id: newer.order_id.unwrap_or_default(),
+            resting_order_id: older.order_id.unwrap_or_default(),
+            cancelled,
+            resting_cancelled,
+        });
+    }
+
+    /// Cancels the market orders collected for `symbol` that did not execute, in arrival
+    /// order.
+    fn cancel_auction_market(&mut self, symbol: &str) {
+        let Some(orders) = self.auction_market.remove(symbol) else {
+            return;
+        };
+        let mut left: Vec<_> = orders.bids.into_iter().chain(orders.asks).collect();
+        left.sort_unstable_by_key(|order| order.arrival);
+        for order in left {
+            self.events.push(EngineEvent::Cancelled {
+                order_id: order.order_id,
+                quantity: order.quantity,
+            });
+        }
+    }
+
+    /// Applies `mode` to an incoming order that would trade with a resting order of the same
+    /// account. Returns the incoming quantity cancelled.
+    fn prevent_self_trade(
+        &mut self,
+        mode: SelfTradePrevention,
+        symbol: &str,
+        order_id: u64,
+        resting_order_id: u64,
+        resting: Front,
+        quantity: u64,
+    ) -> u64 {
+        let (cancelled, resting_cancelled) = match mode {
+            SelfTradePrevention::CancelNewest => (quantity, 0),
+            SelfTradePrevention::CancelOldest => (0, resting.quantity),
+            SelfTradePrevention::CancelBoth => (quantity, resting.quantity),
+            SelfTradePrevention::DecrementAndCancel => {
+                let overlap = quantity.min(resting.quantity);
+                (overlap, overlap)
+            }
+        };
+        if resting_cancelled > 0 {
+            self.reduce_resting(symbol, resting_order_id, resting.hidden, resting_cancelled);
+        }
+        self.events.push(EngineEvent::SelfTradePrevented {
+            order_id,
+            resting_order_id,
+            cancelled,
+            resting_cancelled,
+        });
+        cancelled
+    }
+
+    /// Reduces a resting order, forgetting its owner once it is gone.
+    fn reduce_resting(&mut self, symbol: &str, order_id: u64, hidden: bool, by: u64) {
+        let book_result = if hidden {
+            self.hidden.reduce_order(symbol, order_id, by)
+        } else {
+            self.order_book.reduce_order(symbol, order_id, by)
+        };
+        if book_result == Ok(0) {
+            self.resting.remove(&order_id);
+        }
+    }
+
+    fn record_fill(&mut self, fill: Fill) {
+        self.fills.push(fill);
+        self.events.push(EngineEvent::Fill(fill));
+    }
+}
+
+/// Order attributes the books do not store.
+#[derive(Debug, Clone, Copy)]
+struct RestingMeta {
+    account: Option<AccountId>,
+    flags: OrderFlags,
+    tif: TimeInForce,
+    /// Arrival sequence, renewed when a replace loses priority.
+    arrival: u64,
+}
+
+/// A market order collected by an auction.
+#[derive(Debug, Clone, Copy)]
+struct AuctionMarketOrder {
+    order_id: u64,
+    quantity: u64,
+    account: Option<AccountId>,
+    arrival: u64,
+}
+
+/// Market orders collected for one symbol, in arrival order per side.
+#[derive(Debug, Default)]
+struct AuctionMarketOrders {
+    bids: VecDeque<AuctionMarketOrder>,
+    asks: VecDeque<AuctionMarketOrder>,
+}
+
+impl AuctionMarketOrders {
+    fn side(&self, side: Side) -> &VecDeque<AuctionMarketOrder> {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut VecDeque<AuctionMarketOrder> {
+        match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        }
+    }
+
+    fn quantity(&self, side: Side) -> u64 {
+        self.side(side).iter().map(|order| order.quantity).sum()
+    }
+
+    fn contains(&self, order_id: u64) -> bool {
+        self.bids
+            .iter()
+            .chain(&self.asks)
+            .any(|order| order.order_id == order_id)
+    }
+
+    fn remove(&mut self, order_id: u64) -> Option<AuctionMarketOrder> {
+        for queue in [&mut self.bids, &mut self.asks] {
+            if let Some(index) = queue.iter().position(|order| order.order_id == order_id) {
+                return queue.remove(index);
+            }
+        }
         None
     }
-}
\ No newline at end of file
+}
+
+/// Next order to execute on one side at the uncross.
+#[derive(Debug, Clone, Copy)]
+struct AuctionFront {
+    order_id: Option<u64>,
+    /// `None` for a market order.
+    limit: Option<Price>,
+    quantity: u64,
+    account: Option<AccountId>,
+    arrival: u64,
+    /// Book entry of a resting order; `None` for a collected market order.
+    resting: Option<Front>,
+}
+
+/// Next resting liquidity on one side of the book.
+#[derive(Debug, Clone, Copy)]
+struct Front {
+    price: Price,
+    hidden: bool,
+    /// `None` for an aggregated L2 level.
+    order_id: Option<u64>,
+    quantity: u64,
+}
+
+fn unknown_order(order_id: u64) -> EngineEvent {
+    EngineEvent::Rejected {
+        order_id,
+        reason: RejectReason::UnknownOrder,
+    }
+}
+
+/// Whether an order on `side` with `limit` can trade against a resting level at `price`.
+#[inline]
+fn crosses(side: Side, price: Price, limit: Option<Price>) -> bool {
+    match (side, limit) {
+        (_, None) => true,
+        (Side::Bid, Some(limit)) => price <= limit,
+        (Side::Ask, Some(limit)) => price >= limit,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(ticks: i64) -> Price {
+        Price::from_ticks(ticks)
+    }
+
+    #[test]
+    fn test_fills_in_price_time_priority() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 1, Side::Ask, px(101), 5).unwrap();
+        book.add_order("ES", 2, Side::Ask, px(100), 3).unwrap();
+        book.add_order("ES", 3, Side::Ask, px(100), 4).unwrap();
+
+        let mut engine = MatchEngine::new(&mut book);
+        let fills = engine.match_order("ES", Side::Bid, 9, None).to_vec();
+        assert_eq!(
+            fills,
+            vec![
+                Fill {
+                    price: px(100),
+                    quantity: 3,
+                    resting_order_id: Some(2),
+                    aggressor: Side::Bid
+                },
+                Fill {
+                    price: px(100),
+                    quantity: 4,
+                    resting_order_id: Some(3),
+                    aggressor: Side::Bid
+                },
+                Fill {
+                    price: px(101),
+                    quantity: 2,
+                    resting_order_id: Some(1),
+                    aggressor: Side::Bid
+                },
+            ]
+        );
+        assert_eq!(book.order("ES", 1).map(|o| o.quantity), Some(3));
+        assert_eq!(book.best_bid_ask("ES").1, Some((px(101), 3)));
+    }
+
+    #[test]
+    fn test_limit_stops_matching() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 1, Side::Bid, px(100), 5).unwrap();
+        book.add_order("ES", 2, Side::Bid, px(99), 5).unwrap();
+
+        let mut engine = MatchEngine::new(&mut book);
+        let fills = engine
+            .match_order("ES", Side::Ask, 8, Some(px(100)))
+            .to_vec();
+        assert_eq!(fills.len(), 1);
+        assert_eq!((fills[0].price, fills[0].quantity), (px(100), 5));
+        assert!(engine
+            .match_order("ES", Side::Ask, 1, Some(px(100)))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_matches_l2_levels_without_order_ids() {
+        let mut book = OrderBook::new();
+        book.apply_quote("ES", Side::Ask, px(100), 2).unwrap();
+        book.apply_quote("ES", Side::Ask, px(101), 2).unwrap();
+
+        let mut engine = MatchEngine::new(&mut book);
+        let fills = engine.match_order("ES", Side::Bid, 3, None).to_vec();
+        assert_eq!(fills.iter().map(|f| f.quantity).sum::<u64>(), 3);
+        assert!(fills.iter().all(|f| f.resting_order_id.is_none()));
+        assert_eq!(book.best_bid_ask("ES").1, Some((px(101), 1)));
+    }
+
+    #[test]
+    fn test_limit_remainder_rests_and_ioc_cancels() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 1, Side::Ask, px(100), 2).unwrap();
+
+        let mut engine = MatchEngine::new(&mut book);
+        let events = engine
+            .submit(&Order::limit(10, "ES", Side::Bid, px(100), 5))
+            .to_vec();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[1], EngineEvent::Fill(f) if f.quantity == 2));
+        assert_eq!(
+            events[2],
+            EngineEvent::Rested {
+                order_id: 10,
+                price: px(100),
+                quantity: 3
+            }
+        );
+
+        let ioc = Order::limit(11, "ES", Side::Ask, px(99), 4).with_tif(TimeInForce::Ioc);
+        let events = engine.submit(&ioc).to_vec();
+        assert_eq!(
+            events.last(),
+            Some(&EngineEvent::Cancelled {
+                order_id: 11,
+                quantity: 1
+            })
+        );
+        let market = Order::market(12, "ES", Side::Ask, 1);
+        assert!(matches!(
+            engine.submit(&market).last(),
+            Some(EngineEvent::Cancelled { quantity: 1, .. })
+        ));
+        assert_eq!(book.order_count("ES"), 0);
+    }
+
+    #[test]
+    fn test_fok_and_post_only_rejections() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 1, Side::Ask, px(100), 2).unwrap();
+        book.add_order("ES", 2, Side::Ask, px(101), 2).unwrap();
+
+        let mut engine = MatchEngine::new(&mut book);
+        let fok = Order::limit(10, "ES", Side::Bid, px(100), 3).with_tif(TimeInForce::Fok);
+        assert_eq!(
+            engine.submit(&fok),
+            [EngineEvent::Rejected {
+                order_id: 10,
+                reason: RejectReason::FillOrKillUnfillable
+            }]
+        );
+        let fok = Order::market(11, "ES", Side::Bid, 4).with_tif(TimeInForce::Fok);
+        assert_eq!(engine.submit(&fok).len(), 3);
+
+        engine.submit(&Order::limit(12, "ES", Side::Ask, px(102), 1));
+        let post = Order::limit(13, "ES", Side::Bid, px(102), 1).with_flags(OrderFlags::POST_ONLY);
+        assert!(matches!(
+            engine.submit(&post),
+            [EngineEvent::Rejected {
+                reason: RejectReason::PostOnlyWouldCross,
+                ..
+            }]
+        ));
+        let post = Order::limit(14, "ES", Side::Bid, px(101), 1).with_flags(OrderFlags::POST_ONLY);
+        assert!(matches!(
+            engine.submit(&post).last(),
+            Some(EngineEvent::Rested { .. })
+        ));
+    }
+
+    #[test]
+    fn test_hidden_trades_after_displayed_and_day_orders_expire() {
+        let mut book = OrderBook::market_by_order();
+        let mut engine = MatchEngine::new(&mut book);
+        let hidden = Order::limit(1, "ES", Side::Ask, px(100), 5)
+            .with_tif(TimeInForce::Gtc)
+            .with_flags(OrderFlags::HIDDEN);
+        engine.submit(&hidden);
+        engine.submit(&Order::limit(2, "ES", Side::Ask, px(100), 5));
+        assert_eq!(
+            engine.order_book().level_size("ES", Side::Ask, px(100)),
+            Some(5)
+        );
+
+        let fills: Vec<_> = engine
+            .submit(&Order::market(3, "ES", Side::Bid, 7))
+            .iter()
+            .filter_map(|e| match e {
+                EngineEvent::Fill(f) => Some((f.resting_order_id, f.quantity)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fills, vec![(Some(2), 5), (Some(1), 2)]);
+
+        engine.submit(&Order::limit(4, "ES", Side::Bid, px(90), 1));
+        assert_eq!(
+            engine.end_of_day(),
+            [EngineEvent::Cancelled {
+                order_id: 4,
+                quantity: 1
+            }]
+        );
+        assert_eq!(
+            engine.hidden_book().order("ES", 1).map(|o| o.quantity),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_reduce_only_caps_at_position() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 1, Side::Bid, px(100), 10).unwrap();
+        let mut engine = MatchEngine::new(&mut book);
+
+        let sell = Order::market(2, "ES", Side::Ask, 5).with_flags(OrderFlags::REDUCE_ONLY);
+        assert!(matches!(
+            engine.submit(&sell),
+            [EngineEvent::Rejected {
+                reason: RejectReason::ReduceOnlyWouldIncrease,
+                ..
+            }]
+        ));
+        engine.set_position("ES", 3);
+        let sell = Order::market(3, "ES", Side::Ask, 5).with_flags(OrderFlags::REDUCE_ONLY);
+        assert!(matches!(
+            engine.submit(&sell),
+            [
+                EngineEvent::Accepted { .. },
+                EngineEvent::Fill(Fill { quantity: 3, .. })
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_cancel_and_amend() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 1, Side::Bid, px(100), 5).unwrap();
+        book.add_order("ES", 2, Side::Bid, px(100), 5).unwrap();
+        let mut engine = MatchEngine::new(&mut book);
+
+        assert_eq!(
+            engine.amend("ES", 2, 5),
+            [EngineEvent::Rejected {
+                order_id: 2,
+                reason: RejectReason::AmendNotReduction
+            }]
+        );
+        assert_eq!(
+            engine.amend("ES", 2, 3),
+            [EngineEvent::Amended {
+                order_id: 2,
+                quantity: 3
+            }]
+        );
+        assert_eq!(
+            engine.cancel("ES", 1),
+            [EngineEvent::Cancelled {
+                order_id: 1,
+                quantity: 5
+            }]
+        );
+        assert_eq!(engine.cancel("ES", 1), [unknown_order(1)]);
+        assert_eq!(book.order("ES", 2).map(|o| o.quantity), Some(3));
+    }
+
+    #[test]
+    fn test_replace_priority_rules() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 1, Side::Bid, px(100), 5).unwrap();
+        book.add_order("ES", 2, Side::Bid, px(100), 5).unwrap();
+        let mut engine = MatchEngine::new(&mut book);
+
+        assert!(matches!(
+            engine.replace("ES", 1, px(100), 4),
+            [EngineEvent::Replaced {
+                priority_kept: true,
+                ..
+            }]
+        ));
+        assert_eq!(
+            engine
+                .order_book()
+                .best_order("ES", Side::Bid)
+                .map(|o| o.order_id),
+            Some(1)
+        );
+        assert!(matches!(
+            engine.replace("ES", 1, px(100), 6),
+            [EngineEvent::Replaced {
+                priority_kept: false,
+                ..
+            }]
+        ));
+        assert_eq!(
+            engine
+                .order_book()
+                .best_order("ES", Side::Bid)
+                .map(|o| o.order_id),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_crossing_replace_trades() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 1, Side::Bid, px(99), 5).unwrap();
+        book.add_order("ES", 2, Side::Ask, px(101), 2).unwrap();
+        let mut engine = MatchEngine::new(&mut book);
+
+        let events = engine.replace("ES", 1, px(101), 5).to_vec();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(
+            events[1],
+            EngineEvent::Fill(Fill {
+                resting_order_id: Some(2),
+                quantity: 2,
+                ..
+            })
+        ));
+        assert_eq!(
+            events[2],
+            EngineEvent::Rested {
+                order_id: 1,
+                price: px(101),
+                quantity: 3
+            }
+        );
+        assert_eq!(book.best_bid_ask("ES"), (Some((px(101), 3)), None));
+    }
+
+    #[test]
+    fn test_replace_keeps_order_attributes() {
+        let mut book = OrderBook::market_by_order();
+        let mut engine = MatchEngine::new(&mut book);
+        engine.submit(&Order::limit(1, "ES", Side::Ask, px(101), 2));
+        let post = Order::limit(2, "ES", Side::Bid, px(99), 5).with_flags(OrderFlags::POST_ONLY);
+        engine.submit(&post);
+
+        // A post-only order cannot be replaced through the spread.
+        assert_eq!(
+            engine.replace("ES", 2, px(101), 5),
+            [EngineEvent::Rejected {
+                order_id: 2,
+                reason: RejectReason::PostOnlyWouldCross
+            }]
+        );
+        assert_eq!(
+            engine.order_book().order("ES", 2).map(|o| o.price),
+            Some(px(99))
+        );
+        assert!(matches!(
+            engine.replace("ES", 2, px(100), 5),
+            [EngineEvent::Replaced { .. }]
+        ));
+
+        // Self-trade prevention still knows the account of a replaced order.
+        engine.set_self_trade_prevention(Some(SelfTradePrevention::CancelNewest));
+        engine.submit(&own_ask(3, 102, 1));
+        engine.submit(&own_bid(4, 98, 5));
+        let events = engine.replace("ES", 4, px(102), 5).to_vec();
+        assert!(matches!(
+            events[..],
+            [
+                EngineEvent::Replaced { .. },
+                EngineEvent::Fill(Fill { quantity: 2, .. }),
+                EngineEvent::SelfTradePrevented {
+                    resting_order_id: 3,
+                    cancelled: 3,
+                    ..
+                },
+            ]
+        ));
+
+        // Replaced day orders still expire; a gone order's entry does not.
+        engine.replace("ES", 2, px(100), 4);
+        engine.cancel("ES", 3);
+        engine.submit(&own_bid(5, 97, 1).with_tif(TimeInForce::Gtc));
+        assert_eq!(
+            engine.end_of_day(),
+            [EngineEvent::Cancelled {
+                order_id: 2,
+                quantity: 4
+            }]
+        );
+        assert_eq!(engine.order_book().order_count("ES"), 1);
+    }
+
+    fn own_bid(order_id: u64, price: i64, quantity: u64) -> Order {
+        Order::limit(order_id, "ES", Side::Bid, px(price), quantity).with_account(7)
+    }
+
+    fn own_ask(order_id: u64, price: i64, quantity: u64) -> Order {
+        Order::limit(order_id, "ES", Side::Ask, px(price), quantity).with_account(7)
+    }
+
+    #[test]
+    fn test_self_trade_cancel_newest_and_oldest() {
+        let mut book = OrderBook::market_by_order();
+        let mut engine = MatchEngine::new(&mut book);
+        engine.set_self_trade_prevention(Some(SelfTradePrevention::CancelNewest));
+        engine.submit(&own_ask(1, 100, 5));
+        engine.submit(&Order::limit(2, "ES", Side::Ask, px(100), 5));
+
+        let events = engine.submit(&own_bid(3, 100, 4)).to_vec();
+        assert_eq!(
+            events[1],
+            EngineEvent::SelfTradePrevented {
+                order_id: 3,
+                resting_order_id: 1,
+                cancelled: 4,
+                resting_cancelled: 0
+            }
+        );
+        assert_eq!(events.len(), 2);
+
+        engine.set_self_trade_prevention(Some(SelfTradePrevention::CancelOldest));
+        let events = engine.submit(&own_bid(4, 100, 4)).to_vec();
+        assert!(matches!(
+            events[1],
+            EngineEvent::SelfTradePrevented {
+                resting_order_id: 1,
+                resting_cancelled: 5,
+                ..
+            }
+        ));
+        assert!(matches!(
+            events[2],
+            EngineEvent::Fill(Fill {
+                resting_order_id: Some(2),
+                quantity: 4,
+                ..
+            })
+        ));
+        assert!(engine.order_book().order("ES", 1).is_none());
+    }
+
+    #[test]
+    fn test_self_trade_cancel_both_and_decrement() {
+        let mut book = OrderBook::market_by_order();
+        let mut engine = MatchEngine::new(&mut book);
+        engine.set_self_trade_prevention(Some(SelfTradePrevention::DecrementAndCancel));
+        engine.submit(&own_ask(1, 100, 5));
+
+        let events = engine.submit(&own_bid(2, 100, 3)).to_vec();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            engine.order_book().order("ES", 1).map(|o| o.quantity),
+            Some(2)
+        );
+
+        let events = engine.submit(&own_bid(3, 100, 6)).to_vec();
+        assert!(matches!(
+            events[1],
+            EngineEvent::SelfTradePrevented {
+                cancelled: 2,
+                resting_cancelled: 2,
+                ..
+            }
+        ));
+        assert_eq!(
+            events[2],
+            EngineEvent::Rested {
+                order_id: 3,
+                price: px(100),
+                quantity: 4
+            }
+        );
+
+        engine.set_self_trade_prevention(Some(SelfTradePrevention::CancelBoth));
+        let events = engine.submit(&own_ask(4, 99, 1)).to_vec();
+        assert_eq!(events.len(), 2);
+        assert_eq!(engine.order_book().order_count("ES"), 0);
+
+        engine.submit(&own_ask(5, 100, 1));
+        let other = Order::limit(6, "ES", Side::Bid, px(100), 1).with_account(8);
+        assert!(matches!(engine.submit(&other)[1], EngineEvent::Fill(_)));
+    }
+
+    #[test]
+    fn test_fok_excludes_own_liquidity() {
+        let mut book = OrderBook::market_by_order();
+        let mut engine = MatchEngine::new(&mut book);
+        engine.submit(&Order::limit(1, "ES", Side::Ask, px(100), 2));
+        engine.submit(&own_ask(2, 100, 3));
+        engine.submit(&Order::limit(3, "ES", Side::Ask, px(101), 4));
+        let fok = |order_id, quantity| own_bid(order_id, 101, quantity).with_tif(TimeInForce::Fok);
+        let unfillable = |order_id| {
+            [EngineEvent::Rejected {
+                order_id,
+                reason: RejectReason::FillOrKillUnfillable,
+            }]
+        };
+
+        // CancelNewest stops at the own order: only the 2 lots ahead of it can fill.
+        engine.set_self_trade_prevention(Some(SelfTradePrevention::CancelNewest));
+        assert_eq!(engine.submit(&fok(10, 3)), unfillable(10));
+        assert!(matches!(
+            engine.submit(&fok(11, 2))[1],
+            EngineEvent::Fill(_)
+        ));
+
+        // CancelOldest skips it: the 4 lots behind it count, its own 3 do not.
+        engine.submit(&Order::limit(4, "ES", Side::Ask, px(100), 2));
+        engine.set_self_trade_prevention(Some(SelfTradePrevention::CancelOldest));
+        assert_eq!(engine.submit(&fok(12, 7)), unfillable(12));
+        let events = engine.submit(&fok(13, 6)).to_vec();
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| matches!(e, EngineEvent::Fill(_)))
+                .count(),
+            2
+        );
+        assert_eq!(engine.order_book().order_count("ES"), 0);
+    }
+
+    #[test]
+    fn test_auction_collects_and_uncrosses() {
+        let mut book = OrderBook::market_by_order();
+        let mut engine = MatchEngine::new(&mut book);
+        engine.set_phase(TradingPhase::Auction);
+
+        engine.submit(&Order::limit(1, "ES", Side::Bid, px(102), 5));
+        let events = engine
+            .submit(&Order::limit(2, "ES", Side::Ask, px(100), 3))
+            .to_vec();
+        assert!(!events.iter().any(|e| matches!(e, EngineEvent::Fill(_))));
+        assert_eq!(
+            events.last(),
+            Some(&EngineEvent::Indicative(Equilibrium {
+                price: px(102),
+                volume: 3,
+                imbalance: 2
+            }))
+        );
+        let ioc = Order::limit(3, "ES", Side::Bid, px(102), 1).with_tif(TimeInForce::Ioc);
+        assert!(matches!(
+            engine.submit(&ioc),
+            [EngineEvent::Rejected {
+                reason: RejectReason::NotAllowedInAuction,
+                ..
+            }]
+        ));
+        engine.submit(&Order::limit(4, "ES", Side::Ask, px(101), 4));
+
+        let trades = engine.uncross("ES").to_vec();
+        assert_eq!(
+            trades,
+            vec![
+                EngineEvent::AuctionTrade {
+                    price: px(101),
+                    quantity: 3,
+                    buy_order_id: Some(1),
+                    sell_order_id: Some(2)
+                },
+                EngineEvent::AuctionTrade {
+                    price: px(101),
+                    quantity: 2,
+                    buy_order_id: Some(1),
+                    sell_order_id: Some(4)
+                },
+            ]
+        );
+        engine.set_phase(TradingPhase::Continuous);
+        assert_eq!(
+            engine.order_book().best_bid_ask("ES"),
+            (None, Some((px(101), 2)))
+        );
+    }
+
+    #[test]
+    fn test_auction_market_orders_self_trades_and_stops() {
+        let mut book = OrderBook::market_by_order();
+        let mut engine = MatchEngine::new(&mut book);
+        engine.set_self_trade_prevention(Some(SelfTradePrevention::CancelNewest));
+        engine.set_phase(TradingPhase::Auction);
+        engine.submit(&Order::limit(1, "ES", Side::Ask, px(100), 3));
+        engine.submit(&own_ask(2, 101, 2));
+        engine.submit(&Order::limit(6, "ES", Side::Ask, px(102), 1));
+        assert_eq!(
+            engine.submit(&Order::market(3, "ES", Side::Bid, 4)),
+            [
+                EngineEvent::Accepted { order_id: 3 },
+                EngineEvent::Indicative(Equilibrium {
+                    price: px(101),
+                    volume: 4,
+                    imbalance: -1
+                })
+            ]
+        );
+        engine.submit(&own_bid(4, 101, 2));
+        engine.submit(&Order::market(5, "ES", Side::Bid, 1).with_stop(px(101)));
+
+        // The market order executes first; the account's own bid and ask do not meet.
+        assert_eq!(
+            engine.uncross("ES"),
+            [
+                EngineEvent::AuctionTrade {
+                    price: px(101),
+                    quantity: 3,
+                    buy_order_id: Some(3),
+                    sell_order_id: Some(1)
+                },
+                EngineEvent::AuctionTrade {
+                    price: px(101),
+                    quantity: 1,
+                    buy_order_id: Some(3),
+                    sell_order_id: Some(2)
+                },
+                EngineEvent::SelfTradePrevented {
+                    order_id: 4,
+                    resting_order_id: 2,
+                    cancelled: 2,
+                    resting_cancelled: 0
+                },
+            ]
+        );
+        assert_eq!(engine.pending_stops("ES"), 1);
+
+        // The stop triggered by the uncross is released with continuous trading.
+        let events = engine.set_phase(TradingPhase::Continuous).to_vec();
+        assert_eq!(
+            events[0],
+            EngineEvent::StopTriggered {
+                order_id: 5,
+                last_trade: px(101)
+            }
+        );
+        assert!(matches!(
+            events[1],
+            EngineEvent::Fill(Fill {
+                resting_order_id: Some(2),
+                quantity: 1,
+                ..
+            })
+        ));
+
+        // Market orders an auction did not execute are cancelled.
+        engine.set_phase(TradingPhase::Auction);
+        engine.submit(&Order::market(7, "ES", Side::Ask, 2));
+        assert_eq!(
+            engine.set_phase(TradingPhase::Continuous),
+            [EngineEvent::Cancelled {
+                order_id: 7,
+                quantity: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn test_stop_cascade() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 1, Side::Ask, px(101), 1).unwrap();
+        book.add_order("ES", 2, Side::Ask, px(102), 1).unwrap();
+        book.add_order("ES", 3, Side::Ask, px(103), 5).unwrap();
+        let mut engine = MatchEngine::new(&mut book);
+
+        let stop_limit = Order::limit(10, "ES", Side::Bid, px(103), 2).with_stop(px(102));
+        let stop_market = Order::market(11, "ES", Side::Bid, 2).with_stop(px(101));
+        assert_eq!(
+            engine.submit(&stop_limit),
+            [EngineEvent::Accepted { order_id: 10 }]
+        );
+        engine.submit(&stop_market);
+        assert_eq!(engine.pending_stops("ES"), 2);
+        assert_eq!(engine.order_book().order_count("ES"), 3);
+
+        // A trade at 101 triggers the 101 stop, whose fill at 102 triggers the 102 stop.
+        let triggered: Vec<u64> = engine
+            .on_trade("ES", px(101))
+            .iter()
+            .filter_map(|e| match e {
+                EngineEvent::StopTriggered { order_id, .. } => Some(*order_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(triggered, vec![11, 10]);
+        assert_eq!(engine.pending_stops("ES"), 0);
+        assert_eq!(engine.last_trade("ES"), Some(px(103)));
+        assert_eq!(
+            engine.order_book().level_size("ES", Side::Ask, px(103)),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_stop_cancel_and_own_fill_trigger() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 1, Side::Bid, px(99), 5).unwrap();
+        let mut engine = MatchEngine::new(&mut book);
+
+        let sell_stop = Order::market(10, "ES", Side::Ask, 2).with_stop(px(99));
+        engine.submit(&sell_stop);
+        engine.submit(&Order::market(11, "ES", Side::Ask, 5).with_stop(px(90)));
+        assert_eq!(
+            engine.cancel("ES", 11),
+            [EngineEvent::Cancelled {
+                order_id: 11,
+                quantity: 5
+            }]
+        );
+
+        let events = engine
+            .submit(&Order::market(12, "ES", Side::Ask, 1))
+            .to_vec();
+        assert!(events.contains(&EngineEvent::StopTriggered {
+            order_id: 10,
+            last_trade: px(99)
+        }));
+        assert_eq!(
+            engine.order_book().level_size("ES", Side::Bid, px(99)),
+            Some(2)
+        );
+
+        // Held stops expire at the end of the day unless good-till-cancelled.
+        engine.submit(&Order::limit(13, "ES", Side::Ask, px(95), 3).with_stop(px(96)));
+        let gtc = Order::market(14, "ES", Side::Ask, 1).with_stop(px(90));
+        engine.submit(&gtc.with_tif(TimeInForce::Gtc));
+        assert_eq!(
+            engine.end_of_day(),
+            [EngineEvent::Cancelled {
+                order_id: 13,
+                quantity: 3
+            }]
+        );
+        assert_eq!(engine.pending_stops("ES"), 1);
+    }
+}
@@ -2,32 +2,1254 @@
 //! Maintains a low-latency limit order book. Updates as new quotes and trades arrive.
 //!
 //! # Design
-//! - Data structure should allow O(log n) or better updates.
+//! - One two-sided book per symbol. Each side is stored by a `PriceLevels` backend:
+//!   `TreeLevels` (`BTreeMap`, the default, O(log n)) or `PriceLadder` (flat array keyed by
+//!   tick offset, O(1) near the touch). Pick one with the `OrderBook<L>` type parameter.
+//! - Levels are keyed by fixed-point `Price` ticks, so lookups are exact.
 //! - Emphasis on minimal allocations and cache-friendly layouts.
 //!
-//! # Future Improvements
-//! - Consider specialized skip-lists or flat arrays keyed by price increments.
+//! # Modes
+//! - `BookMode::MarketByPrice` (L2): levels are set directly from quotes via `apply_quote`.
+//! - `BookMode::MarketByOrder` (L3): every resting order is tracked by `order_id` in FIFO order
+//!   inside its level. Level sizes are kept as the sum of their orders, so the L2 queries
+//!   (`best_bid_ask`, `level_size`, `levels`) work unchanged on an L3 book.
+//! - Depth queries (`top_levels`, `cumulative_depth`, `vwap_to_fill`, `imbalance`) walk
+//!   levels in place without allocating. `snapshot`/`restore` copy a whole symbol's book.
+//! - With `record_events(true)` every mutation records compact `BookEvent`s (level added,
+//!   changed, removed, top of book changed) for subscribers; see `book_events.rs`.
+//! - Orders in a level form an intrusive doubly linked list keyed by order id, so add, cancel
+//!   and execute are O(1) apart from the O(log n) level lookup.
 
-pub struct OrderBook {
-    // For simplicity, we store minimal info. In production: a price map (BTreeMap, RBTree, or custom structure).
-    // Could consider arrays indexed by price increments for constant-time lookups.
+use std::collections::{HashMap, HashSet};
+
+use common::price::Price;
+pub use common::side::Side;
+
+use super::book_events::{BookEvent, BookListener};
+use super::book_snapshot::{BookSnapshot, SnapshotError};
+use super::price_levels::{PriceLevels, TreeLevels};
+
+/// A price level as `(price, size)`.
+pub type Level = (Price, u64);
+
+/// How an `OrderBook` is maintained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookMode {
+    /// Aggregated price levels only (L2), updated with `apply_quote`.
+    MarketByPrice,
+    /// Individual orders (L3), updated with the order methods.
+    MarketByOrder,
+}
+
+/// Errors returned by the order-by-order (L3) operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderError {
+    /// An order with the same id is already resting for the symbol.
+    DuplicateOrderId,
+    /// No resting order with this id exists for the symbol.
+    UnknownOrder,
+    /// Orders must be added with a non-zero quantity.
+    ZeroQuantity,
+    /// The operation does not match the book's mode: order-by-order operations need a
+    /// `MarketByOrder` book and quotes a `MarketByPrice` book.
+    WrongMode,
+}
+
+/// A resting order as seen by callers of the L3 API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestingOrder {
+    pub order_id: u64,
+    pub side: Side,
+    pub price: Price,
+    pub quantity: u64,
+}
+
+/// Position of an order inside its price level's FIFO queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuePosition {
+    /// Number of orders ahead of this one at the same price.
+    pub orders_ahead: usize,
+    /// Total quantity ahead of this one at the same price.
+    pub quantity_ahead: u64,
+}
+
+/// A resting order linked into its level's FIFO queue.
+#[derive(Debug, Clone, Copy)]
+struct OrderNode {
+    side: Side,
+    price: Price,
+    quantity: u64,
+    prev: Option<u64>,
+    next: Option<u64>,
+}
+
+/// Two-sided book for a single symbol.
+#[derive(Debug)]
+struct SymbolBook<L> {
+    bids: L,
+    asks: L,
+    orders: HashMap<u64, OrderNode>,
+}
+
+impl<L: PriceLevels> SymbolBook<L> {
+    fn new() -> Self {
+        SymbolBook {
+            bids: L::new(Side::Bid),
+            asks: L::new(Side::Ask),
+            orders: HashMap::new(),
+        }
+    }
+
+    fn side(&self, side: Side) -> &L {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut L {
+        match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        }
+    }
+
+    fn best_bid(&self) -> Option<Level> {
+        self.bids.best().map(|(p, l)| (p, l.size))
+    }
+
+    fn best_ask(&self) -> Option<Level> {
+        self.asks.best().map(|(p, l)| (p, l.size))
+    }
+
+    /// Passes the mid price, if both sides have levels, on to the sides.
+    fn follow_mid(&mut self) {
+        if let (Some((bid, _)), Some((ask, _))) = (self.bids.best(), self.asks.best()) {
+            let mid = bid.offset((ask.ticks() - bid.ticks()) / 2);
+            self.bids.follow_mid(mid);
+            self.asks.follow_mid(mid);
+        }
+    }
+
+    /// Appends an order to the back of its level's queue.
+    fn push_order(&mut self, order_id: u64, side: Side, price: Price, quantity: u64) {
+        let level = match side {
+            Side::Bid => self.bids.get_or_insert(price),
+            Side::Ask => self.asks.get_or_insert(price),
+        };
+        let prev = level.tail;
+        match prev {
+            Some(tail) => {
+                if let Some(node) = self.orders.get_mut(&tail) {
+                    node.next = Some(order_id);
+                }
+            }
+            None => level.head = Some(order_id),
+        }
+        level.tail = Some(order_id);
+        level.size += quantity;
+        level.order_count += 1;
+        self.orders.insert(
+            order_id,
+            OrderNode {
+                side,
+                price,
+                quantity,
+                prev,
+                next: None,
+            },
+        );
+    }
+
+    /// Unlinks an order from its level and returns it; drops the level once empty.
+    fn unlink_order(&mut self, order_id: u64) -> Option<OrderNode> {
+        let node = self.orders.remove(&order_id)?;
+        if let Some(prev) = node.prev {
+            if let Some(p) = self.orders.get_mut(&prev) {
+                p.next = node.next;
+            }
+        }
+        if let Some(next) = node.next {
+            if let Some(n) = self.orders.get_mut(&next) {
+                n.prev = node.prev;
+            }
+        }
+
+        let levels = self.side_mut(node.side);
+        if let Some(level) = levels.get_mut(node.price) {
+            if level.head == Some(order_id) {
+                level.head = node.next;
+            }
+            if level.tail == Some(order_id) {
+                level.tail = node.prev;
+            }
+            level.size -= node.quantity;
+            level.order_count -= 1;
+            if level.order_count == 0 {
+                levels.remove(node.price);
+            }
+        }
+        Some(node)
+    }
+
+    /// Reduces an order in place, keeping its queue position. Removes it when nothing is left.
+    fn reduce_order(&mut self, order_id: u64, by: u64) -> Result<u64, OrderError> {
+        let node = self
+            .orders
+            .get_mut(&order_id)
+            .ok_or(OrderError::UnknownOrder)?;
+        let by = by.min(node.quantity);
+        if by == node.quantity {
+            self.unlink_order(order_id);
+            return Ok(0);
+        }
+        node.quantity -= by;
+        let (side, price, remaining) = (node.side, node.price, node.quantity);
+        if let Some(level) = self.side_mut(side).get_mut(price) {
+            level.size -= by;
+        }
+        Ok(remaining)
+    }
+}
+
+/// Limit order book holding one two-sided book per symbol, in either L2 or L3 mode.
+/// `L` selects the storage backend for each side.
+#[derive(Debug)]
+pub struct OrderBook<L = TreeLevels> {
+    mode: BookMode,
+    books: HashMap<String, SymbolBook<L>>,
+    record_events: bool,
+    events: Vec<BookEvent>,
+}
+
+/// Book state captured before a mutation, used to derive the events it caused.
+struct PendingEvents {
+    touched: [(Side, Price, Option<u64>); 2],
+    touched_len: usize,
+    top: (Option<Level>, Option<Level>),
+}
+
+impl Default for OrderBook {
+    fn default() -> Self {
+        OrderBook::new()
+    }
 }
 
 impl OrderBook {
+    /// Creates a price-level (L2) book with the tree backend.
     pub fn new() -> Self {
-        OrderBook {}
+        OrderBook::with_mode(BookMode::MarketByPrice)
+    }
+
+    /// Creates an order-by-order (L3) book with the tree backend.
+    pub fn market_by_order() -> Self {
+        OrderBook::with_mode(BookMode::MarketByOrder)
+    }
+}
+
+impl<L: PriceLevels> OrderBook<L> {
+    /// Creates a book in `mode`; use `OrderBook::<PriceLadder>::with_mode(..)` for the ladder.
+    pub fn with_mode(mode: BookMode) -> Self {
+        OrderBook {
+            mode,
+            books: HashMap::new(),
+            record_events: false,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> BookMode {
+        self.mode
+    }
+
+    /// Turns change-event recording on or off. Off by default.
+    pub fn record_events(&mut self, enabled: bool) {
+        self.record_events = enabled;
+        if !enabled {
+            self.events.clear();
+        }
+    }
+
+    /// Removes and returns the events recorded since the last drain.
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, BookEvent> {
+        self.events.drain(..)
+    }
+
+    /// Hands every recorded event to each listener, tagged with `symbol`, and clears them.
+    /// Call after each message so the events all refer to `symbol`.
+    pub fn publish_events(&mut self, symbol: &str, listeners: &mut [&mut dyn BookListener]) {
+        for event in self.events.drain(..) {
+            for listener in listeners.iter_mut() {
+                listener.on_book_event(symbol, &event);
+            }
+        }
+    }
+
+    /// Captures the touched levels and top of book before a mutation, if recording.
+    fn begin_events(&self, symbol: &str, touched: &[(Side, Price)]) -> Option<PendingEvents> {
+        if !self.record_events {
+            return None;
+        }
+        let mut pending = PendingEvents {
+            touched: [(Side::Bid, Price::ZERO, None); 2],
+            touched_len: 0,
+            top: self.best_bid_ask(symbol),
+        };
+        for &(side, price) in touched.iter().take(2) {
+            if pending.touched[..pending.touched_len]
+                .iter()
+                .any(|&(s, p, _)| s == side && p == price)
+            {
+                continue;
+            }
+            pending.touched[pending.touched_len] =
+                (side, price, self.level_size(symbol, side, price));
+            pending.touched_len += 1;
+        }
+        Some(pending)
+    }
+
+    /// Called after every change to `symbol`'s book: lets the sides follow the mid, then
+    /// compares the book with the state captured by `begin_events` and records the differences.
+    fn finish_events(&mut self, symbol: &str, pending: Option<PendingEvents>) {
+        if let Some(book) = self.books.get_mut(symbol) {
+            book.follow_mid();
+        }
+        let Some(pending) = pending else {
+            return;
+        };
+        for &(side, price, before) in &pending.touched[..pending.touched_len] {
+            let after = self.level_size(symbol, side, price);
+            let event = match (before, after) {
+                (None, Some(size)) => BookEvent::LevelAdded { side, price, size },
+                (Some(_), None) => BookEvent::LevelRemoved { side, price },
+                (Some(old), Some(size)) if old != size => {
+                    BookEvent::LevelChanged { side, price, size }
+                }
+                _ => continue,
+            };
+            self.events.push(event);
+        }
+        let (bid, ask) = self.best_bid_ask(symbol);
+        if (bid, ask) != pending.top {
+            self.events.push(BookEvent::TopOfBook { bid, ask });
+        }
+    }
+
+    /// Captures the touched levels for an order change, if recording.
+    fn begin_order_events(
+        &self,
+        symbol: &str,
+        order_id: u64,
+        new_price: Option<Price>,
+    ) -> Option<PendingEvents> {
+        if !self.record_events {
+            return None;
+        }
+        let node = self.books.get(symbol)?.orders.get(&order_id)?;
+        let new_price = new_price.unwrap_or(node.price);
+        self.begin_events(symbol, &[(node.side, node.price), (node.side, new_price)])
+    }
+
+    /// Records a `Reset` and the resulting top of book.
+    fn reset_events(&mut self, symbol: &str) {
+        if self.record_events {
+            let (bid, ask) = self.best_bid_ask(symbol);
+            self.events.push(BookEvent::Reset);
+            self.events.push(BookEvent::TopOfBook { bid, ask });
+        }
+    }
+
+    /// Returns the book for `symbol`, creating it on first use.
+    fn book_mut(&mut self, symbol: &str) -> &mut SymbolBook<L> {
+        // Avoid allocating the symbol key on the common path where the book already exists.
+        if !self.books.contains_key(symbol) {
+            self.books.insert(symbol.to_string(), SymbolBook::new());
+        }
+        self.books.get_mut(symbol).expect("book inserted above")
+    }
+
+    /// Updates the book with a new quote (price/size). Inserts or updates the price level;
+    /// a size of 0 removes the level.
+    ///
+    /// Fails with `WrongMode` on a `MarketByOrder` book, whose levels must stay the sum of
+    /// their orders.
+    pub fn apply_quote(
+        &mut self,
+        symbol: &str,
+        side: Side,
+        price: Price,
+        size: u64,
+    ) -> Result<(), OrderError> {
+        if self.mode != BookMode::MarketByPrice {
+            return Err(OrderError::WrongMode);
+        }
+        let pending = self.begin_events(symbol, &[(side, price)]);
+        if size == 0 {
+            if let Some(book) = self.books.get_mut(symbol) {
+                book.side_mut(side).remove(price);
+            }
+        } else {
+            let levels = self.book_mut(symbol).side_mut(side);
+            levels.get_or_insert(price).size = size;
+        }
+        self.finish_events(symbol, pending);
+        Ok(())
+    }
+
+    /// Returns the best bid and ask for `symbol`, if present.
+    pub fn best_bid_ask(&self, symbol: &str) -> (Option<Level>, Option<Level>) {
+        match self.books.get(symbol) {
+            Some(book) => (book.best_bid(), book.best_ask()),
+            None => (None, None),
+        }
+    }
+
+    /// Returns the size resting at `price` on `side`, if the level exists.
+    pub fn level_size(&self, symbol: &str, side: Side, price: Price) -> Option<u64> {
+        let book = self.books.get(symbol)?;
+        book.side(side).get(price).map(|l| l.size)
+    }
+
+    /// Number of price levels on `side` for `symbol`.
+    pub fn level_count(&self, symbol: &str, side: Side) -> usize {
+        self.books
+            .get(symbol)
+            .map_or(0, |book| book.side(side).len())
+    }
+
+    /// Aggregated L2 view of one side, best price first. In L3 mode the sizes are the sums
+    /// of the resting orders at each price.
+    pub fn levels(&self, symbol: &str, side: Side) -> Vec<Level> {
+        let Some(book) = self.books.get(symbol) else {
+            return Vec::new();
+        };
+        let mut levels = Vec::with_capacity(book.side(side).len());
+        book.side(side).visit(|p, l| {
+            levels.push((p, l.size));
+            true
+        });
+        levels
+    }
+
+    /// Symbols that have a book, sorted.
+    pub fn symbols(&self) -> Vec<&str> {
+        let mut symbols: Vec<&str> = self.books.keys().map(String::as_str).collect();
+        symbols.sort_unstable();
+        symbols
+    }
+
+    /// Drops every level (and order) for `symbol`.
+    pub fn clear(&mut self, symbol: &str) {
+        self.books.remove(symbol);
+        self.reset_events(symbol);
+    }
+
+    /// Fills `out` with the best levels on `side`, best first, and returns how many were written.
+    /// Does not allocate.
+    pub fn top_levels(&self, symbol: &str, side: Side, out: &mut [Level]) -> usize {
+        let Some(book) = self.books.get(symbol) else {
+            return 0;
+        };
+        let mut written = 0;
+        book.side(side).visit(|p, l| {
+            if written == out.len() {
+                return false;
+            }
+            out[written] = (p, l.size);
+            written += 1;
+            true
+        });
+        written
+    }
+
+    /// Total size on `side` at prices at or better than `limit`
+    /// (bids priced `>= limit`, asks priced `<= limit`).
+    pub fn cumulative_depth(&self, symbol: &str, side: Side, limit: Price) -> u64 {
+        let Some(book) = self.books.get(symbol) else {
+            return 0;
+        };
+        let mut total = 0;
+        book.side(side).visit(|p, l| {
+            let within = match side {
+                Side::Bid => p >= limit,
+                Side::Ask => p <= limit,
+            };
+            if within {
+                total += l.size;
+            }
+            within
+        });
+        total
+    }
+
+    /// Volume-weighted average price, in ticks, of filling `quantity` by sweeping `side`
+    /// (a buy sweeps `Side::Ask`). Returns `None` if the side cannot fill the full quantity.
+    pub fn vwap_to_fill(&self, symbol: &str, side: Side, quantity: u64) -> Option<f64> {
+        if quantity == 0 {
+            return None;
+        }
+        let book = self.books.get(symbol)?;
+        let mut remaining = quantity;
+        let mut notional: i128 = 0;
+        book.side(side).visit(|p, l| {
+            let take = remaining.min(l.size);
+            notional += p.ticks() as i128 * take as i128;
+            remaining -= take;
+            remaining > 0
+        });
+        (remaining == 0).then(|| notional as f64 / quantity as f64)
+    }
+
+    /// Book imbalance over the best `depth` levels per side:
+    /// `(bid_size - ask_size) / (bid_size + ask_size)`, in `[-1, 1]`.
+    /// Returns `None` when both sides are empty.
+    pub fn imbalance(&self, symbol: &str, depth: usize) -> Option<f64> {
+        let book = self.books.get(symbol)?;
+        let sum_side = |levels: &L| {
+            let mut total = 0u64;
+            let mut seen = 0;
+            levels.visit(|_, l| {
+                total += l.size;
+                seen += 1;
+                seen < depth
+            });
+            total
+        };
+        if depth == 0 {
+            return None;
+        }
+        let bid = sum_side(&book.bids) as f64;
+        let ask = sum_side(&book.asks) as f64;
+        let total = bid + ask;
+        (total > 0.0).then(|| (bid - ask) / total)
+    }
+
+    /// Takes a full snapshot of `symbol`'s book. In L3 mode the snapshot includes every
+    /// resting order in queue order.
+    pub fn snapshot(&self, symbol: &str) -> BookSnapshot {
+        let bids = self.levels(symbol, Side::Bid);
+        let asks = self.levels(symbol, Side::Ask);
+        let mut orders = Vec::new();
+        if self.mode == BookMode::MarketByOrder {
+            for (side, levels) in [(Side::Bid, &bids), (Side::Ask, &asks)] {
+                for &(price, _) in levels {
+                    self.for_each_order_at(symbol, side, price, |order_id, quantity| {
+                        orders.push(RestingOrder {
+                            order_id,
+                            side,
+                            price,
+                            quantity,
+                        });
+                    });
+                }
+            }
+        }
+        BookSnapshot {
+            symbol: symbol.to_string(),
+            mode: self.mode,
+            bids,
+            asks,
+            orders,
+        }
+    }
+
+    /// Replaces `snapshot.symbol`'s book with the snapshot contents. L3 books are rebuilt
+    /// from the orders (preserving queue order); L2 books from the levels, so an L3 snapshot
+    /// can seed an L2 book. An L2 snapshot cannot seed an L3 book.
+    ///
+    /// Orders are validated first: ids must be unique, quantities non-zero and, when the
+    /// snapshot carries levels, the orders must add up to them. On any error the book is
+    /// left unchanged.
+    pub fn restore(&mut self, snapshot: &BookSnapshot) -> Result<(), SnapshotError> {
+        let mode = self.mode;
+        if mode == BookMode::MarketByOrder && snapshot.mode == BookMode::MarketByPrice {
+            return Err(SnapshotError::ModeMismatch);
+        }
+        if mode == BookMode::MarketByOrder {
+            validate_orders(snapshot)?;
+        }
+        let book = self.book_mut(&snapshot.symbol);
+        *book = SymbolBook::new();
+        match mode {
+            BookMode::MarketByOrder => {
+                for order in &snapshot.orders {
+                    book.push_order(order.order_id, order.side, order.price, order.quantity);
+                }
+            }
+            BookMode::MarketByPrice => {
+                for (side, levels) in [(Side::Bid, &snapshot.bids), (Side::Ask, &snapshot.asks)] {
+                    for &(price, size) in levels.iter().filter(|(_, size)| *size > 0) {
+                        book.side_mut(side).get_or_insert(price).size = size;
+                    }
+                }
+            }
+        }
+        book.follow_mid();
+        self.reset_events(&snapshot.symbol);
+        Ok(())
+    }
+
+    /// Adds a new order to the back of the queue at its price. Fails with `WrongMode` on a
+    /// `MarketByPrice` book.
+    pub fn add_order(
+        &mut self,
+        symbol: &str,
+        order_id: u64,
+        side: Side,
+        price: Price,
+        quantity: u64,
+    ) -> Result<(), OrderError> {
+        if self.mode != BookMode::MarketByOrder {
+            return Err(OrderError::WrongMode);
+        }
+        if quantity == 0 {
+            return Err(OrderError::ZeroQuantity);
+        }
+        if self
+            .books
+            .get(symbol)
+            .is_some_and(|b| b.orders.contains_key(&order_id))
+        {
+            return Err(OrderError::DuplicateOrderId);
+        }
+        let pending = self.begin_events(symbol, &[(side, price)]);
+        self.book_mut(symbol)
+            .push_order(order_id, side, price, quantity);
+        self.finish_events(symbol, pending);
+        Ok(())
+    }
+
+    /// Modifies an order's price and/or quantity.
+    ///
+    /// A quantity reduction at the same price keeps queue priority. A price change or a
+    /// quantity increase moves the order to the back of the queue at the new price.
+    /// A quantity of 0 deletes the order.
+    pub fn modify_order(
+        &mut self,
+        symbol: &str,
+        order_id: u64,
+        price: Price,
+        quantity: u64,
+    ) -> Result<(), OrderError> {
+        if self.mode != BookMode::MarketByOrder {
+            return Err(OrderError::WrongMode);
+        }
+        let pending = self.begin_order_events(symbol, order_id, Some(price));
+        let book = self.books.get_mut(symbol).ok_or(OrderError::UnknownOrder)?;
+        let node = *book.orders.get(&order_id).ok_or(OrderError::UnknownOrder)?;
+        if quantity == 0 {
+            book.unlink_order(order_id);
+        } else if price == node.price && quantity <= node.quantity {
+            book.reduce_order(order_id, node.quantity - quantity)?;
+        } else {
+            book.unlink_order(order_id);
+            book.push_order(order_id, node.side, price, quantity);
+        }
+        self.finish_events(symbol, pending);
+        Ok(())
+    }
+
+    /// Reduces an order's quantity by `by`, keeping its queue position.
+    /// Returns the remaining quantity; the order is removed when it reaches 0.
+    pub fn reduce_order(
+        &mut self,
+        symbol: &str,
+        order_id: u64,
+        by: u64,
+    ) -> Result<u64, OrderError> {
+        let pending = self.begin_order_events(symbol, order_id, None);
+        let book = self.books.get_mut(symbol).ok_or(OrderError::UnknownOrder)?;
+        let remaining = book.reduce_order(order_id, by)?;
+        self.finish_events(symbol, pending);
+        Ok(remaining)
+    }
+
+    /// Records an execution of `quantity` against a resting order.
+    /// Returns the remaining quantity; the order is removed when fully filled.
+    pub fn execute_order(
+        &mut self,
+        symbol: &str,
+        order_id: u64,
+        quantity: u64,
+    ) -> Result<u64, OrderError> {
+        self.reduce_order(symbol, order_id, quantity)
     }
 
-    /// Updates the book with a new quote (price/size). Insert or update existing price level.
-    pub fn apply_quote(&mut self, _symbol: &str, _price: f64, _size: u64) {
-        // Pseudocode:
-        // 1. Find the price level entry
-        // 2. Update the size
-        // 3. If size=0, remove the level
+    /// Removes an order from the book and returns it.
+    pub fn delete_order(
+        &mut self,
+        symbol: &str,
+        order_id: u64,
+    ) -> Result<RestingOrder, OrderError> {
+        let pending = self.begin_order_events(symbol, order_id, None);
+        let book = self.books.get_mut(symbol).ok_or(OrderError::UnknownOrder)?;
+        let node = book
+            .unlink_order(order_id)
+            .ok_or(OrderError::UnknownOrder)?;
+        self.finish_events(symbol, pending);
+        Ok(RestingOrder {
+            order_id,
+            side: node.side,
+            price: node.price,
+            quantity: node.quantity,
+        })
     }
 
-    /// Returns the best bid and ask. In real code, return actual data.
-    pub fn best_bid_ask(&self, _symbol: &str) -> (Option<(f64, u64)>, Option<(f64, u64)>) {
-        (None, None)
+    /// Looks up a resting order.
+    pub fn order(&self, symbol: &str, order_id: u64) -> Option<RestingOrder> {
+        let node = self.books.get(symbol)?.orders.get(&order_id)?;
+        Some(RestingOrder {
+            order_id,
+            side: node.side,
+            price: node.price,
+            quantity: node.quantity,
+        })
+    }
+
+    /// Number of resting orders for `symbol`.
+    pub fn order_count(&self, symbol: &str) -> usize {
+        self.books.get(symbol).map_or(0, |book| book.orders.len())
+    }
+
+    /// Returns the FIFO position of an order inside its price level.
+    pub fn queue_position(&self, symbol: &str, order_id: u64) -> Option<QueuePosition> {
+        let book = self.books.get(symbol)?;
+        let mut node = book.orders.get(&order_id)?;
+        let mut position = QueuePosition {
+            orders_ahead: 0,
+            quantity_ahead: 0,
+        };
+        while let Some(prev) = node.prev {
+            node = &book.orders[&prev];
+            position.orders_ahead += 1;
+            position.quantity_ahead += node.quantity;
+        }
+        Some(position)
+    }
+
+    /// Calls `f` with `(order_id, quantity)` for each order at `price`, front of the queue first.
+    pub fn for_each_order_at(
+        &self,
+        symbol: &str,
+        side: Side,
+        price: Price,
+        mut f: impl FnMut(u64, u64),
+    ) {
+        let Some(book) = self.books.get(symbol) else {
+            return;
+        };
+        let mut cursor = book.side(side).get(price).and_then(|l| l.head);
+        while let Some(id) = cursor {
+            let node = &book.orders[&id];
+            f(id, node.quantity);
+            cursor = node.next;
+        }
+    }
+
+    /// Calls `f` with each order on `side` at or better than `limit`, in priority order (best
+    /// price first, then queue order), until `f` returns `false`. Does not allocate.
+    pub fn for_each_order_within(
+        &self,
+        symbol: &str,
+        side: Side,
+        limit: Price,
+        mut f: impl FnMut(RestingOrder) -> bool,
+    ) {
+        let Some(book) = self.books.get(symbol) else {
+            return;
+        };
+        book.side(side).visit(|price, level| {
+            let within = match side {
+                Side::Bid => price >= limit,
+                Side::Ask => price <= limit,
+            };
+            if !within {
+                return false;
+            }
+            let mut cursor = level.head;
+            while let Some(order_id) = cursor {
+                let node = &book.orders[&order_id];
+                let order = RestingOrder {
+                    order_id,
+                    side,
+                    price,
+                    quantity: node.quantity,
+                };
+                if !f(order) {
+                    return false;
+                }
+                cursor = node.next;
+            }
+            true
+        });
+    }
+
+    /// Order at the front of the queue at the best price on `side` (L3 only).
+    pub fn best_order(&self, symbol: &str, side: Side) -> Option<RestingOrder> {
+        let book = self.books.get(symbol)?;
+        let (price, level) = book.side(side).best()?;
+        let order_id = level.head?;
+        let node = &book.orders[&order_id];
+        Some(RestingOrder {
+            order_id,
+            side,
+            price,
+            quantity: node.quantity,
+        })
+    }
+}
+
+/// Checks that `snapshot.orders` can be linked into a book: unique ids, non-zero quantities
+/// and, if the snapshot has levels, per-level sums that match them.
+fn validate_orders(snapshot: &BookSnapshot) -> Result<(), SnapshotError> {
+    let mut sums: HashMap<(Side, Price), u64> = HashMap::new();
+    let mut ids = HashSet::with_capacity(snapshot.orders.len());
+    for order in &snapshot.orders {
+        let order_id = order.order_id;
+        if !ids.insert(order_id) {
+            return Err(SnapshotError::DuplicateOrder { order_id });
+        }
+        if order.quantity == 0 {
+            return Err(SnapshotError::InvalidOrder { order_id });
+        }
+        *sums.entry((order.side, order.price)).or_default() += order.quantity;
+    }
+    if snapshot.bids.is_empty() && snapshot.asks.is_empty() {
+        return Ok(());
+    }
+    let mut levels = 0;
+    for (side, side_levels) in [(Side::Bid, &snapshot.bids), (Side::Ask, &snapshot.asks)] {
+        for &(price, size) in side_levels.iter().filter(|(_, size)| *size > 0) {
+            levels += 1;
+            if sums.get(&(side, price)) != Some(&size) {
+                return Err(SnapshotError::LevelMismatch { side, price });
+            }
+        }
+    }
+    if levels != sums.len() {
+        // An order sits at a price the levels do not have.
+        let order = snapshot
+            .orders
+            .iter()
+            .find(|o| {
+                let side_levels = match o.side {
+                    Side::Bid => &snapshot.bids,
+                    Side::Ask => &snapshot.asks,
+                };
+                !side_levels
+                    .iter()
+                    .any(|&(p, size)| p == o.price && size > 0)
+            })
+            .expect("an order without a level");
+        return Err(SnapshotError::LevelMismatch {
+            side: order.side,
+            price: order.price,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::price_ladder::PriceLadder;
+
+    fn px(ticks: i64) -> Price {
+        Price::from_ticks(ticks)
+    }
+
+    #[test]
+    fn test_best_bid_ask_tracks_top_of_book() {
+        let mut book = OrderBook::new();
+        book.apply_quote("ES", Side::Bid, px(10000), 5).unwrap();
+        book.apply_quote("ES", Side::Bid, px(10050), 3).unwrap();
+        book.apply_quote("ES", Side::Ask, px(10100), 7).unwrap();
+        book.apply_quote("ES", Side::Ask, px(10150), 2).unwrap();
+
+        assert_eq!(
+            book.best_bid_ask("ES"),
+            (Some((px(10050), 3)), Some((px(10100), 7)))
+        );
+        assert_eq!(book.best_bid_ask("NQ"), (None, None));
+    }
+
+    #[test]
+    fn test_update_and_remove_level() {
+        let mut book = OrderBook::new();
+        book.apply_quote("ES", Side::Bid, px(10000), 5).unwrap();
+        book.apply_quote("ES", Side::Bid, px(10000), 9).unwrap();
+        assert_eq!(book.level_size("ES", Side::Bid, px(10000)), Some(9));
+
+        book.apply_quote("ES", Side::Bid, px(10000), 0).unwrap();
+        assert_eq!(book.level_size("ES", Side::Bid, px(10000)), None);
+        assert_eq!(book.level_count("ES", Side::Bid), 0);
+        assert_eq!(book.best_bid_ask("ES"), (None, None));
+    }
+
+    #[test]
+    fn test_symbols_are_independent() {
+        let mut book = OrderBook::new();
+        book.apply_quote("ES", Side::Ask, px(10100), 1).unwrap();
+        book.apply_quote("NQ", Side::Ask, px(1500000), 2).unwrap();
+        book.apply_quote("ES", Side::Ask, px(10100), 0).unwrap();
+
+        assert_eq!(book.best_bid_ask("ES"), (None, None));
+        assert_eq!(book.best_bid_ask("NQ"), (None, Some((px(1500000), 2))));
+    }
+
+    fn queue(book: &OrderBook, side: Side, price: Price) -> Vec<(u64, u64)> {
+        let mut out = Vec::new();
+        book.for_each_order_at("ES", side, price, |id, qty| out.push((id, qty)));
+        out
+    }
+
+    #[test]
+    fn test_l3_orders_aggregate_into_levels() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 1, Side::Bid, px(10000), 5).unwrap();
+        book.add_order("ES", 2, Side::Bid, px(10000), 3).unwrap();
+        book.add_order("ES", 3, Side::Bid, px(9975), 4).unwrap();
+        book.add_order("ES", 4, Side::Ask, px(10025), 6).unwrap();
+
+        assert_eq!(
+            book.add_order("ES", 1, Side::Bid, px(10000), 1),
+            Err(OrderError::DuplicateOrderId)
+        );
+        assert_eq!(
+            book.best_bid_ask("ES"),
+            (Some((px(10000), 8)), Some((px(10025), 6)))
+        );
+        assert_eq!(
+            book.levels("ES", Side::Bid),
+            vec![(px(10000), 8), (px(9975), 4)]
+        );
+        assert_eq!(queue(&book, Side::Bid, px(10000)), vec![(1, 5), (2, 3)]);
+
+        let mut within = Vec::new();
+        book.for_each_order_within("ES", Side::Bid, px(9975), |order| {
+            within.push(order.order_id);
+            order.order_id != 2
+        });
+        assert_eq!(within, vec![1, 2]);
+        within.clear();
+        book.for_each_order_within("ES", Side::Bid, px(9976), |order| {
+            within.push(order.order_id);
+            true
+        });
+        assert_eq!(within, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_l3_priority_rules() {
+        let mut book = OrderBook::market_by_order();
+        for id in 1..=3 {
+            book.add_order("ES", id, Side::Ask, px(10025), 10).unwrap();
+        }
+
+        // Size reduction keeps priority.
+        book.modify_order("ES", 1, px(10025), 4).unwrap();
+        assert_eq!(
+            queue(&book, Side::Ask, px(10025)),
+            vec![(1, 4), (2, 10), (3, 10)]
+        );
+
+        // Size increase loses priority.
+        book.modify_order("ES", 1, px(10025), 12).unwrap();
+        assert_eq!(
+            queue(&book, Side::Ask, px(10025)),
+            vec![(2, 10), (3, 10), (1, 12)]
+        );
+        assert_eq!(
+            book.queue_position("ES", 1),
+            Some(QueuePosition {
+                orders_ahead: 2,
+                quantity_ahead: 20
+            })
+        );
+
+        // Price change moves the order to the new level.
+        book.modify_order("ES", 3, px(10050), 10).unwrap();
+        assert_eq!(queue(&book, Side::Ask, px(10025)), vec![(2, 10), (1, 12)]);
+        assert_eq!(book.level_size("ES", Side::Ask, px(10050)), Some(10));
+    }
+
+    #[test]
+    fn test_l3_execute_and_delete() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 1, Side::Bid, px(10000), 5).unwrap();
+        book.add_order("ES", 2, Side::Bid, px(10000), 5).unwrap();
+
+        assert_eq!(book.execute_order("ES", 1, 2), Ok(3));
+        assert_eq!(book.level_size("ES", Side::Bid, px(10000)), Some(8));
+        assert_eq!(book.execute_order("ES", 1, 3), Ok(0));
+        assert_eq!(book.order("ES", 1), None);
+        assert_eq!(queue(&book, Side::Bid, px(10000)), vec![(2, 5)]);
+
+        let removed = book.delete_order("ES", 2).unwrap();
+        assert_eq!(removed.quantity, 5);
+        assert_eq!(book.level_count("ES", Side::Bid), 0);
+        assert_eq!(book.order_count("ES"), 0);
+        assert_eq!(book.delete_order("ES", 2), Err(OrderError::UnknownOrder));
+    }
+
+    #[test]
+    fn test_mode_mismatch() {
+        let mut l2 = OrderBook::new();
+        l2.apply_quote("ES", Side::Bid, px(10000), 5).unwrap();
+        assert_eq!(
+            l2.add_order("ES", 1, Side::Bid, px(10000), 5),
+            Err(OrderError::WrongMode)
+        );
+        assert_eq!(
+            l2.modify_order("ES", 1, px(10000), 2),
+            Err(OrderError::WrongMode)
+        );
+        assert_eq!(l2.level_size("ES", Side::Bid, px(10000)), Some(5));
+
+        let mut l3 = OrderBook::market_by_order();
+        l3.add_order("ES", 1, Side::Bid, px(10000), 5).unwrap();
+        assert_eq!(
+            l3.apply_quote("ES", Side::Bid, px(10000), 9),
+            Err(OrderError::WrongMode)
+        );
+        assert_eq!(l3.level_size("ES", Side::Bid, px(10000)), Some(5));
+    }
+
+    #[test]
+    fn test_ladder_backend_l3() {
+        let mut book = OrderBook::<PriceLadder<64>>::with_mode(BookMode::MarketByOrder);
+        book.add_order("ES", 1, Side::Ask, px(10025), 5).unwrap();
+        book.add_order("ES", 2, Side::Ask, px(10050), 5).unwrap();
+        book.add_order("ES", 3, Side::Ask, px(10025), 2).unwrap();
+        assert_eq!(book.best_bid_ask("ES"), (None, Some((px(10025), 7))));
+
+        book.delete_order("ES", 1).unwrap();
+        book.delete_order("ES", 3).unwrap();
+        assert_eq!(book.levels("ES", Side::Ask), vec![(px(10050), 5)]);
+    }
+
+    fn depth_book() -> OrderBook {
+        let mut book = OrderBook::new();
+        book.apply_quote("ES", Side::Bid, px(100), 10).unwrap();
+        book.apply_quote("ES", Side::Bid, px(99), 20).unwrap();
+        book.apply_quote("ES", Side::Bid, px(98), 30).unwrap();
+        book.apply_quote("ES", Side::Ask, px(101), 5).unwrap();
+        book.apply_quote("ES", Side::Ask, px(102), 15).unwrap();
+        book
+    }
+
+    #[test]
+    fn test_depth_queries() {
+        let book = depth_book();
+
+        let mut buf = [(Price::ZERO, 0); 2];
+        assert_eq!(book.top_levels("ES", Side::Bid, &mut buf), 2);
+        assert_eq!(buf, [(px(100), 10), (px(99), 20)]);
+        let mut buf = [(Price::ZERO, 0); 4];
+        assert_eq!(book.top_levels("ES", Side::Ask, &mut buf), 2);
+
+        assert_eq!(book.cumulative_depth("ES", Side::Bid, px(99)), 30);
+        assert_eq!(book.cumulative_depth("ES", Side::Ask, px(101)), 5);
+        assert_eq!(book.cumulative_depth("ES", Side::Ask, px(100)), 0);
+
+        // Buy 10: 5 @ 101 + 5 @ 102.
+        assert_eq!(book.vwap_to_fill("ES", Side::Ask, 10), Some(101.5));
+        assert_eq!(book.vwap_to_fill("ES", Side::Ask, 21), None);
+
+        // Top level: (10 - 5) / 15; two levels: (30 - 20) / 50.
+        assert_eq!(book.imbalance("ES", 1), Some(5.0 / 15.0));
+        assert_eq!(book.imbalance("ES", 2), Some(0.2));
+        assert_eq!(book.imbalance("NQ", 1), None);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip_l2() {
+        let book = depth_book();
+        let snapshot = book.snapshot("ES");
+        let mut bytes = Vec::new();
+        snapshot.encode(&mut bytes);
+        let decoded = BookSnapshot::decode(&bytes).unwrap();
+        assert_eq!(decoded, snapshot);
+
+        let mut restored = OrderBook::new();
+        restored.apply_quote("ES", Side::Bid, px(50), 1).unwrap();
+        restored.restore(&decoded).unwrap();
+        assert_eq!(
+            restored.levels("ES", Side::Bid),
+            book.levels("ES", Side::Bid)
+        );
+        assert_eq!(
+            restored.levels("ES", Side::Ask),
+            book.levels("ES", Side::Ask)
+        );
+
+        assert_eq!(
+            BookSnapshot::decode(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_snapshot_round_trip_l3_keeps_queue_order() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 7, Side::Bid, px(100), 3).unwrap();
+        book.add_order("ES", 2, Side::Bid, px(100), 4).unwrap();
+        book.add_order("ES", 5, Side::Ask, px(101), 1).unwrap();
+
+        let mut bytes = Vec::new();
+        book.snapshot("ES").encode(&mut bytes);
+        let mut restored = OrderBook::market_by_order();
+        restored
+            .restore(&BookSnapshot::decode(&bytes).unwrap())
+            .unwrap();
+
+        assert_eq!(queue(&restored, Side::Bid, px(100)), vec![(7, 3), (2, 4)]);
+        assert_eq!(restored.order("ES", 5), book.order("ES", 5));
+
+        // An L3 snapshot seeds an L2 book from its levels; the reverse is refused.
+        let mut l2 = OrderBook::new();
+        l2.restore(&book.snapshot("ES")).unwrap();
+        assert_eq!(l2.best_bid_ask("ES"), book.best_bid_ask("ES"));
+        assert_eq!(
+            restored.restore(&l2.snapshot("ES")),
+            Err(SnapshotError::ModeMismatch)
+        );
+        assert_eq!(restored.order_count("ES"), 3);
+    }
+
+    #[test]
+    fn test_restore_rejects_inconsistent_orders() {
+        let mut source = OrderBook::market_by_order();
+        source.add_order("ES", 5, Side::Bid, px(100), 3).unwrap();
+        source.add_order("ES", 6, Side::Ask, px(101), 2).unwrap();
+        let mut book = OrderBook::market_by_order();
+        book.restore(&source.snapshot("ES")).unwrap();
+
+        let mut duplicate = source.snapshot("ES");
+        duplicate.orders.push(duplicate.orders[0]);
+        duplicate.bids = vec![(px(100), 6)];
+        assert_eq!(
+            book.restore(&duplicate),
+            Err(SnapshotError::DuplicateOrder { order_id: 5 })
+        );
+
+        let mut empty = source.snapshot("ES");
+        empty.orders[1].quantity = 0;
+        assert_eq!(
+            book.restore(&empty),
+            Err(SnapshotError::InvalidOrder { order_id: 6 })
+        );
+
+        let mut mismatch = source.snapshot("ES");
+        mismatch.orders[0].price = px(99);
+        assert_eq!(
+            book.restore(&mismatch),
+            Err(SnapshotError::LevelMismatch {
+                side: Side::Bid,
+                price: px(100)
+            })
+        );
+        mismatch.bids = vec![(px(100), 3), (px(99), 3)];
+        mismatch.orders.push(RestingOrder {
+            order_id: 7,
+            side: Side::Bid,
+            price: px(100),
+            quantity: 3,
+        });
+        assert_eq!(book.restore(&mismatch), Ok(()));
+        mismatch.asks.clear();
+        assert_eq!(
+            book.restore(&mismatch),
+            Err(SnapshotError::LevelMismatch {
+                side: Side::Ask,
+                price: px(101)
+            })
+        );
+
+        // The last good restore is untouched by the rejected ones.
+        assert_eq!(queue(&book, Side::Bid, px(99)), vec![(5, 3)]);
+        assert_eq!(queue(&book, Side::Bid, px(100)), vec![(7, 3)]);
+        assert_eq!(book.order_count("ES"), 3);
+    }
+
+    #[test]
+    fn test_events_for_quotes() {
+        let mut book = OrderBook::new();
+        book.record_events(true);
+        book.apply_quote("ES", Side::Bid, px(100), 5).unwrap();
+        book.apply_quote("ES", Side::Bid, px(99), 3).unwrap();
+        book.apply_quote("ES", Side::Bid, px(99), 4).unwrap();
+        book.apply_quote("ES", Side::Bid, px(100), 0).unwrap();
+
+        let mut events: Vec<BookEvent> = Vec::new();
+        book.publish_events("ES", &mut [&mut events]);
+        assert_eq!(
+            events,
+            vec![
+                BookEvent::LevelAdded {
+                    side: Side::Bid,
+                    price: px(100),
+                    size: 5
+                },
+                BookEvent::TopOfBook {
+                    bid: Some((px(100), 5)),
+                    ask: None
+                },
+                BookEvent::LevelAdded {
+                    side: Side::Bid,
+                    price: px(99),
+                    size: 3
+                },
+                BookEvent::LevelChanged {
+                    side: Side::Bid,
+                    price: px(99),
+                    size: 4
+                },
+                BookEvent::LevelRemoved {
+                    side: Side::Bid,
+                    price: px(100)
+                },
+                BookEvent::TopOfBook {
+                    bid: Some((px(99), 4)),
+                    ask: None
+                },
+            ]
+        );
+        assert_eq!(book.drain_events().count(), 0);
+    }
+
+    #[test]
+    fn test_events_for_order_moves() {
+        let mut book = OrderBook::market_by_order();
+        book.add_order("ES", 1, Side::Ask, px(101), 5).unwrap();
+        book.add_order("ES", 2, Side::Ask, px(101), 5).unwrap();
+        book.record_events(true);
+
+        book.modify_order("ES", 1, px(102), 5).unwrap();
+        let events: Vec<_> = book.drain_events().collect();
+        assert_eq!(
+            events,
+            vec![
+                BookEvent::LevelChanged {
+                    side: Side::Ask,
+                    price: px(101),
+                    size: 5
+                },
+                BookEvent::LevelAdded {
+                    side: Side::Ask,
+                    price: px(102),
+                    size: 5
+                },
+                BookEvent::TopOfBook {
+                    bid: None,
+                    ask: Some((px(101), 5))
+                },
+            ]
+        );
+
+        // Unknown orders record nothing.
+        assert!(book.delete_order("ES", 9).is_err());
+        assert_eq!(book.drain_events().count(), 0);
     }
 }
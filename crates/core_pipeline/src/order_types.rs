@@ -0,0 +1,149 @@
+//! order_types.rs
+//! Orders as submitted to the `MatchEngine`: limit price, time-in-force and execution flags.
+//!
+//! # Key Concepts
+//! - A limit order carries a price; a market order (`limit: None`) sweeps until filled or the
+//!   book is empty and never rests.
+//! - Time-in-force decides what happens to an unfilled remainder: `Day` and `Gtc` rest in the
+//!   book, `Ioc` is cancelled, and `Fok` is rejected up front unless it can fill completely.
+//! - Flags modify execution: post-only orders never take liquidity, hidden orders rest without
+//!   being displayed, and reduce-only orders may only shrink an existing position.
+//! - A stop price turns the order into a stop (market) or stop-limit order, held off-book until
+//!   the last trade reaches the stop price.
+//! - An optional account id identifies the trader or strategy for self-trade prevention.
+
+use common::price::Price;
+
+use super::order_book::Side;
+
+/// Identifies the trader or strategy that owns an order.
+pub type AccountId = u32;
+
+/// How long an order stays working.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeInForce {
+    /// Rests until filled, cancelled or the end of the trading day.
+    Day,
+    /// Immediate-or-cancel: fills what it can, cancels the rest.
+    Ioc,
+    /// Fill-or-kill: fills completely or not at all.
+    Fok,
+    /// Good-till-cancelled: rests until filled or cancelled.
+    Gtc,
+}
+
+impl TimeInForce {
+    /// Whether an unfilled remainder rests in the book.
+    pub fn rests(self) -> bool {
+        matches!(self, TimeInForce::Day | TimeInForce::Gtc)
+    }
+}
+
+/// Execution flags, combined with `|`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct OrderFlags(u8);
+
+impl OrderFlags {
+    pub const NONE: OrderFlags = OrderFlags(0);
+    /// Reject the order instead of letting it take liquidity.
+    pub const POST_ONLY: OrderFlags = OrderFlags(1);
+    /// Rest without showing in the displayed book; matched after displayed size at a price.
+    pub const HIDDEN: OrderFlags = OrderFlags(1 << 1);
+    /// Only allowed to reduce the current position; the quantity is capped at the position.
+    pub const REDUCE_ONLY: OrderFlags = OrderFlags(1 << 2);
+
+    pub fn contains(self, other: OrderFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Raw bit representation, for encoding.
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        OrderFlags(bits)
+    }
+}
+
+impl std::ops::BitOr for OrderFlags {
+    type Output = OrderFlags;
+
+    fn bitor(self, rhs: OrderFlags) -> OrderFlags {
+        OrderFlags(self.0 | rhs.0)
+    }
+}
+
+/// An order submitted to the matching engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Order {
+    pub order_id: u64,
+    pub symbol: String,
+    pub side: Side,
+    pub quantity: u64,
+    /// Limit price; `None` for a market order.
+    pub limit: Option<Price>,
+    pub tif: TimeInForce,
+    pub flags: OrderFlags,
+    /// Owner of the order, for self-trade prevention.
+    pub account: Option<AccountId>,
+    /// Stop trigger price for stop and stop-limit orders.
+    pub stop: Option<Price>,
+}
+
+impl Order {
+    /// A day limit order with no flags.
+    pub fn limit(order_id: u64, symbol: &str, side: Side, price: Price, quantity: u64) -> Self {
+        Order {
+            order_id,
+            symbol: symbol.to_string(),
+            side,
+            quantity,
+            limit: Some(price),
+            tif: TimeInForce::Day,
+            flags: OrderFlags::NONE,
+            account: None,
+            stop: None,
+        }
+    }
+
+    /// A market order. Its remainder is always cancelled, so it is immediate-or-cancel.
+    pub fn market(order_id: u64, symbol: &str, side: Side, quantity: u64) -> Self {
+        Order {
+            order_id,
+            symbol: symbol.to_string(),
+            side,
+            quantity,
+            limit: None,
+            tif: TimeInForce::Ioc,
+            flags: OrderFlags::NONE,
+            account: None,
+            stop: None,
+        }
+    }
+
+    pub fn with_tif(mut self, tif: TimeInForce) -> Self {
+        self.tif = tif;
+        self
+    }
+
+    pub fn with_flags(mut self, flags: OrderFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn with_account(mut self, account: AccountId) -> Self {
+        self.account = Some(account);
+        self
+    }
+
+    /// Makes this a stop order (or stop-limit, if it has a limit price) triggering at `stop`.
+    pub fn with_stop(mut self, stop: Price) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    pub fn is_market(&self) -> bool {
+        self.limit.is_none()
+    }
+}
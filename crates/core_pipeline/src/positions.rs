@@ -0,0 +1,396 @@
+//! positions.rs
+//! Tracks net positions, average cost and PnL from our executions.
+//!
+//! # Key Concepts
+//! - Executions come from `MatchEngine` fills, for our incoming order (`Execution::from_fill`)
+//!   or our resting order (`Execution::from_resting_fill`), or from external execution
+//!   reports, and update both the instrument's position (over all accounts) and the account's
+//!   position in that instrument.
+//! - Average cost method: adding to a position re-averages the cost, reducing it realises
+//!   `(price - average cost) * closed quantity`, and flipping through zero opens the new
+//!   position at the execution price.
+//! - Unrealised PnL is marked from book mids (`mark_from_book`) or an explicit `mark`.
+//! - Prices and per-instrument PnL are in ticks (PnL is ticks times quantity), as elsewhere in
+//!   the pipeline. A tick is worth a different amount in each instrument, so PnL summed across
+//!   instruments (`total_pnl`, `account_pnl`) is converted to currency with each instrument's
+//!   tick value (`set_tick_value`) and fails if one is missing.
+//! - `reset_daily_pnl` starts a new trading day: realised PnL goes to zero and the unrealised
+//!   PnL of open positions at the current mark becomes the day's baseline, so daily PnL is
+//!   realised plus the change in unrealised since the reset. Average costs are kept.
+
+use std::collections::HashMap;
+
+use common::price::Price;
+
+use super::match_engine::Fill;
+use super::order_book::{OrderBook, Side};
+use super::order_types::AccountId;
+use super::price_levels::PriceLevels;
+
+/// One of our executions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execution {
+    pub account: Option<AccountId>,
+    pub symbol: String,
+    /// `Side::Bid` for a buy, `Side::Ask` for a sell.
+    pub side: Side,
+    pub price: Price,
+    pub quantity: u64,
+}
+
+impl Execution {
+    /// The execution of our incoming order reported by a `MatchEngine` fill.
+    pub fn from_fill(symbol: &str, account: Option<AccountId>, fill: &Fill) -> Self {
+        Execution {
+            account,
+            symbol: symbol.to_string(),
+            side: fill.aggressor,
+            price: fill.price,
+            quantity: fill.quantity,
+        }
+    }
+
+    /// The execution of our resting order reported by a `MatchEngine` fill: the opposite
+    /// side of the aggressor.
+    pub fn from_resting_fill(symbol: &str, account: Option<AccountId>, fill: &Fill) -> Self {
+        Execution {
+            side: fill.aggressor.opposite(),
+            ..Execution::from_fill(symbol, account, fill)
+        }
+    }
+}
+
+/// Net position in one instrument.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Position {
+    /// Positive long, negative short.
+    pub net: i64,
+    /// Average cost of the open position, in ticks; 0 when flat.
+    pub average_cost: f64,
+    /// PnL realised since the last daily reset.
+    pub realised: f64,
+    /// Unrealised PnL of the position at the last daily reset, in ticks times quantity.
+    pub day_start_unrealised: f64,
+}
+
+impl Position {
+    fn apply(&mut self, side: Side, price: Price, quantity: u64) {
+        let price = price.ticks() as f64;
+        let delta = match side {
+            Side::Bid => quantity as i64,
+            Side::Ask => -(quantity as i64),
+        };
+        if self.net == 0 || self.net.signum() == delta.signum() {
+            let held = self.net.unsigned_abs() as f64;
+            self.average_cost =
+                (self.average_cost * held + price * quantity as f64) / (held + quantity as f64);
+            self.net += delta;
+            return;
+        }
+
+        let closed = quantity.min(self.net.unsigned_abs()) as f64;
+        self.realised += closed * (price - self.average_cost) * self.net.signum() as f64;
+        let before = self.net;
+        self.net += delta;
+        if self.net == 0 {
+            self.average_cost = 0.0;
+        } else if self.net.signum() != before.signum() {
+            self.average_cost = price;
+        }
+    }
+
+    /// PnL of the open position at `mark`.
+    pub fn unrealised(&self, mark: f64) -> f64 {
+        (mark - self.average_cost) * self.net as f64
+    }
+}
+
+/// Realised and unrealised PnL since the last daily reset: `unrealised` is the change in the
+/// open position's value since then.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pnl {
+    pub realised: f64,
+    pub unrealised: f64,
+}
+
+impl Pnl {
+    pub fn total(&self) -> f64 {
+        self.realised + self.unrealised
+    }
+
+    fn scaled(&self, factor: f64) -> Pnl {
+        Pnl {
+            realised: self.realised * factor,
+            unrealised: self.unrealised * factor,
+        }
+    }
+}
+
+/// Why PnL could not be converted to currency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PnlError {
+    /// The instrument has no tick value (`set_tick_value`).
+    NoTickValue(String),
+}
+
+#[derive(Debug, Default)]
+pub struct PositionKeeper {
+    instruments: HashMap<String, Position>,
+    accounts: HashMap<AccountId, HashMap<String, Position>>,
+    /// Latest mark price per instrument, in ticks.
+    marks: HashMap<String, f64>,
+    /// Currency value of one tick for one unit of quantity, per instrument.
+    tick_values: HashMap<String, f64>,
+}
+
+impl PositionKeeper {
+    pub fn new() -> Self {
+        PositionKeeper::default()
+    }
+
+    pub fn on_execution(&mut self, execution: &Execution) {
+        let symbol = &execution.symbol;
+        let (side, price, quantity) = (execution.side, execution.price, execution.quantity);
+        self.instruments
+            .entry(symbol.clone())
+            .or_default()
+            .apply(side, price, quantity);
+        if let Some(account) = execution.account {
+            self.accounts
+                .entry(account)
+                .or_default()
+                .entry(symbol.clone())
+                .or_default()
+                .apply(side, price, quantity);
+        }
+    }
+
+    /// Sets the currency value of one tick for one unit of quantity in `symbol`: the tick size
+    /// times the contract multiplier (e.g. 12.5 for a 0.25 tick on a 50x future).
+    pub fn set_tick_value(&mut self, symbol: &str, value: f64) {
+        self.tick_values.insert(symbol.to_string(), value);
+    }
+
+    /// Sets the mark price of `symbol`, in ticks.
+    pub fn mark(&mut self, symbol: &str, price: f64) {
+        self.marks.insert(symbol.to_string(), price);
+    }
+
+    /// Marks every instrument we hold at its book mid. Instruments without a two-sided book
+    /// keep their previous mark.
+    pub fn mark_from_book<L: PriceLevels>(&mut self, book: &OrderBook<L>) {
+        for symbol in self.instruments.keys() {
+            if let (Some((bid, _)), Some((ask, _))) = book.best_bid_ask(symbol) {
+                let mid = (bid.ticks() as f64 + ask.ticks() as f64) / 2.0;
+                self.marks.insert(symbol.clone(), mid);
+            }
+        }
+    }
+
+    /// Position in `symbol` over all accounts.
+    pub fn position(&self, symbol: &str) -> Position {
+        self.instruments.get(symbol).copied().unwrap_or_default()
+    }
+
+    pub fn account_position(&self, account: AccountId, symbol: &str) -> Position {
+        self.accounts
+            .get(&account)
+            .and_then(|positions| positions.get(symbol))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// PnL in `symbol` over all accounts, in ticks times quantity.
+    pub fn pnl(&self, symbol: &str) -> Pnl {
+        self.position_pnl(symbol, &self.position(symbol))
+    }
+
+    /// PnL in `symbol` over all accounts, in currency.
+    pub fn pnl_value(&self, symbol: &str) -> Result<Pnl, PnlError> {
+        Ok(self.pnl(symbol).scaled(self.required_tick_value(symbol)?))
+    }
+
+    /// PnL over all instruments and accounts, in currency. Fails if an instrument held has
+    /// no tick value rather than adding up ticks of different sizes.
+    pub fn total_pnl(&self) -> Result<Pnl, PnlError> {
+        self.sum_pnl(&self.instruments)
+    }
+
+    /// PnL of `account` over all instruments, in currency. Fails like `total_pnl`.
+    pub fn account_pnl(&self, account: AccountId) -> Result<Pnl, PnlError> {
+        match self.accounts.get(&account) {
+            Some(positions) => self.sum_pnl(positions),
+            None => Ok(Pnl::default()),
+        }
+    }
+
+    fn sum_pnl(&self, positions: &HashMap<String, Position>) -> Result<Pnl, PnlError> {
+        let mut total = Pnl::default();
+        for (symbol, position) in positions {
+            let pnl = self
+                .position_pnl(symbol, position)
+                .scaled(self.required_tick_value(symbol)?);
+            total.realised += pnl.realised;
+            total.unrealised += pnl.unrealised;
+        }
+        Ok(total)
+    }
+
+    /// Currency value of one tick for one unit of quantity in `symbol`, if set.
+    pub fn tick_value(&self, symbol: &str) -> Option<f64> {
+        self.tick_values.get(symbol).copied()
+    }
+
+    fn required_tick_value(&self, symbol: &str) -> Result<f64, PnlError> {
+        self.tick_value(symbol)
+            .ok_or_else(|| PnlError::NoTickValue(symbol.to_string()))
+    }
+
+    fn position_pnl(&self, symbol: &str, position: &Position) -> Pnl {
+        Pnl {
+            realised: position.realised,
+            unrealised: self
+                .marks
+                .get(symbol)
+                .map_or(0.0, |&mark| position.unrealised(mark))
+                - position.day_start_unrealised,
+        }
+    }
+
+    /// Starts a new trading day: clears realised PnL and records the unrealised PnL of open
+    /// positions at their current mark as the day's baseline, so daily PnL restarts from zero.
+    pub fn reset_daily_pnl(&mut self) {
+        let marks = &self.marks;
+        let positions = self
+            .instruments
+            .iter_mut()
+            .chain(self.accounts.values_mut().flat_map(|p| p.iter_mut()));
+        for (symbol, position) in positions {
+            position.realised = 0.0;
+            position.day_start_unrealised = marks
+                .get(symbol)
+                .map_or(0.0, |&mark| position.unrealised(mark));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(ticks: i64) -> Price {
+        Price::from_ticks(ticks)
+    }
+
+    fn exec(side: Side, price: i64, quantity: u64) -> Execution {
+        Execution {
+            account: Some(1),
+            symbol: "ES".to_string(),
+            side,
+            price: px(price),
+            quantity,
+        }
+    }
+
+    #[test]
+    fn test_average_cost_and_realised() {
+        let mut keeper = PositionKeeper::new();
+        keeper.on_execution(&exec(Side::Bid, 100, 2));
+        keeper.on_execution(&exec(Side::Bid, 103, 1));
+        assert_eq!(keeper.position("ES").net, 3);
+        assert_eq!(keeper.position("ES").average_cost, 101.0);
+
+        keeper.on_execution(&exec(Side::Ask, 105, 1));
+        assert_eq!(keeper.position("ES").realised, 4.0);
+        assert_eq!(keeper.position("ES").average_cost, 101.0);
+
+        // Flip from long 2 to short 1: realise on 2, open the short at 99.
+        keeper.on_execution(&exec(Side::Ask, 99, 3));
+        let position = keeper.account_position(1, "ES");
+        assert_eq!((position.net, position.average_cost), (-1, 99.0));
+        assert_eq!(position.realised, 0.0);
+    }
+
+    #[test]
+    fn test_unrealised_from_book_mid() {
+        let mut keeper = PositionKeeper::new();
+        keeper.on_execution(&exec(Side::Ask, 100, 2));
+        let mut book = OrderBook::new();
+        book.apply_quote("ES", Side::Bid, px(96), 1).unwrap();
+        book.apply_quote("ES", Side::Ask, px(98), 1).unwrap();
+        keeper.mark_from_book(&book);
+        keeper.set_tick_value("ES", 1.0);
+        assert_eq!(
+            keeper.account_pnl(1),
+            Ok(Pnl {
+                realised: 0.0,
+                unrealised: 6.0
+            })
+        );
+
+        keeper.reset_daily_pnl();
+        assert_eq!(keeper.pnl("ES").total(), 0.0);
+        assert_eq!(keeper.position("ES").net, -2);
+        assert_eq!(keeper.position("ES").average_cost, 100.0);
+
+        // The day's PnL runs from the reset mark of 97; buying back realises against cost.
+        keeper.mark("ES", 98.0);
+        assert_eq!(keeper.account_pnl(1).unwrap().total(), -2.0);
+        keeper.on_execution(&exec(Side::Bid, 98, 1));
+        assert_eq!(
+            keeper.account_pnl(1),
+            Ok(Pnl {
+                realised: 2.0,
+                unrealised: -4.0
+            })
+        );
+    }
+
+    #[test]
+    fn test_totals_convert_each_instrument_to_currency() {
+        let mut keeper = PositionKeeper::new();
+        keeper.on_execution(&exec(Side::Bid, 100, 2));
+        keeper.on_execution(&Execution {
+            symbol: "CL".to_string(),
+            ..exec(Side::Ask, 7000, 1)
+        });
+        keeper.mark("ES", 104.0);
+        keeper.mark("CL", 6990.0);
+        keeper.set_tick_value("ES", 12.5);
+        assert_eq!(
+            keeper.total_pnl(),
+            Err(PnlError::NoTickValue("CL".to_string()))
+        );
+
+        keeper.set_tick_value("CL", 10.0);
+        // ES: 8 ticks * 12.5, CL: 10 ticks * 10.
+        assert_eq!(keeper.total_pnl().unwrap().total(), 200.0);
+        assert_eq!(keeper.account_pnl(1).unwrap().total(), 200.0);
+        assert_eq!(keeper.pnl("ES").total(), 8.0);
+        assert_eq!(keeper.pnl_value("ES").unwrap().total(), 100.0);
+        assert_eq!(keeper.account_pnl(2), Ok(Pnl::default()));
+    }
+
+    #[test]
+    fn test_resting_fill_is_opposite_side() {
+        let fill = Fill {
+            price: px(101),
+            quantity: 3,
+            resting_order_id: Some(7),
+            aggressor: Side::Bid,
+        };
+        let ours = Execution::from_resting_fill("ES", Some(1), &fill);
+        assert_eq!(
+            (ours.side, ours.price, ours.quantity),
+            (Side::Ask, px(101), 3)
+        );
+        assert_eq!(Execution::from_fill("ES", Some(2), &fill).side, Side::Bid);
+
+        let mut keeper = PositionKeeper::new();
+        keeper.on_execution(&ours);
+        keeper.on_execution(&Execution::from_fill("ES", Some(2), &fill));
+        assert_eq!(keeper.account_position(1, "ES").net, -3);
+        assert_eq!(keeper.account_position(2, "ES").net, 3);
+        assert_eq!(keeper.position("ES").net, 0);
+    }
+}
@@ -0,0 +1,443 @@
+//! price_ladder.rs
+//! Flat, array-indexed price ladder: an alternative `PriceLevels` backend with constant-time
+//! lookups for prices near the touch.
+//!
+//! # Design
+//! - A window of `N` consecutive ticks is stored in a flat array; the slot for a price is
+//!   `price - base`. An occupancy bitmap lets the next best level be found 64 ticks at a time.
+//! - The window is centred on the book's mid price: the `OrderBook` passes the mid after each
+//!   update (`follow_mid`) and the ladder re-centres once the mid has drifted more than `N / 4`
+//!   ticks from the centre. Each side then holds `N / 2` ticks of depth behind the mid and
+//!   `N / 2` ticks of room in front of it for improving quotes.
+//! - Levels that fall outside the window live in an overflow `BTreeMap`. The best level is
+//!   always inside the window: when a better price arrives outside it, or the window empties,
+//!   the ladder re-centres on its best price. This is also where the window sits before the
+//!   book is two-sided, or when the spread is too wide for the mid and the best level to fit
+//!   in one window.
+//!
+//! # Performance
+//! - Get/insert/remove inside the window: O(1). Removing the best level: O(N / 64) worst case.
+//! - Re-centring is O(N + overflow) but only happens when the mid drifts by a quarter of the
+//!   window or the touch leaves it.
+
+use std::collections::BTreeMap;
+
+use common::price::Price;
+
+use super::order_book::Side;
+use super::price_levels::{LevelEntry, PriceLevels};
+
+/// Default window size in ticks.
+pub const DEFAULT_LADDER_TICKS: usize = 1024;
+
+/// Array-backed side of a book covering a window of `N` ticks (`N` must be a multiple of 64).
+#[derive(Debug, Clone)]
+pub struct PriceLadder<const N: usize = DEFAULT_LADDER_TICKS> {
+    side: Side,
+    /// Price stored in slot 0.
+    base: Price,
+    slots: Box<[LevelEntry]>,
+    occupied: Box<[u64]>,
+    /// Slot of the best level; `Some` whenever the window holds any level.
+    best: Option<usize>,
+    in_window: usize,
+    overflow: BTreeMap<Price, LevelEntry>,
+}
+
+impl<const N: usize> PriceLadder<N> {
+    #[inline]
+    fn slot(&self, price: Price) -> Option<usize> {
+        let offset = price.ticks().checked_sub(self.base.ticks())?;
+        if (0..N as i64).contains(&offset) {
+            Some(offset as usize)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn price_at(&self, slot: usize) -> Price {
+        self.base.offset(slot as i64)
+    }
+
+    #[inline]
+    fn is_occupied(&self, slot: usize) -> bool {
+        self.occupied[slot / 64] & (1u64 << (slot % 64)) != 0
+    }
+
+    #[inline]
+    fn is_better(&self, a: usize, b: usize) -> bool {
+        match self.side {
+            Side::Bid => a > b,
+            Side::Ask => a < b,
+        }
+    }
+
+    /// Highest occupied slot at or below `slot`.
+    fn occupied_at_or_below(&self, slot: usize) -> Option<usize> {
+        let mut word = slot / 64;
+        let bit = slot % 64;
+        let mask = if bit == 63 {
+            u64::MAX
+        } else {
+            (1u64 << (bit + 1)) - 1
+        };
+        let mut bits = self.occupied[word] & mask;
+        loop {
+            if bits != 0 {
+                return Some(word * 64 + 63 - bits.leading_zeros() as usize);
+            }
+            if word == 0 {
+                return None;
+            }
+            word -= 1;
+            bits = self.occupied[word];
+        }
+    }
+
+    /// Lowest occupied slot at or above `slot`.
+    fn occupied_at_or_above(&self, slot: usize) -> Option<usize> {
+        let mut word = slot / 64;
+        let mut bits = self.occupied[word] & (u64::MAX << (slot % 64));
+        loop {
+            if bits != 0 {
+                return Some(word * 64 + bits.trailing_zeros() as usize);
+            }
+            word += 1;
+            if word == self.occupied.len() {
+                return None;
+            }
+            bits = self.occupied[word];
+        }
+    }
+
+    /// Next occupied slot worse than `slot`.
+    fn next_worse(&self, slot: usize) -> Option<usize> {
+        match self.side {
+            Side::Bid => slot
+                .checked_sub(1)
+                .and_then(|s| self.occupied_at_or_below(s)),
+            Side::Ask => {
+                if slot + 1 < N {
+                    self.occupied_at_or_above(slot + 1)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Best occupied slot in the window.
+    fn scan_best(&self) -> Option<usize> {
+        match self.side {
+            Side::Bid => self.occupied_at_or_below(N - 1),
+            Side::Ask => self.occupied_at_or_above(0),
+        }
+    }
+
+    /// Stores `entry` in the window if `price` is in range, otherwise in the overflow map.
+    fn place(&mut self, price: Price, entry: LevelEntry) {
+        match self.slot(price) {
+            Some(slot) => {
+                self.occupied[slot / 64] |= 1u64 << (slot % 64);
+                self.slots[slot] = entry;
+                self.in_window += 1;
+            }
+            None => {
+                self.overflow.insert(price, entry);
+            }
+        }
+    }
+
+    /// Rebuilds the window centred on `centre`, moving levels between array and overflow.
+    fn recentre(&mut self, centre: Price) {
+        let old_base = self.base;
+        let old_slots = std::mem::replace(
+            &mut self.slots,
+            vec![LevelEntry::default(); N].into_boxed_slice(),
+        );
+        let old_occupied =
+            std::mem::replace(&mut self.occupied, vec![0u64; N / 64].into_boxed_slice());
+        let old_overflow = std::mem::take(&mut self.overflow);
+
+        self.base = centre.offset(-(N as i64 / 2));
+        self.in_window = 0;
+        for (word_idx, mut bits) in old_occupied.iter().copied().enumerate() {
+            while bits != 0 {
+                let slot = word_idx * 64 + bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                self.place(old_base.offset(slot as i64), old_slots[slot]);
+            }
+        }
+        for (price, entry) in old_overflow {
+            self.place(price, entry);
+        }
+        self.best = self.scan_best();
+    }
+}
+
+impl<const N: usize> PriceLevels for PriceLadder<N> {
+    fn new(side: Side) -> Self {
+        assert!(
+            N > 0 && N.is_multiple_of(64),
+            "ladder size must be a non-zero multiple of 64"
+        );
+        PriceLadder {
+            side,
+            base: Price::ZERO,
+            slots: vec![LevelEntry::default(); N].into_boxed_slice(),
+            occupied: vec![0u64; N / 64].into_boxed_slice(),
+            best: None,
+            in_window: 0,
+            overflow: BTreeMap::new(),
+        }
+    }
+
+    #[inline]
+    fn get(&self, price: Price) -> Option<&LevelEntry> {
+        match self.slot(price) {
+            Some(slot) => self.is_occupied(slot).then(|| &self.slots[slot]),
+            None => self.overflow.get(&price),
+        }
+    }
+
+    #[inline]
+    fn get_mut(&mut self, price: Price) -> Option<&mut LevelEntry> {
+        match self.slot(price) {
+            Some(slot) => {
+                if self.is_occupied(slot) {
+                    Some(&mut self.slots[slot])
+                } else {
+                    None
+                }
+            }
+            None => self.overflow.get_mut(&price),
+        }
+    }
+
+    fn get_or_insert(&mut self, price: Price) -> &mut LevelEntry {
+        let slot = match self.slot(price) {
+            Some(slot) => slot,
+            None => {
+                let better_than_best = match self.best {
+                    None => true,
+                    Some(best) => match self.side {
+                        Side::Bid => price > self.price_at(best),
+                        Side::Ask => price < self.price_at(best),
+                    },
+                };
+                if !better_than_best {
+                    return self.overflow.entry(price).or_default();
+                }
+                self.recentre(price);
+                self.slot(price).expect("window is centred on price")
+            }
+        };
+
+        if !self.is_occupied(slot) {
+            self.occupied[slot / 64] |= 1u64 << (slot % 64);
+            self.slots[slot] = LevelEntry::default();
+            self.in_window += 1;
+            if self.best.is_none_or(|best| self.is_better(slot, best)) {
+                self.best = Some(slot);
+            }
+        }
+        &mut self.slots[slot]
+    }
+
+    fn remove(&mut self, price: Price) -> Option<LevelEntry> {
+        let Some(slot) = self.slot(price) else {
+            return self.overflow.remove(&price);
+        };
+        if !self.is_occupied(slot) {
+            return None;
+        }
+
+        self.occupied[slot / 64] &= !(1u64 << (slot % 64));
+        self.in_window -= 1;
+        let entry = std::mem::take(&mut self.slots[slot]);
+        if self.best == Some(slot) {
+            self.best = self.next_worse(slot);
+        }
+        if self.in_window == 0 {
+            // Keep the best level inside the window.
+            let next = match self.side {
+                Side::Bid => self.overflow.keys().next_back(),
+                Side::Ask => self.overflow.keys().next(),
+            };
+            if let Some(&next) = next {
+                self.recentre(next);
+            }
+        }
+        Some(entry)
+    }
+
+    #[inline]
+    fn best(&self) -> Option<(Price, &LevelEntry)> {
+        self.best
+            .map(|slot| (self.price_at(slot), &self.slots[slot]))
+    }
+
+    fn len(&self) -> usize {
+        self.in_window + self.overflow.len()
+    }
+
+    fn visit(&self, mut f: impl FnMut(Price, &LevelEntry) -> bool) {
+        let mut cursor = self.best;
+        while let Some(slot) = cursor {
+            if !f(self.price_at(slot), &self.slots[slot]) {
+                return;
+            }
+            cursor = self.next_worse(slot);
+        }
+        // Everything in the overflow map is worse than the window.
+        match self.side {
+            Side::Bid => {
+                for (p, l) in self.overflow.iter().rev() {
+                    if !f(*p, l) {
+                        return;
+                    }
+                }
+            }
+            Side::Ask => {
+                for (p, l) in self.overflow.iter() {
+                    if !f(*p, l) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.slots.fill(LevelEntry::default());
+        self.occupied.fill(0);
+        self.best = None;
+        self.in_window = 0;
+        self.overflow.clear();
+    }
+
+    fn follow_mid(&mut self, mid: Price) {
+        let half = N as i64 / 2;
+        let centre = self.base.offset(half);
+        if mid.ticks().abs_diff(centre.ticks()) <= N as u64 / 4 {
+            return;
+        }
+        // The best level must stay inside the window, so a side whose touch is half a window
+        // or more away from the mid stays centred on its touch.
+        if let Some((best, _)) = self.best() {
+            if best.ticks().abs_diff(mid.ticks()) >= half as u64 {
+                return;
+            }
+        }
+        self.recentre(mid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::price_levels::TreeLevels;
+
+    fn px(ticks: i64) -> Price {
+        Price::from_ticks(ticks)
+    }
+
+    fn set<L: PriceLevels>(levels: &mut L, ticks: i64, size: u64) {
+        if size == 0 {
+            levels.remove(px(ticks));
+        } else {
+            levels.get_or_insert(px(ticks)).size = size;
+        }
+    }
+
+    fn collect<L: PriceLevels>(levels: &L) -> Vec<(i64, u64)> {
+        let mut out = Vec::new();
+        levels.visit(|p, l| {
+            out.push((p.ticks(), l.size()));
+            true
+        });
+        out
+    }
+
+    #[test]
+    fn test_ladder_best_and_order() {
+        let mut bids = PriceLadder::<64>::new(Side::Bid);
+        set(&mut bids, 100, 1);
+        set(&mut bids, 102, 2);
+        set(&mut bids, 99, 3);
+        assert_eq!(
+            bids.best().map(|(p, l)| (p.ticks(), l.size())),
+            Some((102, 2))
+        );
+        assert_eq!(collect(&bids), vec![(102, 2), (100, 1), (99, 3)]);
+
+        set(&mut bids, 102, 0);
+        assert_eq!(bids.best().map(|(p, _)| p.ticks()), Some(100));
+        assert_eq!(bids.len(), 2);
+    }
+
+    #[test]
+    fn test_ladder_recentres_and_overflows() {
+        let mut asks = PriceLadder::<64>::new(Side::Ask);
+        set(&mut asks, 1000, 1);
+        // Far worse price goes to the overflow map.
+        set(&mut asks, 1200, 2);
+        // Better price outside the window re-centres around it.
+        set(&mut asks, 900, 3);
+        assert_eq!(collect(&asks), vec![(900, 3), (1000, 1), (1200, 2)]);
+
+        // Emptying the window pulls the next best level back in.
+        set(&mut asks, 900, 0);
+        set(&mut asks, 1000, 0);
+        assert_eq!(asks.best().map(|(p, _)| p.ticks()), Some(1200));
+        assert_eq!(asks.get(px(1200)).map(|l| l.size()), Some(2));
+    }
+
+    #[test]
+    fn test_ladder_follows_mid() {
+        let mut bids = PriceLadder::<64>::new(Side::Bid);
+        set(&mut bids, 1000, 1);
+        set(&mut bids, 990, 2);
+        assert_eq!(bids.base, px(968));
+
+        // Within a quarter window of the centre: no move.
+        bids.follow_mid(px(1010));
+        assert_eq!(bids.base, px(968));
+        bids.follow_mid(px(1020));
+        assert_eq!(bids.base, px(988));
+        assert_eq!(collect(&bids), vec![(1000, 1), (990, 2)]);
+        assert_eq!(bids.overflow.len(), 0);
+
+        // The mid has moved too far from the touch for both to fit.
+        bids.follow_mid(px(1040));
+        assert_eq!(bids.base, px(988));
+        assert_eq!(bids.best().map(|(p, _)| p.ticks()), Some(1000));
+    }
+
+    #[test]
+    fn test_ladder_matches_tree() {
+        // Deterministic random walk; both backends must agree on every step.
+        let mut ladder = PriceLadder::<128>::new(Side::Bid);
+        let mut tree = TreeLevels::new(Side::Bid);
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut mid: i64 = 10_000;
+        for _ in 0..5_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            mid += (state % 7) as i64 - 3;
+            let ticks = mid - (state >> 8) as i64 % 200;
+            let size = (state >> 20) % 4;
+            set(&mut ladder, ticks, size);
+            set(&mut tree, ticks, size);
+            ladder.follow_mid(px(mid));
+            assert_eq!(ladder.len(), tree.len());
+            assert_eq!(
+                ladder.best().map(|(p, l)| (p, *l)),
+                tree.best().map(|(p, l)| (p, *l))
+            );
+        }
+        assert_eq!(collect(&ladder), collect(&tree));
+    }
+}
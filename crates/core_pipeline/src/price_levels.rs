@@ -0,0 +1,150 @@
+//! price_levels.rs
+//! Storage backends for one side of an order book.
+//!
+//! # Design
+//! - `PriceLevels` is the common trait the `OrderBook` is generic over.
+//! - `TreeLevels` keeps levels in a `BTreeMap`: O(log n) everything, no range limits.
+//! - `PriceLadder` (see `price_ladder.rs`) keeps levels in a flat array indexed by tick
+//!   offset for constant-time lookups near the touch.
+//! - Each side knows whether it is a bid or ask side, so "best" and iteration order are
+//!   handled by the backend rather than the caller.
+//! - After each update to a two-sided book the `OrderBook` passes the mid price to both sides
+//!   (`follow_mid`), for backends that keep a window around it.
+
+use std::collections::BTreeMap;
+
+use common::price::Price;
+
+use super::order_book::Side;
+
+/// Per-level state stored by a backend: the aggregated size plus, in L3 mode, the ends
+/// of the level's FIFO order queue. Only the book itself writes these fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LevelEntry {
+    pub(crate) size: u64,
+    pub(crate) order_count: u32,
+    pub(crate) head: Option<u64>,
+    pub(crate) tail: Option<u64>,
+}
+
+impl LevelEntry {
+    /// Total quantity resting at the level.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Number of individual orders at the level (0 for L2 books).
+    pub fn order_count(&self) -> u32 {
+        self.order_count
+    }
+}
+
+/// One side of a book: a set of price levels ordered from best to worst.
+pub trait PriceLevels {
+    /// Creates an empty side. Bids treat the highest price as best, asks the lowest.
+    fn new(side: Side) -> Self;
+
+    /// Returns the level at `price`, if present.
+    fn get(&self, price: Price) -> Option<&LevelEntry>;
+
+    /// Returns the level at `price` mutably, if present.
+    fn get_mut(&mut self, price: Price) -> Option<&mut LevelEntry>;
+
+    /// Returns the level at `price`, inserting an empty one first if needed.
+    fn get_or_insert(&mut self, price: Price) -> &mut LevelEntry;
+
+    /// Removes the level at `price` and returns it.
+    fn remove(&mut self, price: Price) -> Option<LevelEntry>;
+
+    /// Returns the best level.
+    fn best(&self) -> Option<(Price, &LevelEntry)>;
+
+    /// Number of levels.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Visits levels from best to worst until `f` returns `false`.
+    fn visit(&self, f: impl FnMut(Price, &LevelEntry) -> bool);
+
+    /// Removes every level.
+    fn clear(&mut self);
+
+    /// Called with the book's mid price after an update that leaves both sides non-empty.
+    /// Backends with a bounded window re-centre on it; the default does nothing.
+    fn follow_mid(&mut self, _mid: Price) {}
+}
+
+/// Tree-based side backed by a `BTreeMap`.
+#[derive(Debug, Clone)]
+pub struct TreeLevels {
+    side: Side,
+    levels: BTreeMap<Price, LevelEntry>,
+}
+
+impl PriceLevels for TreeLevels {
+    fn new(side: Side) -> Self {
+        TreeLevels {
+            side,
+            levels: BTreeMap::new(),
+        }
+    }
+
+    #[inline]
+    fn get(&self, price: Price) -> Option<&LevelEntry> {
+        self.levels.get(&price)
+    }
+
+    #[inline]
+    fn get_mut(&mut self, price: Price) -> Option<&mut LevelEntry> {
+        self.levels.get_mut(&price)
+    }
+
+    #[inline]
+    fn get_or_insert(&mut self, price: Price) -> &mut LevelEntry {
+        self.levels.entry(price).or_default()
+    }
+
+    #[inline]
+    fn remove(&mut self, price: Price) -> Option<LevelEntry> {
+        self.levels.remove(&price)
+    }
+
+    #[inline]
+    fn best(&self) -> Option<(Price, &LevelEntry)> {
+        let best = match self.side {
+            Side::Bid => self.levels.iter().next_back(),
+            Side::Ask => self.levels.iter().next(),
+        };
+        best.map(|(p, l)| (*p, l))
+    }
+
+    fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    fn visit(&self, mut f: impl FnMut(Price, &LevelEntry) -> bool) {
+        match self.side {
+            Side::Bid => {
+                for (p, l) in self.levels.iter().rev() {
+                    if !f(*p, l) {
+                        return;
+                    }
+                }
+            }
+            Side::Ask => {
+                for (p, l) in self.levels.iter() {
+                    if !f(*p, l) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.levels.clear();
+    }
+}
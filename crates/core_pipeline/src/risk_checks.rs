@@ -4,17 +4,877 @@
 //! # Purpose
 //! - Prevent placing orders that exceed pre-defined limits.
 //! - This must be done quickly to not impact latency.
+//!
+//! # Key Concepts
+//! - `RiskLimits` is a set of optional limits; any subset can be enabled.
+//! - A tripped `KillSwitch` (global or for the order's account) rejects every order before any
+//!   limit is checked. `update_kill_switch` feeds it the current PnL for its loss limits and
+//!   the caller feeds it every rejected order with `record_reject` for its reject storms.
+//! - Limits are configured per instrument (applying to all accounts trading it) and per
+//!   account. An order must pass the instrument limits, then the account limits.
+//! - `check` evaluates the rules in a fixed order (quantity, notional, price collar, open
+//!   orders, position, daily loss) and returns the first violation with its scope and typed
+//!   reason.
+//! - Open orders are fed back by the caller with `order_opened` and `order_closed`.
+//!   Positions and PnL come from the checker's `PositionKeeper`, fed with `record_execution`
+//!   and marked with `positions_mut().mark_from_book(..)`.
+//! - `check_with_book` additionally validates the order against the live book: a stale or
+//!   crossed book, a limit price too far through the opposite touch, or outside a band around
+//!   the last trade (falling back to the reference price). A book its `BookBuilder` reports as
+//!   `Stale` always fails; `check_with_builder` reads that state for the caller. The caller
+//!   feeds book update times and trades with `record_book_update` and `record_trade`.
+//! - Notional, the percentage through the touch and daily loss are in currency, converted
+//!   with the `PositionKeeper` tick values (tick size times contract multiplier), since ticks
+//!   are worth a different amount in each instrument. A rule that needs a missing tick value
+//!   fails with `NoTickValue`.
+//! - Message rate limits (orders and cancels per second, order-to-trade ratio) are applied
+//!   separately by `throttle::Throttle`, since they consume state on every message.
+
+use std::collections::HashMap;
+
+use common::price::Price;
+
+use super::book_builder::{BookBuilder, BookState};
+use super::kill_switch::{KillScope, KillSwitch};
+use super::order_book::{OrderBook, Side};
+use super::order_types::{AccountId, Order};
+use super::positions::{Execution, PnlError, PositionKeeper};
+use super::price_levels::PriceLevels;
+
+/// Optional pre-trade limits. `None` disables a rule.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RiskLimits {
+    pub max_order_quantity: Option<u64>,
+    /// Maximum value of a single order in currency: price ticks times quantity times the
+    /// instrument's tick value.
+    pub max_notional: Option<u64>,
+    /// Maximum distance in ticks between a limit price and the reference price.
+    pub price_collar_ticks: Option<u64>,
+    pub max_open_orders: Option<u32>,
+    /// Maximum absolute position if the order fills completely.
+    pub max_position: Option<u64>,
+    /// Maximum loss since the start of the day (realised plus unrealised), in currency.
+    pub max_daily_loss: Option<u64>,
+    /// Maximum ticks a limit price may be through the opposite touch.
+    pub max_ticks_through_touch: Option<u64>,
+    /// Maximum value through the opposite touch in basis points of the order's value at the
+    /// touch, both in currency.
+    pub max_bps_through_touch: Option<u32>,
+    /// Maximum distance in ticks between a limit price and the last trade, or the reference
+    /// price before the first trade.
+    pub trade_band_ticks: Option<u64>,
+    /// Maximum time since the last book update.
+    pub max_book_age_ns: Option<u64>,
+    pub reject_crossed_book: bool,
+}
+
+/// Which set of limits was violated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiskScope {
+    /// The global kill switch.
+    Global,
+    Instrument,
+    Account,
+}
+
+/// The rule an order violated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiskReason {
+    /// The kill switch of the violation's scope is tripped.
+    KillSwitch,
+    MaxOrderQuantity {
+        limit: u64,
+        quantity: u64,
+    },
+    /// `notional` is the order's value in currency, rounded up to whole units.
+    MaxNotional {
+        limit: u64,
+        notional: u64,
+    },
+    /// The limit price is further than `limit_ticks` from the reference price.
+    PriceCollar {
+        reference: Price,
+        price: Price,
+        limit_ticks: u64,
+    },
+    /// A notional or collar limit is configured but the instrument has no reference price.
+    NoReferencePrice,
+    MaxOpenOrders {
+        limit: u32,
+    },
+    /// `position` is what the position would become if the order filled.
+    MaxPosition {
+        limit: u64,
+        position: i64,
+    },
+    /// `pnl` is the current daily PnL, rounded down to whole currency units.
+    MaxDailyLoss {
+        limit: u64,
+        pnl: i64,
+    },
+    /// A notional, through-touch or daily loss limit is configured but an instrument it
+    /// needs has no tick value.
+    NoTickValue,
+    /// The book has not been updated for `age_ns`; `None` if it never was.
+    StaleBook {
+        age_ns: Option<u64>,
+    },
+    CrossedBook {
+        bid: Price,
+        ask: Price,
+    },
+    /// The limit price is further through the opposite touch than allowed.
+    ThroughTouch {
+        touch: Price,
+        price: Price,
+    },
+    /// The limit price is further than `band_ticks` from the last trade or reference price.
+    OutsideBand {
+        anchor: Price,
+        price: Price,
+        band_ticks: u64,
+    },
+    /// The book builder missed updates and is waiting for a snapshot.
+    BookNotLive {
+        state: BookState,
+    },
+}
+
+/// First failed rule, returned by `RiskChecker::check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RiskViolation {
+    pub scope: RiskScope,
+    pub reason: RiskReason,
+}
 
+/// Exposure the limits are checked against.
+#[derive(Debug, Clone, Copy)]
+struct Exposure {
+    open_orders: u32,
+    position: i64,
+    /// Whose daily PnL the loss limit applies to: the account's over all instruments, or the
+    /// instrument's over all accounts if `None`. Only computed when a loss limit is set.
+    pnl_account: Option<AccountId>,
+}
+
+/// Market state fed by the caller for the book checks.
+#[derive(Debug, Clone, Copy, Default)]
+struct Market {
+    last_trade: Option<Price>,
+    book_updated_ns: Option<u64>,
+}
+
+#[derive(Debug, Default)]
 pub struct RiskChecker {
-    max_order_size: u64,
+    instrument_limits: HashMap<String, RiskLimits>,
+    account_limits: HashMap<AccountId, RiskLimits>,
+    reference_prices: HashMap<String, Price>,
+    markets: HashMap<String, Market>,
+    /// Open orders per instrument, over all accounts.
+    instrument_open_orders: HashMap<String, u32>,
+    /// Open orders per account, over all instruments.
+    account_open_orders: HashMap<AccountId, u32>,
+    positions: PositionKeeper,
+    kill_switch: KillSwitch,
 }
 
 impl RiskChecker {
-    pub fn new(max_order_size: u64) -> Self {
-        RiskChecker { max_order_size }
+    pub fn new() -> Self {
+        RiskChecker::default()
+    }
+
+    pub fn set_instrument_limits(&mut self, symbol: &str, limits: RiskLimits) {
+        self.instrument_limits.insert(symbol.to_string(), limits);
+    }
+
+    pub fn set_account_limits(&mut self, account: AccountId, limits: RiskLimits) {
+        self.account_limits.insert(account, limits);
+    }
+
+    /// Sets the reference price used for the price collar and for the notional of market
+    /// orders (e.g. the last trade or the previous close).
+    pub fn set_reference_price(&mut self, symbol: &str, price: Price) {
+        self.reference_prices.insert(symbol.to_string(), price);
+    }
+
+    /// Records the time `symbol`'s book was last updated.
+    pub fn record_book_update(&mut self, symbol: &str, now_ns: u64) {
+        self.market_mut(symbol).book_updated_ns = Some(now_ns);
+    }
+
+    /// Records the last trade price of `symbol`, the anchor of the trade band.
+    pub fn record_trade(&mut self, symbol: &str, price: Price) {
+        self.market_mut(symbol).last_trade = Some(price);
+    }
+
+    fn market_mut(&mut self, symbol: &str) -> &mut Market {
+        if !self.markets.contains_key(symbol) {
+            self.markets.insert(symbol.to_string(), Market::default());
+        }
+        self.markets.get_mut(symbol).unwrap()
+    }
+
+    /// Runs `check`, then checks `order` against the live `book` at `now_ns` with the
+    /// instrument's and then the account's book limits. `state` is the symbol's state in the
+    /// `BookBuilder` feeding `book` (`Live` for a book not built from a feed); a `Stale` book
+    /// is rejected whatever the limits, since its levels may be missing updates. A
+    /// `Recovering` book is current as of its snapshot and is checked normally.
+    pub fn check_with_book<L: PriceLevels>(
+        &self,
+        order: &Order,
+        book: &OrderBook<L>,
+        state: BookState,
+        now_ns: u64,
+    ) -> Result<(), RiskViolation> {
+        self.check(order)?;
+        if state == BookState::Stale {
+            return Err(RiskViolation {
+                scope: RiskScope::Instrument,
+                reason: RiskReason::BookNotLive { state },
+            });
+        }
+        let scopes = [
+            (
+                RiskScope::Instrument,
+                self.instrument_limits.get(&order.symbol),
+            ),
+            (
+                RiskScope::Account,
+                order
+                    .account
+                    .and_then(|account| self.account_limits.get(&account)),
+            ),
+        ];
+        for (scope, limits) in scopes {
+            if let Some(limits) = limits {
+                self.check_book_limits(limits, order, book, now_ns)
+                    .map_err(|reason| RiskViolation { scope, reason })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `check_with_book` against the builder's book and its state for the order's symbol.
+    pub fn check_with_builder<L: PriceLevels>(
+        &self,
+        order: &Order,
+        builder: &BookBuilder<L>,
+        now_ns: u64,
+    ) -> Result<(), RiskViolation> {
+        let state = builder.state(&order.symbol);
+        self.check_with_book(order, builder.book(), state, now_ns)
+    }
+
+    fn check_book_limits<L: PriceLevels>(
+        &self,
+        limits: &RiskLimits,
+        order: &Order,
+        book: &OrderBook<L>,
+        now_ns: u64,
+    ) -> Result<(), RiskReason> {
+        let market = self.markets.get(&order.symbol).copied().unwrap_or_default();
+        if let Some(max_age) = limits.max_book_age_ns {
+            let age_ns = market
+                .book_updated_ns
+                .map(|updated| now_ns.saturating_sub(updated));
+            if age_ns.is_none_or(|age| age > max_age) {
+                return Err(RiskReason::StaleBook { age_ns });
+            }
+        }
+
+        let (bid, ask) = book.best_bid_ask(&order.symbol);
+        if let (true, Some((bid, _)), Some((ask, _))) = (limits.reject_crossed_book, bid, ask) {
+            if bid >= ask {
+                return Err(RiskReason::CrossedBook { bid, ask });
+            }
+        }
+
+        let Some(price) = order.limit else {
+            return Ok(());
+        };
+        let touch = match order.side {
+            Side::Bid => ask,
+            Side::Ask => bid,
+        };
+        if let Some((touch, _)) = touch {
+            let through = match order.side {
+                Side::Bid => price.ticks() - touch.ticks(),
+                Side::Ask => touch.ticks() - price.ticks(),
+            };
+            let too_many_ticks = limits
+                .max_ticks_through_touch
+                .is_some_and(|limit| through > 0 && through as u64 > limit);
+            let too_many_bps = match limits.max_bps_through_touch {
+                Some(limit) if through > 0 => {
+                    let through = self.value(order, through as u64)?;
+                    let at_touch = self.value(order, touch.ticks().unsigned_abs())?;
+                    through * 10_000.0 > limit as f64 * at_touch
+                }
+                _ => false,
+            };
+            if too_many_ticks || too_many_bps {
+                return Err(RiskReason::ThroughTouch { touch, price });
+            }
+        }
+
+        if let Some(band_ticks) = limits.trade_band_ticks {
+            let anchor = market
+                .last_trade
+                .or_else(|| self.reference_prices.get(&order.symbol).copied())
+                .ok_or(RiskReason::NoReferencePrice)?;
+            if price.ticks().abs_diff(anchor.ticks()) > band_ticks {
+                return Err(RiskReason::OutsideBand {
+                    anchor,
+                    price,
+                    band_ticks,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks `order` against its instrument's limits and then its account's limits.
+    pub fn check(&self, order: &Order) -> Result<(), RiskViolation> {
+        if let Some(scope) = self.kill_switch.blocks(order.account) {
+            return Err(RiskViolation {
+                scope: match scope {
+                    KillScope::Global => RiskScope::Global,
+                    KillScope::Account(_) => RiskScope::Account,
+                },
+                reason: RiskReason::KillSwitch,
+            });
+        }
+        if let Some(limits) = self.instrument_limits.get(&order.symbol) {
+            let instrument = Exposure {
+                open_orders: self
+                    .instrument_open_orders
+                    .get(&order.symbol)
+                    .copied()
+                    .unwrap_or(0),
+                position: self.positions.position(&order.symbol).net,
+                pnl_account: None,
+            };
+            self.check_limits(limits, order, instrument)
+                .map_err(|reason| RiskViolation {
+                    scope: RiskScope::Instrument,
+                    reason,
+                })?;
+        }
+
+        let Some(account) = order.account else {
+            return Ok(());
+        };
+        if let Some(limits) = self.account_limits.get(&account) {
+            let exposure = Exposure {
+                open_orders: self.account_open_orders.get(&account).copied().unwrap_or(0),
+                position: self.positions.account_position(account, &order.symbol).net,
+                pnl_account: Some(account),
+            };
+            self.check_limits(limits, order, exposure)
+                .map_err(|reason| RiskViolation {
+                    scope: RiskScope::Account,
+                    reason,
+                })?;
+        }
+        Ok(())
+    }
+
+    fn check_limits(
+        &self,
+        limits: &RiskLimits,
+        order: &Order,
+        exposure: Exposure,
+    ) -> Result<(), RiskReason> {
+        if let Some(limit) = limits.max_order_quantity {
+            if order.quantity > limit {
+                return Err(RiskReason::MaxOrderQuantity {
+                    limit,
+                    quantity: order.quantity,
+                });
+            }
+        }
+
+        let reference = self.reference_prices.get(&order.symbol).copied();
+        if let Some(limit) = limits.max_notional {
+            let price = order
+                .limit
+                .or(reference)
+                .ok_or(RiskReason::NoReferencePrice)?;
+            let notional = self.value(order, price.ticks().unsigned_abs())?.ceil() as u64;
+            if notional > limit {
+                return Err(RiskReason::MaxNotional { limit, notional });
+            }
+        }
+
+        if let (Some(limit_ticks), Some(price)) = (limits.price_collar_ticks, order.limit) {
+            let reference = reference.ok_or(RiskReason::NoReferencePrice)?;
+            if price.ticks().abs_diff(reference.ticks()) > limit_ticks {
+                return Err(RiskReason::PriceCollar {
+                    reference,
+                    price,
+                    limit_ticks,
+                });
+            }
+        }
+
+        if let Some(limit) = limits.max_open_orders {
+            if exposure.open_orders >= limit {
+                return Err(RiskReason::MaxOpenOrders { limit });
+            }
+        }
+
+        if let Some(limit) = limits.max_position {
+            let position = exposure.position + signed(order.side, order.quantity);
+            if position.unsigned_abs() > limit {
+                return Err(RiskReason::MaxPosition { limit, position });
+            }
+        }
+
+        if let Some(limit) = limits.max_daily_loss {
+            let pnl = match exposure.pnl_account {
+                Some(account) => self.positions.account_pnl(account),
+                None => self.positions.pnl_value(&order.symbol),
+            }
+            .map_err(|_| RiskReason::NoTickValue)?
+            .total();
+            if pnl < -(limit as f64) {
+                return Err(RiskReason::MaxDailyLoss {
+                    limit,
+                    pnl: pnl.floor() as i64,
+                });
+            }
+        }
+        Ok(())
     }
 
-    pub fn check_order_size(&self, size: u64) -> bool {
-        size <= self.max_order_size
+    /// Currency value of `ticks` for the order's quantity.
+    fn value(&self, order: &Order, ticks: u64) -> Result<f64, RiskReason> {
+        let tick_value = self
+            .positions
+            .tick_value(&order.symbol)
+            .ok_or(RiskReason::NoTickValue)?;
+        Ok(ticks as f64 * order.quantity as f64 * tick_value)
+    }
+
+    /// Records that an order is now working (accepted and not yet filled or cancelled).
+    pub fn order_opened(&mut self, order: &Order) {
+        *self
+            .instrument_open_orders
+            .entry(order.symbol.clone())
+            .or_default() += 1;
+        if let Some(account) = order.account {
+            *self.account_open_orders.entry(account).or_default() += 1;
+        }
+    }
+
+    /// Records that a working order was filled, cancelled or expired.
+    pub fn order_closed(&mut self, order: &Order) {
+        if let Some(count) = self.instrument_open_orders.get_mut(&order.symbol) {
+            *count = count.saturating_sub(1);
+        }
+        if let Some(count) = order
+            .account
+            .and_then(|account| self.account_open_orders.get_mut(&account))
+        {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    /// Updates positions and PnL with one of our executions.
+    pub fn record_execution(&mut self, execution: &Execution) {
+        self.positions.on_execution(execution);
+    }
+
+    /// Counts a rejected order, refused by these checks or by the venue, towards the kill
+    /// switch reject storms of its account and the global switch.
+    pub fn record_reject(&mut self, order: &Order, now_ns: u64) {
+        self.kill_switch.on_reject(order.account, now_ns);
+    }
+
+    pub fn positions(&self) -> &PositionKeeper {
+        &self.positions
+    }
+
+    /// Mutable access for marking positions and the daily PnL reset.
+    pub fn positions_mut(&mut self) -> &mut PositionKeeper {
+        &mut self.positions
+    }
+
+    pub fn kill_switch(&self) -> &KillSwitch {
+        &self.kill_switch
+    }
+
+    /// Mutable access for operator commands, conditions and reject/market data feeds.
+    pub fn kill_switch_mut(&mut self) -> &mut KillSwitch {
+        &mut self.kill_switch
+    }
+
+    /// Checks the kill switch loss limits against the current total and per-account PnL, in
+    /// currency. A scope whose PnL cannot be converted is skipped and the first such error is
+    /// returned once the others have been checked.
+    pub fn update_kill_switch(&mut self, now_ns: u64) -> Result<(), PnlError> {
+        let mut result = Ok(());
+        match self.positions.total_pnl() {
+            Ok(total) => self
+                .kill_switch
+                .on_pnl(KillScope::Global, total.total(), now_ns),
+            Err(e) => result = Err(e),
+        }
+        let accounts: Vec<AccountId> = self.kill_switch.accounts().collect();
+        for account in accounts {
+            match self.positions.account_pnl(account) {
+                Ok(pnl) => {
+                    self.kill_switch
+                        .on_pnl(KillScope::Account(account), pnl.total(), now_ns)
+                }
+                Err(e) => result = result.and(Err(e)),
+            }
+        }
+        result
+    }
+}
+
+/// Quantity as a position change: buys positive, sells negative.
+fn signed(side: Side, quantity: u64) -> i64 {
+    match side {
+        Side::Bid => quantity as i64,
+        Side::Ask => -(quantity as i64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kill_switch::{KillCommand, KillConditions, KillState, RejectStorm, TripReason};
+
+    fn px(ticks: i64) -> Price {
+        Price::from_ticks(ticks)
+    }
+
+    #[test]
+    fn test_instrument_limits_in_order() {
+        let mut risk = RiskChecker::new();
+        risk.set_instrument_limits(
+            "ES",
+            RiskLimits {
+                max_order_quantity: Some(10),
+                max_notional: Some(60_000),
+                price_collar_ticks: Some(5),
+                ..RiskLimits::default()
+            },
+        );
+        let order = Order::limit(1, "ES", Side::Bid, px(100), 20);
+        let violation = risk.check(&order).unwrap_err();
+        assert_eq!(violation.scope, RiskScope::Instrument);
+        assert_eq!(
+            violation.reason,
+            RiskReason::MaxOrderQuantity {
+                limit: 10,
+                quantity: 20
+            }
+        );
+
+        let order = Order::limit(2, "ES", Side::Bid, px(500), 10);
+        assert_eq!(
+            risk.check(&order).unwrap_err().reason,
+            RiskReason::NoTickValue
+        );
+        // 500 ticks of 12.5 for 10 contracts.
+        risk.positions_mut().set_tick_value("ES", 12.5);
+        assert_eq!(
+            risk.check(&order).unwrap_err().reason,
+            RiskReason::MaxNotional {
+                limit: 60_000,
+                notional: 62_500
+            }
+        );
+        let order = Order::limit(3, "ES", Side::Bid, px(100), 10);
+        assert_eq!(
+            risk.check(&order).unwrap_err().reason,
+            RiskReason::NoReferencePrice
+        );
+        risk.set_reference_price("ES", px(94));
+        assert!(matches!(
+            risk.check(&order).unwrap_err().reason,
+            RiskReason::PriceCollar { .. }
+        ));
+        risk.set_reference_price("ES", px(98));
+        assert_eq!(risk.check(&order), Ok(()));
+    }
+
+    #[test]
+    fn test_account_open_orders_and_position() {
+        let mut risk = RiskChecker::new();
+        risk.set_account_limits(
+            7,
+            RiskLimits {
+                max_open_orders: Some(1),
+                max_position: Some(5),
+                ..RiskLimits::default()
+            },
+        );
+        let first = Order::limit(1, "ES", Side::Bid, px(100), 4).with_account(7);
+        assert_eq!(risk.check(&first), Ok(()));
+        risk.order_opened(&first);
+
+        let second = Order::limit(2, "NQ", Side::Bid, px(100), 1).with_account(7);
+        assert_eq!(
+            risk.check(&second),
+            Err(RiskViolation {
+                scope: RiskScope::Account,
+                reason: RiskReason::MaxOpenOrders { limit: 1 }
+            })
+        );
+
+        risk.record_execution(&Execution {
+            account: Some(7),
+            symbol: "ES".to_string(),
+            side: Side::Bid,
+            price: px(100),
+            quantity: 4,
+        });
+        risk.order_closed(&first);
+        let third = Order::limit(3, "ES", Side::Bid, px(100), 2).with_account(7);
+        assert_eq!(
+            risk.check(&third).unwrap_err().reason,
+            RiskReason::MaxPosition {
+                limit: 5,
+                position: 6
+            }
+        );
+        let other = Order::limit(4, "ES", Side::Bid, px(100), 2).with_account(8);
+        assert_eq!(risk.check(&other), Ok(()));
+        assert_eq!(risk.positions().account_position(7, "ES").net, 4);
+    }
+
+    #[test]
+    fn test_daily_loss_from_marked_positions() {
+        let mut risk = RiskChecker::new();
+        risk.set_instrument_limits(
+            "ES",
+            RiskLimits {
+                max_daily_loss: Some(10),
+                ..RiskLimits::default()
+            },
+        );
+        risk.record_execution(&Execution {
+            account: None,
+            symbol: "ES".to_string(),
+            side: Side::Bid,
+            price: px(100),
+            quantity: 3,
+        });
+        let order = Order::limit(1, "ES", Side::Bid, px(95), 1);
+        risk.positions_mut().mark("ES", 97.0);
+        assert_eq!(
+            risk.check(&order).unwrap_err().reason,
+            RiskReason::NoTickValue
+        );
+        risk.positions_mut().set_tick_value("ES", 1.0);
+        assert_eq!(risk.check(&order), Ok(()));
+        risk.positions_mut().mark("ES", 96.5);
+        assert_eq!(
+            risk.check(&order).unwrap_err().reason,
+            RiskReason::MaxDailyLoss {
+                limit: 10,
+                pnl: -11
+            }
+        );
+        risk.positions_mut().reset_daily_pnl();
+        assert_eq!(risk.check(&order), Ok(()));
+    }
+
+    #[test]
+    fn test_book_checks() {
+        let mut risk = RiskChecker::new();
+        risk.set_instrument_limits(
+            "ES",
+            RiskLimits {
+                max_book_age_ns: Some(1_000),
+                reject_crossed_book: true,
+                max_ticks_through_touch: Some(5),
+                max_bps_through_touch: Some(200),
+                trade_band_ticks: Some(10),
+                ..RiskLimits::default()
+            },
+        );
+        risk.positions_mut().set_tick_value("ES", 12.5);
+        let mut book = OrderBook::new();
+        book.apply_quote("ES", Side::Bid, px(99), 1).unwrap();
+        book.apply_quote("ES", Side::Ask, px(101), 1).unwrap();
+        let order = Order::limit(1, "ES", Side::Bid, px(104), 1);
+        assert_eq!(
+            risk.check_with_book(&order, &book, BookState::Live, 0)
+                .unwrap_err()
+                .reason,
+            RiskReason::StaleBook { age_ns: None }
+        );
+        risk.record_book_update("ES", 100);
+        assert_eq!(
+            risk.check_with_book(&order, &book, BookState::Live, 1_200)
+                .unwrap_err()
+                .reason,
+            RiskReason::StaleBook {
+                age_ns: Some(1_100)
+            }
+        );
+
+        // 3 ticks through a touch of 101 is within 5 ticks but over 2%.
+        assert_eq!(
+            risk.check_with_book(&order, &book, BookState::Live, 200)
+                .unwrap_err()
+                .reason,
+            RiskReason::ThroughTouch {
+                touch: px(101),
+                price: px(104)
+            }
+        );
+        let order = Order::limit(2, "ES", Side::Bid, px(102), 1);
+        assert_eq!(
+            risk.check_with_book(&order, &book, BookState::Live, 200)
+                .unwrap_err()
+                .reason,
+            RiskReason::NoReferencePrice
+        );
+        risk.set_reference_price("ES", px(100));
+        assert_eq!(
+            risk.check_with_book(&order, &book, BookState::Live, 200),
+            Ok(())
+        );
+        risk.record_trade("ES", px(90));
+        assert_eq!(
+            risk.check_with_book(&order, &book, BookState::Live, 200)
+                .unwrap_err()
+                .reason,
+            RiskReason::OutsideBand {
+                anchor: px(90),
+                price: px(102),
+                band_ticks: 10
+            }
+        );
+
+        book.apply_quote("ES", Side::Bid, px(101), 1).unwrap();
+        assert_eq!(
+            risk.check_with_book(&order, &book, BookState::Live, 200)
+                .unwrap_err()
+                .reason,
+            RiskReason::CrossedBook {
+                bid: px(101),
+                ask: px(101)
+            }
+        );
+    }
+
+    #[test]
+    fn test_stale_builder_book_fails() {
+        let mut risk = RiskChecker::new();
+        risk.set_instrument_limits(
+            "ES",
+            RiskLimits {
+                max_order_quantity: Some(10),
+                ..RiskLimits::default()
+            },
+        );
+        let order = Order::limit(1, "ES", Side::Bid, px(100), 1);
+        let builder = BookBuilder::new(OrderBook::new());
+        assert_eq!(builder.state("ES"), BookState::Stale);
+        assert_eq!(
+            risk.check_with_builder(&order, &builder, 0),
+            Err(RiskViolation {
+                scope: RiskScope::Instrument,
+                reason: RiskReason::BookNotLive {
+                    state: BookState::Stale
+                }
+            })
+        );
+        let book = OrderBook::new();
+        assert_eq!(
+            risk.check_with_book(&order, &book, BookState::Recovering, 0),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_kill_switch_blocks_orders() {
+        let mut risk = RiskChecker::new();
+        let kill = risk.kill_switch_mut();
+        kill.set_conditions(
+            KillScope::Account(7),
+            KillConditions {
+                max_loss: Some(5),
+                ..KillConditions::default()
+            },
+        );
+        kill.command(KillCommand::Arm(KillScope::Account(7)), 0)
+            .unwrap();
+        risk.record_execution(&Execution {
+            account: Some(7),
+            symbol: "ES".to_string(),
+            side: Side::Ask,
+            price: px(100),
+            quantity: 2,
+        });
+        risk.positions_mut().mark("ES", 102.0);
+        risk.positions_mut().set_tick_value("ES", 1.0);
+        risk.update_kill_switch(1).unwrap();
+        let order = Order::limit(1, "ES", Side::Bid, px(100), 1).with_account(7);
+        assert_eq!(risk.check(&order), Ok(()));
+
+        risk.positions_mut().mark("ES", 103.0);
+        risk.update_kill_switch(2).unwrap();
+        assert_eq!(
+            risk.check(&order),
+            Err(RiskViolation {
+                scope: RiskScope::Account,
+                reason: RiskReason::KillSwitch
+            })
+        );
+        let other = Order::limit(2, "ES", Side::Bid, px(100), 1).with_account(8);
+        assert_eq!(risk.check(&other), Ok(()));
+        risk.kill_switch_mut()
+            .command(KillCommand::Trip(KillScope::Global), 3)
+            .unwrap();
+        assert_eq!(risk.check(&other).unwrap_err().scope, RiskScope::Global);
+    }
+
+    #[test]
+    fn test_rejects_trip_reject_storm() {
+        let mut risk = RiskChecker::new();
+        risk.set_account_limits(
+            7,
+            RiskLimits {
+                max_order_quantity: Some(10),
+                ..RiskLimits::default()
+            },
+        );
+        let kill = risk.kill_switch_mut();
+        kill.set_conditions(
+            KillScope::Account(7),
+            KillConditions {
+                reject_storm: Some(RejectStorm {
+                    rejects: 2,
+                    window_ns: 1_000,
+                }),
+                ..KillConditions::default()
+            },
+        );
+        kill.command(KillCommand::Arm(KillScope::Account(7)), 0)
+            .unwrap();
+
+        let order = Order::limit(1, "ES", Side::Bid, px(100), 20).with_account(7);
+        for now_ns in [10, 20, 30] {
+            if risk.check(&order).is_err() {
+                risk.record_reject(&order, now_ns);
+            }
+        }
+        assert_eq!(
+            risk.kill_switch().state(KillScope::Account(7)),
+            KillState::Tripped(TripReason::RejectStorm { rejects: 3 })
+        );
+        let small = Order::limit(2, "ES", Side::Bid, px(100), 1).with_account(7);
+        assert_eq!(
+            risk.check(&small).unwrap_err().reason,
+            RiskReason::KillSwitch
+        );
     }
 }
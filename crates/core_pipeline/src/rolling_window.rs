@@ -0,0 +1,310 @@
+//! rolling_window.rs
+//! Fixed-capacity ring buffer and O(1) rolling statistics over the last N values.
+//!
+//! # Key Concepts
+//! - `RingBuffer` keeps the last `capacity` values in a preallocated slice; a push overwrites
+//!   the oldest value and returns it, so nothing is shifted or reallocated.
+//! - `RollingStats` updates sum, mean and variance from the value entering and the value
+//!   leaving the window. Variance uses a sliding Welford update rather than a sum of squares,
+//!   which loses precision when the values are large relative to their spread.
+//! - The OLS regression of the values on their position in the window (0 for the oldest) keeps
+//!   `sum(i * value)` and updates it in O(1) when the window slides: every remaining value
+//!   moves one position down, which subtracts their sum. The sums over positions have closed
+//!   forms.
+//! - Min and max use monotonic deques of (sequence, value): each value is pushed and popped at
+//!   most once, so both are amortised O(1).
+
+use std::collections::VecDeque;
+
+/// Fixed-capacity FIFO that overwrites its oldest value when full.
+#[derive(Debug, Clone)]
+pub struct RingBuffer<T> {
+    slots: Box<[T]>,
+    /// Index of the oldest value.
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy + Default> RingBuffer<T> {
+    /// A buffer holding up to `capacity` values (at least one).
+    pub fn new(capacity: usize) -> Self {
+        RingBuffer {
+            slots: vec![T::default(); capacity.max(1)].into_boxed_slice(),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Appends `value`, returning the value it evicted if the buffer was full.
+    pub fn push(&mut self, value: T) -> Option<T> {
+        let capacity = self.slots.len();
+        if self.len < capacity {
+            self.slots[(self.head + self.len) % capacity] = value;
+            self.len += 1;
+            return None;
+        }
+        let evicted = std::mem::replace(&mut self.slots[self.head], value);
+        self.head = (self.head + 1) % capacity;
+        Some(evicted)
+    }
+
+    /// Removes and returns the oldest value.
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let value = self.slots[self.head];
+        self.head = (self.head + 1) % self.slots.len();
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// The `index`-th value, oldest first.
+    pub fn get(&self, index: usize) -> Option<T> {
+        (index < self.len).then(|| self.slots[(self.head + index) % self.slots.len()])
+    }
+
+    pub fn front(&self) -> Option<T> {
+        self.get(0)
+    }
+
+    pub fn back(&self) -> Option<T> {
+        self.len.checked_sub(1).and_then(|last| self.get(last))
+    }
+
+    /// Values from oldest to newest.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len).map(move |i| self.slots[(self.head + i) % self.slots.len()])
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.slots.len()
+    }
+}
+
+/// Least-squares line through the window, with the oldest value at x = 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Regression {
+    pub slope: f64,
+    pub intercept: f64,
+    /// Fraction of the variance explained by the line; 1 when the values are all equal.
+    pub r_squared: f64,
+}
+
+/// Rolling sum, mean, variance, min, max and linear regression over the last `window` values.
+#[derive(Debug, Clone)]
+pub struct RollingStats {
+    values: RingBuffer<f64>,
+    /// Number of values pushed so far; the sequence of the next value.
+    seq: u64,
+    sum: f64,
+    mean: f64,
+    /// Sum of squared deviations from the mean.
+    m2: f64,
+    /// Sum of position times value, positions counted from the oldest value.
+    weighted_sum: f64,
+    /// Increasing values, front is the minimum.
+    mins: VecDeque<(u64, f64)>,
+    /// Decreasing values, front is the maximum.
+    maxs: VecDeque<(u64, f64)>,
+}
+
+impl RollingStats {
+    pub fn new(window: usize) -> Self {
+        let values = RingBuffer::new(window);
+        let capacity = values.capacity();
+        RollingStats {
+            values,
+            seq: 0,
+            sum: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+            weighted_sum: 0.0,
+            mins: VecDeque::with_capacity(capacity),
+            maxs: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        let evicted = self.values.push(value);
+        let n = self.values.len() as f64;
+        match evicted {
+            None => {
+                self.weighted_sum += (n - 1.0) * value;
+                self.sum += value;
+                let delta = value - self.mean;
+                self.mean += delta / n;
+                self.m2 += delta * (value - self.mean);
+            }
+            Some(old) => {
+                self.weighted_sum += (n - 1.0) * value - (self.sum - old);
+                self.sum += value - old;
+                let previous_mean = self.mean;
+                self.mean += (value - old) / n;
+                self.m2 += (value - old) * (value - self.mean + old - previous_mean);
+            }
+        }
+        // Rounding can push a zero spread slightly negative.
+        self.m2 = self.m2.max(0.0);
+
+        let seq = self.seq;
+        self.seq += 1;
+        while self.mins.back().is_some_and(|&(_, v)| v >= value) {
+            self.mins.pop_back();
+        }
+        self.mins.push_back((seq, value));
+        while self.maxs.back().is_some_and(|&(_, v)| v <= value) {
+            self.maxs.pop_back();
+        }
+        self.maxs.push_back((seq, value));
+
+        let oldest = self.seq - self.values.len() as u64;
+        for extremes in [&mut self.mins, &mut self.maxs] {
+            while extremes.front().is_some_and(|&(s, _)| s < oldest) {
+                extremes.pop_front();
+            }
+        }
+    }
+
+    /// Values in the window, oldest first.
+    pub fn values(&self) -> &RingBuffer<f64> {
+        &self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Mean of the window; 0 when empty.
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Sample variance of the window; 0 with fewer than two values.
+    pub fn variance(&self) -> f64 {
+        match self.values.len() {
+            0 | 1 => 0.0,
+            n => self.m2 / (n - 1) as f64,
+        }
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// Rolling OLS fit; `None` with fewer than two values.
+    pub fn regression(&self) -> Option<Regression> {
+        let n = self.values.len() as f64;
+        if n < 2.0 {
+            return None;
+        }
+        let sum_x = n * (n - 1.0) / 2.0;
+        // Sum of squared deviations of the positions from their mean.
+        let sxx = n * (n * n - 1.0) / 12.0;
+        let sxy = self.weighted_sum - sum_x * self.sum / n;
+        let slope = sxy / sxx;
+        let intercept = self.mean - slope * sum_x / n;
+        let r_squared = if self.m2 > 0.0 {
+            (slope * sxy / self.m2).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        Some(Regression {
+            slope,
+            intercept,
+            r_squared,
+        })
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        self.mins.front().map(|&(_, v)| v)
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.maxs.front().map(|&(_, v)| v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer_wraps() {
+        let mut ring = RingBuffer::new(3);
+        assert_eq!(ring.push(1), None);
+        assert_eq!(ring.push(2), None);
+        assert_eq!(ring.push(3), None);
+        assert_eq!(ring.push(4), Some(1));
+        assert_eq!(ring.iter().collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_eq!((ring.front(), ring.back()), (Some(2), Some(4)));
+        assert_eq!(ring.pop_front(), Some(2));
+        assert_eq!(ring.push(5), None);
+        assert_eq!(ring.iter().collect::<Vec<_>>(), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn test_rolling_stats_match_recomputation() {
+        let mut stats = RollingStats::new(4);
+        let prices = [
+            100.0, 101.5, 99.0, 103.0, 102.0, 98.5, 98.5, 104.0, 100.0, 97.0,
+        ];
+        for (i, &price) in prices.iter().enumerate() {
+            stats.push(price);
+            let window = &prices[(i + 1).saturating_sub(4)..=i];
+            let n = window.len() as f64;
+            let mean = window.iter().sum::<f64>() / n;
+            let variance = if window.len() < 2 {
+                0.0
+            } else {
+                window.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / (n - 1.0)
+            };
+            assert!((stats.sum() - window.iter().sum::<f64>()).abs() < 1e-9);
+            assert!((stats.mean() - mean).abs() < 1e-9);
+            assert!((stats.variance() - variance).abs() < 1e-9);
+            assert_eq!(stats.min(), window.iter().copied().reduce(f64::min));
+            assert_eq!(stats.max(), window.iter().copied().reduce(f64::max));
+        }
+    }
+
+    #[test]
+    fn test_rolling_regression() {
+        let mut stats = RollingStats::new(3);
+        assert_eq!(stats.regression(), None);
+        for price in [50.0, 10.0, 12.0, 14.0] {
+            stats.push(price);
+        }
+        let fit = stats.regression().unwrap();
+        assert!((fit.slope - 2.0).abs() < 1e-9);
+        assert!((fit.intercept - 10.0).abs() < 1e-9);
+        assert!((fit.r_squared - 1.0).abs() < 1e-9);
+
+        // Window [14, 11, 14]: slope 0, nothing explained.
+        stats.push(11.0);
+        stats.push(14.0);
+        let fit = stats.regression().unwrap();
+        assert!(fit.slope.abs() < 1e-9);
+        assert!((fit.intercept - 13.0).abs() < 1e-9);
+        assert!(fit.r_squared.abs() < 1e-9);
+    }
+}
@@ -3,6 +3,9 @@
 //!
 //! # Idea
 //! If EMA surpasses a certain threshold, generate a "BUY" signal, etc.
+//! No signal is produced while the underlying book is not `Live`.
+
+use super::book_builder::BookState;
 
 pub struct SignalGenerator {
     buy_threshold: f64,
@@ -26,4 +29,12 @@ impl SignalGenerator {
             None
         }
     }
+
+    /// Like `generate_signal`, but refuses to signal unless the book is `Live`.
+    pub fn generate_signal_for_book(&self, metric: f64, state: BookState) -> Option<&'static str> {
+        if state != BookState::Live {
+            return None;
+        }
+        self.generate_signal(metric)
+    }
 }
@@ -0,0 +1,140 @@
+//! stop_book.rs
+//! Off-book store for stop and stop-limit orders waiting for their trigger price.
+//!
+//! # Key Concepts
+//! - A buy stop triggers when the last trade is at or above its stop price, a sell stop when
+//!   the last trade is at or below it.
+//! - Triggered stops are released one at a time: buy stops in ascending stop price, then sell
+//!   stops in descending stop price, with ties going to the earlier arrival. The caller
+//!   re-reads the last trade after each release, so a cascade is deterministic.
+//!
+//! # Performance
+//! - Each side is a `BTreeMap` keyed by (stop price, arrival), so the next trigger is the first
+//!   entry and insert/remove are logarithmic.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+use common::price::Price;
+
+use super::order_book::Side;
+use super::order_types::Order;
+
+/// Pending stop orders for one symbol.
+#[derive(Debug, Default)]
+pub struct StopBook {
+    buys: BTreeMap<(Price, u64), Order>,
+    sells: BTreeMap<(Reverse<Price>, u64), Order>,
+    /// Order id to (side, stop price, arrival sequence).
+    index: HashMap<u64, (Side, Price, u64)>,
+}
+
+impl StopBook {
+    pub fn new() -> Self {
+        StopBook::default()
+    }
+
+    /// Holds `order` until triggered. `seq` is its arrival sequence and must be unique.
+    /// Orders without a stop price are ignored.
+    pub fn insert(&mut self, seq: u64, order: Order) {
+        let Some(stop) = order.stop else {
+            return;
+        };
+        self.index.insert(order.order_id, (order.side, stop, seq));
+        match order.side {
+            Side::Bid => self.buys.insert((stop, seq), order),
+            Side::Ask => self.sells.insert((Reverse(stop), seq), order),
+        };
+    }
+
+    /// Removes a pending stop order.
+    pub fn remove(&mut self, order_id: u64) -> Option<Order> {
+        let (side, stop, seq) = self.index.remove(&order_id)?;
+        match side {
+            Side::Bid => self.buys.remove(&(stop, seq)),
+            Side::Ask => self.sells.remove(&(Reverse(stop), seq)),
+        }
+    }
+
+    pub fn contains(&self, order_id: u64) -> bool {
+        self.index.contains_key(&order_id)
+    }
+
+    /// Removes and returns the next stop triggered by a last trade at `last_trade`.
+    pub fn pop_triggered(&mut self, last_trade: Price) -> Option<Order> {
+        let order_id = match (self.buys.first_key_value(), self.sells.first_key_value()) {
+            (Some((&(stop, _), order)), _) if stop <= last_trade => order.order_id,
+            (_, Some((&(Reverse(stop), _), order))) if stop >= last_trade => order.order_id,
+            _ => return None,
+        };
+        self.remove(order_id)
+    }
+
+    /// Removes every pending stop matching `expired`, returned in arrival order.
+    pub fn remove_where(&mut self, mut expired: impl FnMut(&Order) -> bool) -> Vec<Order> {
+        let mut removed: Vec<(u64, u64)> = self
+            .buys
+            .iter()
+            .map(|(&(_, seq), order)| (seq, order))
+            .chain(self.sells.iter().map(|(&(_, seq), order)| (seq, order)))
+            .filter(|(_, order)| expired(order))
+            .map(|(seq, order)| (seq, order.order_id))
+            .collect();
+        removed.sort_unstable();
+        removed
+            .into_iter()
+            .filter_map(|(_, order_id)| self.remove(order_id))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(ticks: i64) -> Price {
+        Price::from_ticks(ticks)
+    }
+
+    fn stop(order_id: u64, side: Side, stop: i64) -> Order {
+        Order::market(order_id, "ES", side, 1).with_stop(px(stop))
+    }
+
+    #[test]
+    fn test_trigger_order() {
+        let mut stops = StopBook::new();
+        stops.insert(0, stop(1, Side::Bid, 105));
+        stops.insert(1, stop(2, Side::Bid, 103));
+        stops.insert(2, stop(3, Side::Bid, 103));
+        stops.insert(3, stop(4, Side::Ask, 95));
+        stops.insert(4, stop(5, Side::Ask, 97));
+
+        assert!(stops.pop_triggered(px(100)).is_none());
+        let ids: Vec<u64> = std::iter::from_fn(|| stops.pop_triggered(px(104)))
+            .map(|o| o.order_id)
+            .collect();
+        assert_eq!(ids, vec![2, 3]);
+        assert_eq!(stops.pop_triggered(px(96)).map(|o| o.order_id), Some(5));
+        assert_eq!(stops.remove(1).map(|o| o.order_id), Some(1));
+        assert_eq!(stops.len(), 1);
+        assert!(stops.contains(4));
+
+        stops.insert(5, stop(6, Side::Bid, 110));
+        stops.insert(6, stop(7, Side::Ask, 90));
+        let expired: Vec<u64> = stops
+            .remove_where(|o| o.order_id != 7)
+            .iter()
+            .map(|o| o.order_id)
+            .collect();
+        assert_eq!(expired, vec![4, 6]);
+        assert_eq!(stops.len(), 1);
+    }
+}
@@ -0,0 +1,608 @@
+//! throttle.rs
+//! Message rate limits applied before orders and cancels are sent to the exchange.
+//!
+//! # Key Concepts
+//! - Every limit is a token bucket: new orders and cancels each have their own bucket that
+//!   refills at a fixed rate per second up to a burst size.
+//! - The order-to-trade ratio is also a bucket. It starts with an allowance of orders, each new
+//!   order takes a token and each trade adds `ratio` tokens back.
+//! - Limits are configured per instrument and per session. A message must pass both scopes, and
+//!   tokens are only taken when it does.
+//! - In `ThrottleMode::Queue` a throttled message is held in a FIFO queue per session and
+//!   instrument and handed back by `pop_ready` once tokens are available. New orders arriving
+//!   while their queue is non-empty queue behind it so their send order never changes, but a
+//!   throttled instrument does not hold up the others. Cancels skip queued new orders (only
+//!   waiting behind earlier cancels), and a cancel of an order still in the queue withdraws
+//!   it. Each queue holds at most `max_queued` messages. In `ThrottleMode::Reject` the
+//!   message is refused with a typed `ThrottleViolation`.
+//! - Time is passed in by the caller as nanoseconds, so throttling is deterministic in replay.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::order_types::Order;
+
+pub type SessionId = u32;
+
+const NANOS_PER_SECOND: f64 = 1_000_000_000.0;
+
+/// Default bound of each queue in `ThrottleMode::Queue`.
+pub const DEFAULT_MAX_QUEUED: usize = 1_024;
+
+/// A refill rate with a burst size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+/// Orders allowed per trade, with an allowance of orders before the first trade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderToTrade {
+    pub ratio: u32,
+    pub allowance: u32,
+}
+
+/// Optional throttles. `None` disables a limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ThrottleLimits {
+    pub orders: Option<Rate>,
+    pub cancels: Option<Rate>,
+    pub order_to_trade: Option<OrderToTrade>,
+}
+
+/// What happens to a message that exceeds a limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThrottleMode {
+    #[default]
+    Reject,
+    Queue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleScope {
+    Instrument,
+    Session,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleKind {
+    Orders,
+    Cancels,
+    OrderToTrade,
+}
+
+/// First exhausted bucket, returned for rejected messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThrottleViolation {
+    pub scope: ThrottleScope,
+    pub kind: ThrottleKind,
+}
+
+/// A message subject to throttling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    New(Order),
+    Cancel { symbol: String, order_id: u64 },
+}
+
+impl Message {
+    fn symbol(&self) -> &str {
+        match self {
+            Message::New(order) => &order.symbol,
+            Message::Cancel { symbol, .. } => symbol,
+        }
+    }
+
+    fn is_cancel(&self) -> bool {
+        matches!(self, Message::Cancel { .. })
+    }
+}
+
+/// Outcome of `Throttle::admit`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Admission {
+    /// Within limits; send it now.
+    Send(Message),
+    Queued,
+    /// A cancel of an order still in the queue: the order was removed and neither is sent.
+    Withdrawn,
+    /// The message's queue already holds `max_queued` messages.
+    QueueFull,
+    Rejected(ThrottleViolation),
+}
+
+/// Fill level of one bucket, for monitoring.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketUsage {
+    pub available: f64,
+    pub capacity: f64,
+}
+
+/// Current usage of one scope. `None` where the limit is disabled.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ThrottleUsage {
+    pub orders: Option<BucketUsage>,
+    pub cancels: Option<BucketUsage>,
+    pub order_to_trade: Option<BucketUsage>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    capacity: f64,
+    /// Tokens added per nanosecond.
+    refill: f64,
+    tokens: f64,
+    last_ns: u64,
+}
+
+impl TokenBucket {
+    fn rate(rate: Rate, now_ns: u64) -> Self {
+        TokenBucket {
+            capacity: rate.burst as f64,
+            refill: rate.per_second as f64 / NANOS_PER_SECOND,
+            tokens: rate.burst as f64,
+            last_ns: now_ns,
+        }
+    }
+
+    /// A bucket that only refills through `credit`.
+    fn order_to_trade(limit: OrderToTrade, now_ns: u64) -> Self {
+        TokenBucket {
+            capacity: limit.allowance as f64,
+            refill: 0.0,
+            tokens: limit.allowance as f64,
+            last_ns: now_ns,
+        }
+    }
+
+    fn refill(&mut self, now_ns: u64) {
+        let elapsed = now_ns.saturating_sub(self.last_ns);
+        self.tokens = (self.tokens + elapsed as f64 * self.refill).min(self.capacity);
+        self.last_ns = self.last_ns.max(now_ns);
+    }
+
+    fn credit(&mut self, tokens: f64) {
+        self.tokens = (self.tokens + tokens).min(self.capacity);
+    }
+
+    fn usage(&self) -> BucketUsage {
+        BucketUsage {
+            available: self.tokens,
+            capacity: self.capacity,
+        }
+    }
+}
+
+/// Buckets of one instrument or session, created from its limits on first use.
+#[derive(Debug, Clone, Default)]
+struct Buckets {
+    orders: Option<TokenBucket>,
+    cancels: Option<TokenBucket>,
+    order_to_trade: Option<TokenBucket>,
+    /// Orders per trade credited by `record_trade`.
+    ratio: u32,
+}
+
+impl Buckets {
+    fn new(limits: &ThrottleLimits, now_ns: u64) -> Self {
+        Buckets {
+            orders: limits.orders.map(|rate| TokenBucket::rate(rate, now_ns)),
+            cancels: limits.cancels.map(|rate| TokenBucket::rate(rate, now_ns)),
+            order_to_trade: limits
+                .order_to_trade
+                .map(|limit| TokenBucket::order_to_trade(limit, now_ns)),
+            ratio: limits.order_to_trade.map_or(0, |limit| limit.ratio),
+        }
+    }
+
+    /// The buckets `message` takes a token from.
+    fn for_message(&mut self, message: &Message) -> [Option<(ThrottleKind, &mut TokenBucket)>; 2] {
+        match message {
+            Message::New(_) => [
+                self.orders.as_mut().map(|b| (ThrottleKind::Orders, b)),
+                self.order_to_trade
+                    .as_mut()
+                    .map(|b| (ThrottleKind::OrderToTrade, b)),
+            ],
+            Message::Cancel { .. } => [
+                self.cancels.as_mut().map(|b| (ThrottleKind::Cancels, b)),
+                None,
+            ],
+        }
+    }
+
+    /// First bucket without a token for `message`, after refilling to `now_ns`.
+    fn exhausted(&mut self, message: &Message, now_ns: u64) -> Option<ThrottleKind> {
+        self.for_message(message)
+            .into_iter()
+            .flatten()
+            .find_map(|(kind, bucket)| {
+                bucket.refill(now_ns);
+                (bucket.tokens < 1.0).then_some(kind)
+            })
+    }
+
+    fn take(&mut self, message: &Message) {
+        for (_, bucket) in self.for_message(message).into_iter().flatten() {
+            bucket.tokens -= 1.0;
+        }
+    }
+
+    fn usage(&mut self, now_ns: u64) -> ThrottleUsage {
+        let usage = |bucket: &mut Option<TokenBucket>| {
+            bucket.as_mut().map(|b| {
+                b.refill(now_ns);
+                b.usage()
+            })
+        };
+        ThrottleUsage {
+            orders: usage(&mut self.orders),
+            cancels: usage(&mut self.cancels),
+            order_to_trade: usage(&mut self.order_to_trade),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Throttle {
+    mode: ThrottleMode,
+    instrument_limits: HashMap<String, ThrottleLimits>,
+    session_limits: HashMap<SessionId, ThrottleLimits>,
+    instruments: HashMap<String, Buckets>,
+    sessions: HashMap<SessionId, Buckets>,
+    /// Held messages per session and instrument, each with its arrival sequence.
+    queues: HashMap<(SessionId, String), VecDeque<(u64, Message)>>,
+    queued: usize,
+    max_queued: usize,
+    arrivals: u64,
+}
+
+impl Throttle {
+    pub fn new(mode: ThrottleMode) -> Self {
+        Throttle {
+            mode,
+            max_queued: DEFAULT_MAX_QUEUED,
+            ..Throttle::default()
+        }
+    }
+
+    /// Bounds each queue to `max` messages (default `DEFAULT_MAX_QUEUED`).
+    pub fn with_max_queued(mut self, max: usize) -> Self {
+        self.max_queued = max;
+        self
+    }
+
+    pub fn mode(&self) -> ThrottleMode {
+        self.mode
+    }
+
+    /// Sets the limits of `symbol`, starting with full buckets.
+    pub fn set_instrument_limits(&mut self, symbol: &str, limits: ThrottleLimits) {
+        self.instrument_limits.insert(symbol.to_string(), limits);
+        self.instruments.remove(symbol);
+    }
+
+    /// Sets the limits of `session`, starting with full buckets.
+    pub fn set_session_limits(&mut self, session: SessionId, limits: ThrottleLimits) {
+        self.session_limits.insert(session, limits);
+        self.sessions.remove(&session);
+    }
+
+    /// Admits `message` from `session` at `now_ns`, taking its tokens if it can be sent.
+    pub fn admit(&mut self, session: SessionId, message: Message, now_ns: u64) -> Admission {
+        if self.mode == ThrottleMode::Queue {
+            return self.admit_queued(session, message, now_ns);
+        }
+        match self.try_take(session, &message, now_ns) {
+            Ok(()) => Admission::Send(message),
+            Err(violation) => Admission::Rejected(violation),
+        }
+    }
+
+    fn admit_queued(&mut self, session: SessionId, message: Message, now_ns: u64) -> Admission {
+        let key = (session, message.symbol().to_string());
+        let queue = self.queues.get_mut(&key);
+        if let (Message::Cancel { order_id, .. }, Some(queue)) = (&message, queue) {
+            let queued_order = queue
+                .iter()
+                .position(|(_, m)| matches!(m, Message::New(o) if o.order_id == *order_id));
+            if let Some(index) = queued_order {
+                queue.remove(index);
+                if queue.is_empty() {
+                    self.queues.remove(&key);
+                }
+                self.queued -= 1;
+                return Admission::Withdrawn;
+            }
+        }
+
+        // A new order waits behind anything queued for its key, a cancel only behind cancels.
+        let blocked = self.queues.get(&key).is_some_and(|queue| {
+            queue
+                .iter()
+                .any(|(_, m)| !message.is_cancel() || m.is_cancel())
+        });
+        if !blocked && self.try_take(session, &message, now_ns).is_ok() {
+            return Admission::Send(message);
+        }
+
+        let queue = self.queues.entry(key).or_default();
+        if queue.len() >= self.max_queued {
+            return Admission::QueueFull;
+        }
+        let index = if message.is_cancel() {
+            queue.iter().take_while(|(_, m)| m.is_cancel()).count()
+        } else {
+            queue.len()
+        };
+        queue.insert(index, (self.arrivals, message));
+        self.arrivals += 1;
+        self.queued += 1;
+        Admission::Queued
+    }
+
+    /// Releases the queued message whose tokens are available at `now_ns`, taking the head of
+    /// each queue in arrival order.
+    pub fn pop_ready(&mut self, now_ns: u64) -> Option<(SessionId, Message)> {
+        let mut heads: Vec<(u64, (SessionId, String))> = self
+            .queues
+            .iter()
+            .filter_map(|(key, queue)| queue.front().map(|(seq, _)| (*seq, key.clone())))
+            .collect();
+        heads.sort_unstable_by_key(|(seq, _)| *seq);
+        for (_, key) in heads {
+            let message = self.queues[&key].front()?.1.clone();
+            if self.try_take(key.0, &message, now_ns).is_err() {
+                continue;
+            }
+            let queue = self.queues.get_mut(&key)?;
+            queue.pop_front();
+            if queue.is_empty() {
+                self.queues.remove(&key);
+            }
+            self.queued -= 1;
+            return Some((key.0, message));
+        }
+        None
+    }
+
+    /// Number of messages waiting in the queues.
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+
+    /// Credits the order-to-trade buckets of `session` and `symbol` with a trade.
+    pub fn record_trade(&mut self, session: SessionId, symbol: &str) {
+        let buckets = [
+            self.sessions.get_mut(&session),
+            self.instruments.get_mut(symbol),
+        ];
+        for buckets in buckets.into_iter().flatten() {
+            let ratio = buckets.ratio as f64;
+            if let Some(bucket) = buckets.order_to_trade.as_mut() {
+                bucket.credit(ratio);
+            }
+        }
+    }
+
+    pub fn instrument_usage(&mut self, symbol: &str, now_ns: u64) -> ThrottleUsage {
+        let Some(limits) = self.instrument_limits.get(symbol) else {
+            return ThrottleUsage::default();
+        };
+        self.instruments
+            .entry(symbol.to_string())
+            .or_insert_with(|| Buckets::new(limits, now_ns))
+            .usage(now_ns)
+    }
+
+    pub fn session_usage(&mut self, session: SessionId, now_ns: u64) -> ThrottleUsage {
+        let Some(limits) = self.session_limits.get(&session) else {
+            return ThrottleUsage::default();
+        };
+        self.sessions
+            .entry(session)
+            .or_insert_with(|| Buckets::new(limits, now_ns))
+            .usage(now_ns)
+    }
+
+    fn try_take(
+        &mut self,
+        session: SessionId,
+        message: &Message,
+        now_ns: u64,
+    ) -> Result<(), ThrottleViolation> {
+        let symbol = message.symbol();
+        let mut instrument = self.instrument_limits.get(symbol).map(|limits| {
+            self.instruments
+                .entry(symbol.to_string())
+                .or_insert_with(|| Buckets::new(limits, now_ns))
+        });
+        if let Some(kind) = instrument
+            .as_mut()
+            .and_then(|b| b.exhausted(message, now_ns))
+        {
+            return Err(ThrottleViolation {
+                scope: ThrottleScope::Instrument,
+                kind,
+            });
+        }
+
+        let mut session_buckets = self.session_limits.get(&session).map(|limits| {
+            self.sessions
+                .entry(session)
+                .or_insert_with(|| Buckets::new(limits, now_ns))
+        });
+        if let Some(kind) = session_buckets
+            .as_mut()
+            .and_then(|b| b.exhausted(message, now_ns))
+        {
+            return Err(ThrottleViolation {
+                scope: ThrottleScope::Session,
+                kind,
+            });
+        }
+
+        for buckets in [instrument, session_buckets].into_iter().flatten() {
+            buckets.take(message);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_book::Side;
+    use common::price::Price;
+
+    const MS: u64 = 1_000_000;
+
+    fn order(order_id: u64) -> Message {
+        Message::New(Order::limit(
+            order_id,
+            "ES",
+            Side::Bid,
+            Price::from_ticks(100),
+            1,
+        ))
+    }
+
+    #[test]
+    fn test_reject_order_rate_and_order_to_trade() {
+        let mut throttle = Throttle::new(ThrottleMode::Reject);
+        throttle.set_instrument_limits(
+            "ES",
+            ThrottleLimits {
+                orders: Some(Rate {
+                    per_second: 1_000,
+                    burst: 2,
+                }),
+                ..ThrottleLimits::default()
+            },
+        );
+        throttle.set_session_limits(
+            1,
+            ThrottleLimits {
+                order_to_trade: Some(OrderToTrade {
+                    ratio: 1,
+                    allowance: 3,
+                }),
+                ..ThrottleLimits::default()
+            },
+        );
+        assert!(matches!(throttle.admit(1, order(1), 0), Admission::Send(_)));
+        assert!(matches!(throttle.admit(1, order(2), 0), Admission::Send(_)));
+        assert_eq!(
+            throttle.admit(1, order(3), 0),
+            Admission::Rejected(ThrottleViolation {
+                scope: ThrottleScope::Instrument,
+                kind: ThrottleKind::Orders
+            })
+        );
+        let cancel = Message::Cancel {
+            symbol: "ES".to_string(),
+            order_id: 1,
+        };
+        assert_eq!(
+            throttle.admit(1, cancel.clone(), 0),
+            Admission::Send(cancel)
+        );
+
+        assert!(matches!(
+            throttle.admit(1, order(4), MS),
+            Admission::Send(_)
+        ));
+        assert_eq!(
+            throttle.admit(1, order(5), 3 * MS),
+            Admission::Rejected(ThrottleViolation {
+                scope: ThrottleScope::Session,
+                kind: ThrottleKind::OrderToTrade
+            })
+        );
+        throttle.record_trade(1, "ES");
+        assert!(matches!(
+            throttle.admit(1, order(5), 3 * MS),
+            Admission::Send(_)
+        ));
+        let usage = throttle.instrument_usage("ES", 3 * MS);
+        assert_eq!(
+            usage.orders,
+            Some(BucketUsage {
+                available: 1.0,
+                capacity: 2.0
+            })
+        );
+        assert_eq!(usage.cancels, None);
+    }
+
+    #[test]
+    fn test_queue_releases_in_order() {
+        let mut throttle = Throttle::new(ThrottleMode::Queue);
+        throttle.set_session_limits(
+            1,
+            ThrottleLimits {
+                orders: Some(Rate {
+                    per_second: 1_000,
+                    burst: 1,
+                }),
+                ..ThrottleLimits::default()
+            },
+        );
+        assert!(matches!(throttle.admit(1, order(1), 0), Admission::Send(_)));
+        assert_eq!(throttle.admit(1, order(2), 0), Admission::Queued);
+        assert_eq!(throttle.admit(1, order(3), 5 * MS), Admission::Queued);
+        assert_eq!(throttle.queued(), 2);
+
+        assert_eq!(throttle.pop_ready(5 * MS), Some((1, order(2))));
+        assert_eq!(throttle.pop_ready(5 * MS), None);
+        assert_eq!(throttle.pop_ready(6 * MS), Some((1, order(3))));
+        assert_eq!(throttle.queued(), 0);
+    }
+
+    #[test]
+    fn test_queue_per_key_cancels_and_bound() {
+        let mut throttle = Throttle::new(ThrottleMode::Queue).with_max_queued(2);
+        let rate = Some(Rate {
+            per_second: 1_000,
+            burst: 1,
+        });
+        throttle.set_instrument_limits(
+            "ES",
+            ThrottleLimits {
+                orders: rate,
+                cancels: rate,
+                ..ThrottleLimits::default()
+            },
+        );
+        let nq = |order_id| {
+            Message::New(Order::limit(
+                order_id,
+                "NQ",
+                Side::Bid,
+                Price::from_ticks(100),
+                1,
+            ))
+        };
+        let cancel = |order_id| Message::Cancel {
+            symbol: "ES".to_string(),
+            order_id,
+        };
+        assert!(matches!(throttle.admit(1, order(1), 0), Admission::Send(_)));
+        assert_eq!(throttle.admit(1, order(2), 0), Admission::Queued);
+        assert_eq!(throttle.admit(1, order(3), 0), Admission::Queued);
+        assert_eq!(throttle.admit(1, order(4), 0), Admission::QueueFull);
+        // Another instrument is not held up by the ES queue.
+        assert_eq!(throttle.admit(1, nq(5), 0), Admission::Send(nq(5)));
+
+        // Cancels skip queued orders; cancelling a queued order withdraws it.
+        assert_eq!(throttle.admit(1, cancel(1), 0), Admission::Send(cancel(1)));
+        assert_eq!(throttle.admit(1, cancel(3), 0), Admission::Withdrawn);
+        assert_eq!(throttle.queued(), 1);
+
+        // A throttled cancel goes ahead of the queued order.
+        assert_eq!(throttle.admit(1, cancel(9), 0), Admission::Queued);
+        assert_eq!(throttle.pop_ready(MS), Some((1, cancel(9))));
+        assert_eq!(throttle.pop_ready(MS), Some((1, order(2))));
+        assert_eq!(throttle.queued(), 0);
+    }
+}
@@ -0,0 +1,252 @@
+//! time_window.rs
+//! Time-based counterparts of the tick-count statistics in `rolling_window`.
+//!
+//! # Key Concepts
+//! - `TimeDecayEma` treats the series as a step function: each value holds until the next
+//!   update, and on an update the value held over the elapsed `dt` is blended in with
+//!   `alpha = 1 - exp(-dt / tau)` (the previous-point form). A burst of ticks moves the
+//!   average no more than one tick after the same pause would, and a long gap lets the value
+//!   held through it dominate. Ticks at the same instant replace each other, so the last one
+//!   is the value carried forward rather than being dropped.
+//! - `TimeWindowStats` keeps the values whose timestamp is within `window_ns` of the latest
+//!   event and evicts by timestamp, so the window holds however many ticks arrived in it.
+//!   Sum, mean and variance are updated with Welford additions and removals; min and max use
+//!   monotonic deques as in `RollingStats`.
+//! - Timestamps are nanoseconds and expected in non-decreasing order; an earlier timestamp is
+//!   treated as arriving at the latest time seen.
+
+use std::collections::VecDeque;
+
+/// Exponential moving average with a time constant instead of a tick count.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeDecayEma {
+    tau_ns: f64,
+    value: Option<f64>,
+    /// Latest input, held since `last_ns` until the next update.
+    held: f64,
+    last_ns: u64,
+}
+
+impl TimeDecayEma {
+    /// `tau_ns` is the time for the weight of old values to decay by a factor of e.
+    pub fn new(tau_ns: u64) -> Self {
+        TimeDecayEma {
+            tau_ns: tau_ns.max(1) as f64,
+            value: None,
+            held: 0.0,
+            last_ns: 0,
+        }
+    }
+
+    /// Blends in the value held since the previous update, then holds `value`.
+    pub fn update(&mut self, value: f64, timestamp: u64) {
+        let Some(current) = self.value else {
+            self.value = Some(value);
+            self.held = value;
+            self.last_ns = timestamp;
+            return;
+        };
+        let elapsed = timestamp.saturating_sub(self.last_ns) as f64;
+        let alpha = 1.0 - (-elapsed / self.tau_ns).exp();
+        self.value = Some(current + alpha * (self.held - current));
+        self.held = value;
+        self.last_ns = self.last_ns.max(timestamp);
+    }
+
+    /// The average up to the latest update's timestamp; `None` before the first one. The
+    /// latest value is not reflected until the next update, which blends it in for as long
+    /// as it was held (only the first value counts at once).
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+}
+
+/// Rolling sum, mean, variance, min and max over the values of the last `window_ns`.
+#[derive(Debug, Clone)]
+pub struct TimeWindowStats {
+    window_ns: u64,
+    /// (timestamp, sequence, value), oldest first.
+    entries: VecDeque<(u64, u64, f64)>,
+    seq: u64,
+    latest_ns: u64,
+    sum: f64,
+    mean: f64,
+    m2: f64,
+    mins: VecDeque<(u64, f64)>,
+    maxs: VecDeque<(u64, f64)>,
+}
+
+impl TimeWindowStats {
+    pub fn new(window_ns: u64) -> Self {
+        TimeWindowStats {
+            window_ns,
+            entries: VecDeque::new(),
+            seq: 0,
+            latest_ns: 0,
+            sum: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+            mins: VecDeque::new(),
+            maxs: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, value: f64, timestamp: u64) {
+        self.advance(timestamp);
+        let timestamp = self.latest_ns;
+        let seq = self.seq;
+        self.seq += 1;
+        self.entries.push_back((timestamp, seq, value));
+
+        let n = self.entries.len() as f64;
+        self.sum += value;
+        let delta = value - self.mean;
+        self.mean += delta / n;
+        self.m2 += delta * (value - self.mean);
+
+        while self.mins.back().is_some_and(|&(_, v)| v >= value) {
+            self.mins.pop_back();
+        }
+        self.mins.push_back((seq, value));
+        while self.maxs.back().is_some_and(|&(_, v)| v <= value) {
+            self.maxs.pop_back();
+        }
+        self.maxs.push_back((seq, value));
+    }
+
+    /// Evicts the values older than `window_ns` before `now`, e.g. on a timer while no
+    /// events arrive.
+    pub fn advance(&mut self, now: u64) {
+        self.latest_ns = self.latest_ns.max(now);
+        while let Some(&(timestamp, _, value)) = self.entries.front() {
+            if self.latest_ns - timestamp < self.window_ns {
+                break;
+            }
+            self.entries.pop_front();
+            self.remove(value);
+        }
+        let oldest = self.entries.front().map_or(self.seq, |&(_, seq, _)| seq);
+        for extremes in [&mut self.mins, &mut self.maxs] {
+            while extremes.front().is_some_and(|&(s, _)| s < oldest) {
+                extremes.pop_front();
+            }
+        }
+    }
+
+    fn remove(&mut self, value: f64) {
+        let n = self.entries.len() as f64;
+        if n == 0.0 {
+            self.sum = 0.0;
+            self.mean = 0.0;
+            self.m2 = 0.0;
+            return;
+        }
+        self.sum -= value;
+        let previous_mean = self.mean;
+        self.mean = (previous_mean * (n + 1.0) - value) / n;
+        self.m2 = (self.m2 - (value - previous_mean) * (value - self.mean)).max(0.0);
+    }
+
+    pub fn window_ns(&self) -> u64 {
+        self.window_ns
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Mean of the window; 0 when empty.
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Sample variance of the window; 0 with fewer than two values.
+    pub fn variance(&self) -> f64 {
+        match self.entries.len() {
+            0 | 1 => 0.0,
+            n => self.m2 / (n - 1) as f64,
+        }
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        self.mins.front().map(|&(_, v)| v)
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.maxs.front().map(|&(_, v)| v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    #[test]
+    fn test_time_decay_ema_irregular_ticks() {
+        let mut ema = TimeDecayEma::new(100 * MS);
+        ema.update(100.0, 0);
+        // A tick at the same instant replaces the held value; it is blended in once time
+        // passes rather than dropped.
+        ema.update(200.0, 0);
+        assert_eq!(ema.value(), Some(100.0));
+
+        ema.update(300.0, 100 * MS);
+        let expected = 100.0 + (1.0 - (-1.0f64).exp()) * 100.0;
+        assert!((ema.value().unwrap() - expected).abs() < 1e-9);
+
+        // Ten ticks 10 ms apart decay as much as one value held for 100 ms.
+        let mut bursty = TimeDecayEma::new(100 * MS);
+        bursty.update(0.0, 0);
+        bursty.update(1.0, 0);
+        for i in 1..=10 {
+            bursty.update(1.0, i * 10 * MS);
+        }
+        assert!((bursty.value().unwrap() - (1.0 - (-1.0f64).exp())).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_time_window_evicts_by_timestamp() {
+        let mut stats = TimeWindowStats::new(500 * MS);
+        stats.push(10.0, 0);
+        stats.push(14.0, 100 * MS);
+        stats.push(12.0, 450 * MS);
+        assert_eq!(
+            (stats.len(), stats.mean(), stats.max()),
+            (3, 12.0, Some(14.0))
+        );
+
+        stats.push(20.0, 550 * MS);
+        assert_eq!(stats.len(), 3);
+        assert!((stats.mean() - 46.0 / 3.0).abs() < 1e-9);
+        let variance = [14.0f64, 12.0, 20.0]
+            .iter()
+            .map(|v| (v - 46.0 / 3.0).powi(2))
+            .sum::<f64>()
+            / 2.0;
+        assert!((stats.variance() - variance).abs() < 1e-9);
+        assert_eq!(stats.min(), Some(12.0));
+
+        stats.advance(1_000 * MS);
+        assert_eq!(
+            (stats.len(), stats.min(), stats.max()),
+            (1, Some(20.0), Some(20.0))
+        );
+        stats.advance(2_000 * MS);
+        assert!(stats.is_empty());
+        assert_eq!((stats.sum(), stats.min()), (0.0, None));
+    }
+}
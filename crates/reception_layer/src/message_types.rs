@@ -1,24 +1,90 @@
 //! message_types.rs
 //! Defines internal message formats for normalized data (e.g., quotes, trades, order updates).
+//! Prices are fixed-point tick counts; see `common::price`.
+//!
+//! Incremental messages are wrapped in a `SequencedMessage` carrying the exchange channel and
+//! sequence number so the book builder can detect gaps. `Snapshot` messages come from the
+//! recovery feed and carry the last incremental sequence number they include.
 
+use common::price::Price;
+use common::side::Side;
+
+/// What happened to an individual order in an `OrderUpdate` (market-by-order feeds).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderAction {
+    /// New order; `size` is its quantity.
+    Add,
+    /// Price and/or quantity change; `size` is the new quantity.
+    Modify,
+    /// Order removed from the book; `size` is ignored.
+    Delete,
+    /// Order traded; `size` is the executed quantity.
+    Execute,
+}
+
+/// A resting order inside a market-by-order `Snapshot`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotOrder {
+    pub order_id: u64,
+    pub side: Side,
+    pub price: Price,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum MarketMessage {
     Quote {
         symbol: String,
-        price: f64,
+        side: Side,
+        price: Price,
         size: u64,
         timestamp: u64,
     },
     Trade {
         symbol: String,
-        price: f64,
+        price: Price,
         size: u64,
         timestamp: u64,
     },
     OrderUpdate {
         symbol: String,
         order_id: u64,
-        price: f64,
+        action: OrderAction,
+        side: Side,
+        price: Price,
         size: u64,
         timestamp: u64,
     },
+    /// Full book image. `last_seq` is the last incremental sequence number reflected in it.
+    Snapshot {
+        symbol: String,
+        last_seq: u64,
+        /// Bid levels, best first (market-by-price feeds).
+        bids: Vec<(Price, u64)>,
+        /// Ask levels, best first (market-by-price feeds).
+        asks: Vec<(Price, u64)>,
+        /// Resting orders in queue order (market-by-order feeds).
+        orders: Vec<SnapshotOrder>,
+        timestamp: u64,
+    },
+}
+
+impl MarketMessage {
+    /// Symbol the message refers to.
+    pub fn symbol(&self) -> &str {
+        match self {
+            MarketMessage::Quote { symbol, .. }
+            | MarketMessage::Trade { symbol, .. }
+            | MarketMessage::OrderUpdate { symbol, .. }
+            | MarketMessage::Snapshot { symbol, .. } => symbol,
+        }
+    }
+}
+
+/// A message as received on an exchange channel, with its channel sequence number.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequencedMessage {
+    pub channel: u32,
+    pub seq: u64,
+    pub message: MarketMessage,
 }